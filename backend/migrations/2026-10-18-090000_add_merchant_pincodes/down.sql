@@ -0,0 +1,15 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE merchants ADD COLUMN pincodes_serviced VARCHAR NOT NULL DEFAULT '';
+
+UPDATE merchants
+SET pincodes_serviced = grouped.pincodes
+FROM (
+    SELECT merchant_id, STRING_AGG(pincode, ', ' ORDER BY pincode) AS pincodes
+    FROM merchant_pincodes
+    GROUP BY merchant_id
+) AS grouped
+WHERE merchants.id = grouped.merchant_id;
+
+ALTER TABLE merchants ALTER COLUMN pincodes_serviced DROP DEFAULT;
+
+DROP TABLE merchant_pincodes;
@@ -0,0 +1,16 @@
+CREATE TABLE merchant_pincodes (
+    merchant_id INTEGER NOT NULL REFERENCES merchants(id) ON DELETE CASCADE,
+    pincode VARCHAR NOT NULL,
+    PRIMARY KEY (merchant_id, pincode)
+);
+
+CREATE INDEX merchant_pincodes_pincode_idx ON merchant_pincodes (pincode);
+
+-- Split the existing comma joined pincode strings into one row per pincode
+INSERT INTO merchant_pincodes (merchant_id, pincode)
+SELECT merchants.id, TRIM(split.pincode)
+FROM merchants, UNNEST(STRING_TO_ARRAY(merchants.pincodes_serviced, ',')) AS split(pincode)
+WHERE TRIM(split.pincode) <> ''
+ON CONFLICT DO NOTHING;
+
+ALTER TABLE merchants DROP COLUMN pincodes_serviced;
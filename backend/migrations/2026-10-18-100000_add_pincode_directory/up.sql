@@ -0,0 +1,14 @@
+-- One row per post office, a pincode can have several offices
+CREATE TABLE pincode_directory (
+    pincode VARCHAR(6) NOT NULL,
+    office_name VARCHAR(255) NOT NULL,
+    district VARCHAR(255) NOT NULL,
+    state VARCHAR(255) NOT NULL,
+    region VARCHAR(255) NOT NULL,
+    latitude DOUBLE PRECISION,
+    longitude DOUBLE PRECISION,
+    PRIMARY KEY (pincode, office_name)
+);
+
+CREATE INDEX pincode_directory_state_idx ON pincode_directory (state);
+CREATE INDEX pincode_directory_district_idx ON pincode_directory (district);
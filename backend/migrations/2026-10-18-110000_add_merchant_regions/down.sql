@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX pincode_directory_lower_state_idx;
+DROP INDEX pincode_directory_lower_district_idx;
+DROP TABLE merchant_regions;
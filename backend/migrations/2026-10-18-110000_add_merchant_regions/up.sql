@@ -0,0 +1,10 @@
+-- Districts and states a merchant services as a whole. Names are stored lowercased
+CREATE TABLE merchant_regions (
+    merchant_id INTEGER NOT NULL REFERENCES merchants(id) ON DELETE CASCADE,
+    region_type VARCHAR(16) NOT NULL CHECK (region_type IN ('district', 'state')),
+    name VARCHAR(255) NOT NULL,
+    PRIMARY KEY (merchant_id, region_type, name)
+);
+
+CREATE INDEX pincode_directory_lower_district_idx ON pincode_directory (LOWER(district));
+CREATE INDEX pincode_directory_lower_state_idx ON pincode_directory (LOWER(state));
@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE merchants
+    DROP CONSTRAINT merchants_location_check,
+    DROP COLUMN delivery_radius_km,
+    DROP COLUMN longitude,
+    DROP COLUMN latitude;
@@ -0,0 +1,9 @@
+-- Store location and delivery radius of hyperlocal merchants, set together or not at all
+ALTER TABLE merchants
+    ADD COLUMN latitude DOUBLE PRECISION,
+    ADD COLUMN longitude DOUBLE PRECISION,
+    ADD COLUMN delivery_radius_km DOUBLE PRECISION,
+    ADD CONSTRAINT merchants_location_check CHECK (
+        (latitude IS NULL AND longitude IS NULL AND delivery_radius_km IS NULL)
+        OR (latitude IS NOT NULL AND longitude IS NOT NULL AND delivery_radius_km > 0)
+    );
@@ -0,0 +1,7 @@
+-- Prefix ("56") and numeric range ("560001-560100") rules covering many pincodes at once
+CREATE TABLE merchant_pincode_rules (
+    merchant_id INTEGER NOT NULL REFERENCES merchants(id) ON DELETE CASCADE,
+    rule_type VARCHAR(16) NOT NULL CHECK (rule_type IN ('prefix', 'range')),
+    value VARCHAR(13) NOT NULL,
+    PRIMARY KEY (merchant_id, rule_type, value)
+);
@@ -0,0 +1,6 @@
+-- Pincodes a merchant never services, overriding explicit pincodes, regions, prefixes, ranges and delivery radius
+CREATE TABLE merchant_pincode_exclusions (
+    merchant_id INTEGER NOT NULL REFERENCES merchants(id) ON DELETE CASCADE,
+    pincode VARCHAR(6) NOT NULL,
+    PRIMARY KEY (merchant_id, pincode)
+);
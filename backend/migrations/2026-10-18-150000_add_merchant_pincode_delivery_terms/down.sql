@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE merchant_pincodes
+    DROP COLUMN min_order_value_paise,
+    DROP COLUMN delivery_fee_paise,
+    DROP COLUMN delivery_time_hours;
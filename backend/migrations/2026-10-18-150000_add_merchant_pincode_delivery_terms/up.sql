@@ -0,0 +1,5 @@
+-- Delivery terms of a merchant for a pincode, amounts are in paise
+ALTER TABLE merchant_pincodes
+    ADD COLUMN delivery_time_hours INTEGER CHECK (delivery_time_hours > 0),
+    ADD COLUMN delivery_fee_paise INTEGER CHECK (delivery_fee_paise >= 0),
+    ADD COLUMN min_order_value_paise INTEGER CHECK (min_order_value_paise >= 0);
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE merchant_holidays;
+DROP TABLE merchant_operating_hours;
@@ -0,0 +1,18 @@
+-- Weekly opening hours of a merchant, in India Standard Time. weekday runs from 1 (Monday) to 7 (Sunday).
+-- A closing time at or before the opening time runs past midnight into the next day.
+-- Merchants without any row are open around the clock
+CREATE TABLE merchant_operating_hours (
+    merchant_id INTEGER NOT NULL REFERENCES merchants(id) ON DELETE CASCADE,
+    weekday SMALLINT NOT NULL CHECK (weekday BETWEEN 1 AND 7),
+    opens_at TIME NOT NULL,
+    closes_at TIME NOT NULL,
+    PRIMARY KEY (merchant_id, weekday, opens_at)
+);
+
+-- Days a merchant is closed the whole day
+CREATE TABLE merchant_holidays (
+    merchant_id INTEGER NOT NULL REFERENCES merchants(id) ON DELETE CASCADE,
+    holiday DATE NOT NULL,
+    name VARCHAR(255),
+    PRIMARY KEY (merchant_id, holiday)
+);
@@ -0,0 +1,14 @@
+-- Audit trail of temporary serviceability pauses, one row per merchant and pincode.
+-- Rows are kept once the pause expires or is lifted, and after the merchant is deleted
+CREATE TABLE merchant_pauses (
+    id SERIAL PRIMARY KEY,
+    merchant_id INTEGER NOT NULL,
+    pincode VARCHAR(6) NOT NULL,
+    reason VARCHAR(255),
+    paused_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    expires_at TIMESTAMPTZ NOT NULL,
+    resumed_at TIMESTAMPTZ,
+    CHECK (expires_at > paused_at)
+);
+
+CREATE INDEX merchant_pauses_merchant_id_idx ON merchant_pauses (merchant_id, expires_at);
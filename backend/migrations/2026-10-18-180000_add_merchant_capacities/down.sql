@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE merchant_pincode_capacities;
+DROP TABLE merchant_capacities;
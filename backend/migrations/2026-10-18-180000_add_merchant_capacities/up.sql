@@ -0,0 +1,13 @@
+-- Number of orders a merchant accepts per day across all pincodes
+CREATE TABLE merchant_capacities (
+    merchant_id INTEGER PRIMARY KEY REFERENCES merchants(id) ON DELETE CASCADE,
+    daily_capacity INTEGER NOT NULL CHECK (daily_capacity > 0)
+);
+
+-- Number of orders a merchant accepts per day for a pincode
+CREATE TABLE merchant_pincode_capacities (
+    merchant_id INTEGER NOT NULL REFERENCES merchants(id) ON DELETE CASCADE,
+    pincode VARCHAR(6) NOT NULL,
+    daily_capacity INTEGER NOT NULL CHECK (daily_capacity > 0),
+    PRIMARY KEY (merchant_id, pincode)
+);
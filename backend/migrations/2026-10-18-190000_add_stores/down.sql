@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE store_pincodes;
+DROP TABLE stores;
@@ -0,0 +1,23 @@
+-- Outlets of a merchant. Contact details left empty fall back to the merchant's
+CREATE TABLE stores (
+    id SERIAL PRIMARY KEY,
+    merchant_id INTEGER NOT NULL REFERENCES merchants(id) ON DELETE CASCADE,
+    name VARCHAR(255) NOT NULL,
+    address VARCHAR(1024) NOT NULL,
+    latitude DOUBLE PRECISION,
+    longitude DOUBLE PRECISION,
+    phone_number VARCHAR(20),
+    email VARCHAR(255),
+    CHECK ((latitude IS NULL) = (longitude IS NULL))
+);
+
+CREATE INDEX stores_merchant_id_idx ON stores (merchant_id);
+
+-- Pincodes serviced by a store. The merchant services the pincodes of all its stores
+CREATE TABLE store_pincodes (
+    store_id INTEGER NOT NULL REFERENCES stores(id) ON DELETE CASCADE,
+    pincode VARCHAR(6) NOT NULL,
+    PRIMARY KEY (store_id, pincode)
+);
+
+CREATE INDEX store_pincodes_pincode_idx ON store_pincodes (pincode);
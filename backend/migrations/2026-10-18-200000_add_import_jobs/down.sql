@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE import_job_errors;
+DROP TABLE import_jobs;
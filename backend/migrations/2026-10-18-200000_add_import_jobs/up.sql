@@ -0,0 +1,29 @@
+-- Merchant CSV files onboarded in the background. The file is kept with the job until it is processed
+CREATE TABLE import_jobs (
+    id SERIAL PRIMARY KEY,
+    status VARCHAR(16) NOT NULL DEFAULT 'queued' CHECK (status IN ('queued', 'running', 'completed', 'failed')),
+    file_name VARCHAR(255),
+    csv_data BYTEA NOT NULL,
+    allow_unknown_pincodes BOOLEAN NOT NULL DEFAULT FALSE,
+    total_rows INTEGER NOT NULL DEFAULT 0,
+    processed_rows INTEGER NOT NULL DEFAULT 0,
+    accepted_rows INTEGER NOT NULL DEFAULT 0,
+    rejected_rows INTEGER NOT NULL DEFAULT 0,
+    error TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    started_at TIMESTAMPTZ,
+    finished_at TIMESTAMPTZ
+);
+
+CREATE INDEX import_jobs_status_idx ON import_jobs (status);
+
+-- Rows of an import that were rejected, one row per failed field
+CREATE TABLE import_job_errors (
+    id SERIAL PRIMARY KEY,
+    job_id INTEGER NOT NULL REFERENCES import_jobs(id) ON DELETE CASCADE,
+    line BIGINT NOT NULL,
+    field VARCHAR(255) NOT NULL,
+    message TEXT NOT NULL
+);
+
+CREATE INDEX import_job_errors_job_id_idx ON import_job_errors (job_id);
@@ -0,0 +1,5 @@
+-- Reference of the merchant in the seller's own systems, such as a seller ID or GSTIN. CSV uploads
+-- with upsert=true update the merchant with the same reference instead of adding a new one
+ALTER TABLE merchants ADD COLUMN external_ref VARCHAR(64);
+
+ALTER TABLE merchants ADD CONSTRAINT merchants_external_ref_key UNIQUE (external_ref);
@@ -0,0 +1,2 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE import_jobs DROP COLUMN lease_expires_at;
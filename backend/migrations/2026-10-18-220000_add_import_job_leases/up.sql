@@ -0,0 +1,3 @@
+-- End of the lease of the worker running an import. The worker renews it after every batch; a running job
+-- whose lease expired was left by a worker that stopped and is queued again
+ALTER TABLE import_jobs ADD COLUMN lease_expires_at TIMESTAMPTZ;
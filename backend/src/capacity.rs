@@ -0,0 +1,375 @@
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+
+use rocket::serde::json::{Json, json};
+use rocket::time::format_description::well_known::Rfc3339;
+use rocket::time::{Date, Duration, OffsetDateTime};
+use rocket::State;
+use rocket_db_pools::Connection;
+use rocket_db_pools::diesel::{AsyncPgConnection, prelude::*};
+use rocket_db_pools::diesel::scoped_futures::ScopedFutureExt;
+
+use crate::{index, models, schedules, utils, validation, Db, RedisClient};
+
+// Field of the capacity and usage hashes holding the overall limit and count, the other fields are pincodes
+pub const TOTAL_FIELD: &str = "total";
+
+// Checks the overall and pincode limits and counts the orders only if both have room.
+// KEYS[1] is the capacity hash, KEYS[2] the usage hash. ARGV is the pincode, the number of orders
+// and the time the usage expires. Returns {status, overall usage, pincode usage} where status is
+// 1 when the orders were counted, 0 when the overall limit was hit and -1 when the pincode limit was hit
+const CONSUME_SCRIPT: &str = r"
+local orders = tonumber(ARGV[2])
+local total_limit = tonumber(redis.call('HGET', KEYS[1], 'total'))
+local pincode_limit = tonumber(redis.call('HGET', KEYS[1], ARGV[1]))
+local total_used = tonumber(redis.call('HGET', KEYS[2], 'total') or '0')
+local pincode_used = tonumber(redis.call('HGET', KEYS[2], ARGV[1]) or '0')
+
+if total_limit and total_used + orders > total_limit then
+    return {0, total_used, pincode_used}
+end
+if pincode_limit and pincode_used + orders > pincode_limit then
+    return {-1, total_used, pincode_used}
+end
+
+total_used = redis.call('HINCRBY', KEYS[2], 'total', orders)
+pincode_used = redis.call('HINCRBY', KEYS[2], ARGV[1], orders)
+redis.call('EXPIREAT', KEYS[2], ARGV[3])
+return {1, total_used, pincode_used}
+";
+
+// Capacity resets every day at this hour, India Standard Time. Set with CAPACITY_RESET_HOUR
+const DEFAULT_RESET_HOUR: u8 = 0;
+
+fn reset_hour() -> u8 {
+    env::var("CAPACITY_RESET_HOUR")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .filter(|hour| *hour < 24)
+        .unwrap_or(DEFAULT_RESET_HOUR)
+}
+
+// Day of the capacity period the time falls in, and the time the period ends
+pub fn current_period(now: OffsetDateTime) -> (Date, OffsetDateTime) {
+    period(now, reset_hour())
+}
+
+fn period(now: OffsetDateTime, reset_hour: u8) -> (Date, OffsetDateTime) {
+    let day = (now.to_offset(schedules::ist_offset()) - Duration::hours(reset_hour as i64)).date();
+    let resets_at = day
+        .next_day()
+        .and_then(|next_day| next_day.with_hms(reset_hour, 0, 0).ok())
+        .map(|resets_at| resets_at.assume_offset(schedules::ist_offset()))
+        .unwrap_or(now);
+
+    (day, resets_at)
+}
+
+// Capacity hash fields of every merchant with a limit, ("total" or pincode, daily capacity)
+pub async fn load_limits(db: &mut AsyncPgConnection, merchant_ids: Option<&[i32]>) -> QueryResult<BTreeMap<i32, Vec<(String, i32)>>> {
+    use crate::schema::{merchant_capacities, merchant_pincode_capacities};
+
+    let mut overall_query = merchant_capacities::table.select(models::MerchantCapacity::as_select()).into_boxed();
+    let mut pincode_query = merchant_pincode_capacities::table.select(models::MerchantPincodeCapacity::as_select()).into_boxed();
+    if let Some(merchant_ids) = merchant_ids {
+        overall_query = overall_query.filter(merchant_capacities::merchant_id.eq_any(merchant_ids));
+        pincode_query = pincode_query.filter(merchant_pincode_capacities::merchant_id.eq_any(merchant_ids));
+    }
+
+    let mut limits: BTreeMap<i32, Vec<(String, i32)>> = BTreeMap::new();
+    for row in overall_query.load(db).await? {
+        limits.entry(row.merchant_id).or_default().push((TOTAL_FIELD.to_string(), row.daily_capacity));
+    }
+    for row in pincode_query.load(db).await? {
+        limits.entry(row.merchant_id).or_default().push((row.pincode, row.daily_capacity));
+    }
+
+    Ok(limits)
+}
+
+// Replaces the overall and pincode limits of the merchant
+async fn replace_limits(db: &mut AsyncPgConnection, merchant_id: i32, limits: &utils::CapacityLimits) -> QueryResult<()> {
+    use crate::schema::{merchant_capacities, merchant_pincode_capacities};
+
+    diesel::delete(merchant_capacities::table.filter(merchant_capacities::merchant_id.eq(merchant_id)))
+        .execute(db)
+        .await?;
+    diesel::delete(merchant_pincode_capacities::table.filter(merchant_pincode_capacities::merchant_id.eq(merchant_id)))
+        .execute(db)
+        .await?;
+
+    if let Some(daily_capacity) = limits.daily_capacity {
+        diesel::insert_into(merchant_capacities::table)
+            .values(models::MerchantCapacity { merchant_id, daily_capacity })
+            .execute(db)
+            .await?;
+    }
+
+    let rows: Vec<models::MerchantPincodeCapacity> = limits
+        .pincodes
+        .iter()
+        .map(|(pincode, daily_capacity)| models::MerchantPincodeCapacity { merchant_id, pincode: pincode.clone(), daily_capacity: *daily_capacity })
+        .collect();
+    if !rows.is_empty() {
+        diesel::insert_into(merchant_pincode_capacities::table).values(&rows).execute(db).await?;
+    }
+
+    Ok(())
+}
+
+// Replaces the limits of the merchant, removing them when there are none
+pub fn add_limit_commands(pipe: &mut redis::Pipeline, version: u64, merchant_id: i32, limits: &[(String, i32)]) {
+    pipe.del(index::capacity_key(version, merchant_id)).ignore();
+    if !limits.is_empty() {
+        pipe.hset_multiple(index::capacity_key(version, merchant_id), limits).ignore();
+    }
+}
+
+// Keeps the merchants that still have room for orders to the pincodes. A merchant is at capacity when it used up
+// its overall limit, or the limit of all of the pincodes (any of them when all is set)
+pub fn available_merchant_ids(redis_client: &redis::Client, merchant_ids: Vec<u32>, pincodes: &[String], all: bool, available_only: bool) -> redis::RedisResult<Vec<u32>> {
+    if !available_only || merchant_ids.is_empty() {
+        return Ok(merchant_ids);
+    }
+
+    let mut con = redis_client.get_connection()?;
+    let version = index::current_version(&mut con)?;
+    let (period, _) = current_period(OffsetDateTime::now_utc());
+    let period = schedules::format_date(period);
+
+    let fields: Vec<&str> = std::iter::once(TOTAL_FIELD).chain(pincodes.iter().map(String::as_str)).collect();
+    let mut pipe = redis::pipe();
+    for &merchant_id in &merchant_ids {
+        pipe.cmd("HMGET").arg(index::capacity_key(version, merchant_id as i32)).arg(&fields);
+        pipe.cmd("HMGET").arg(index::capacity_usage_key(&period, merchant_id as i32)).arg(&fields);
+    }
+    let results: Vec<Vec<Option<i64>>> = pipe.query(&mut con)?;
+
+    Ok(merchant_ids
+        .into_iter()
+        .zip(results.chunks(2))
+        .filter(|(_, result)| {
+            let (limits, usage) = (&result[0], &result[1]);
+            let full = |field: usize| limits[field].is_some_and(|limit| usage[field].unwrap_or(0) >= limit);
+
+            let pincodes_full = if all {
+                (1..fields.len()).any(full)
+            } else {
+                fields.len() > 1 && (1..fields.len()).all(full)
+            };
+            !full(0) && !pincodes_full
+        })
+        .map(|(merchant_id, _)| merchant_id)
+        .collect())
+}
+
+// Returns the limits of the merchant and the orders taken in the current period
+#[get("/merchant/<merchant_id>/capacity")]
+pub(crate) async fn get_capacity(redis: &State<RedisClient>, mut db: Connection<Db>, merchant_id: i32) -> Json<utils::ApiResponse> {
+    if let Err(response) = crate::merchant_exists(&mut db, merchant_id).await {
+        return Json(response);
+    }
+
+    let limits = match load_limits(&mut db, Some(&[merchant_id])).await {
+        Ok(mut limits) => limits.remove(&merchant_id).unwrap_or_default(),
+        Err(err) => {
+            return Json(utils::ApiResponse {
+                status: utils::ApiResponseStatus::Error,
+                data: json!({"message": format!("Failed to read the capacity: {:?}", err)}).into(),
+            });
+        }
+    };
+
+    let (period, resets_at) = current_period(OffsetDateTime::now_utc());
+    let usage: redis::RedisResult<HashMap<String, i64>> = redis.client
+        .get_connection()
+        .and_then(|mut con| redis::cmd("HGETALL").arg(index::capacity_usage_key(&schedules::format_date(period), merchant_id)).query(&mut con));
+
+    match usage {
+        Ok(mut usage) => {
+            let daily_capacity = limits.iter().find(|(field, _)| field == TOTAL_FIELD).map(|(_, limit)| *limit);
+            let pincodes: BTreeMap<&String, i32> = limits.iter().filter(|(field, _)| field != TOTAL_FIELD).map(|(field, limit)| (field, *limit)).collect();
+            let used = usage.remove(TOTAL_FIELD).unwrap_or(0);
+
+            Json(utils::ApiResponse {
+                status: utils::ApiResponseStatus::Success,
+                data: json!({
+                    "ONDC_merchant_id": format!("{}", merchant_id),
+                    "daily_capacity": daily_capacity,
+                    "pincodes": pincodes,
+                    "period": schedules::format_date(period),
+                    "resets_at": resets_at.format(&Rfc3339).unwrap_or_default(),
+                    "usage": {"total": used, "pincodes": usage.into_iter().collect::<BTreeMap<String, i64>>()}
+                }).into(),
+            })
+        }
+        Err(err) => {
+            eprintln!("Error reading the capacity usage of merchant {}: {:?}", merchant_id, err);
+            Json(utils::ApiResponse {
+                status: utils::ApiResponseStatus::Error,
+                data: json!({"message": format!("{}", err)}).into(),
+            })
+        }
+    }
+}
+
+// Replaces the daily capacity of the merchant, overall and per pincode. Omitted limits are unlimited
+#[put("/merchant/<merchant_id>/capacity", format = "json", data = "<limits>")]
+pub(crate) async fn set_capacity(redis: &State<RedisClient>, mut db: Connection<Db>, limits: Json<utils::CapacityLimits>, merchant_id: i32) -> Json<utils::ApiResponse> {
+    if let Err(response) = crate::merchant_exists(&mut db, merchant_id).await {
+        return Json(response);
+    }
+
+    let limits = limits.into_inner();
+    if let Err(errors) = validation::validate_capacity(&limits) {
+        return Json(validation::error_response(errors));
+    }
+
+    let fields: Vec<(String, i32)> = limits
+        .daily_capacity
+        .map(|limit| (TOTAL_FIELD.to_string(), limit))
+        .into_iter()
+        .chain(limits.pincodes.iter().map(|(pincode, limit)| (pincode.clone(), *limit)))
+        .collect();
+
+    let result = db.transaction::<_, utils::StoreError, _>(|conn| async move {
+        replace_limits(conn, merchant_id, &limits).await?;
+
+        let mut update = index::IndexUpdate::default();
+        update.add(merchant_id, move |_, pipe, version| {
+            add_limit_commands(pipe, version, merchant_id, &fields);
+            Ok(())
+        });
+
+        Ok((limits, update))
+    }.scope_boxed()).await;
+
+    match result {
+        Ok((limits, update)) => {
+            update.apply(&redis.client);
+            Json(utils::ApiResponse {
+                status: utils::ApiResponseStatus::Success,
+                data: json!({
+                    "ONDC_merchant_id": format!("{}", merchant_id),
+                    "daily_capacity": limits.daily_capacity,
+                    "pincodes": limits.pincodes,
+                    "message": "Merchant capacity updated"
+                }).into(),
+            })
+        }
+        Err(err) => {
+            eprintln!("Failed to update the merchant capacity: {}", err);
+            Json(utils::ApiResponse {
+                status: utils::ApiResponseStatus::Error,
+                data: json!({"message": format!("Failed to update the merchant capacity: {}", err)}).into(),
+            })
+        }
+    }
+}
+
+// Counts orders against the merchant's capacity. The orders are only counted if neither the overall
+// nor the pincode limit would be exceeded, the check and the increment are atomic
+#[post("/merchant/<merchant_id>/capacity/consume", format = "json", data = "<consumption>")]
+pub(crate) async fn consume_capacity(redis: &State<RedisClient>, mut db: Connection<Db>, consumption: Json<utils::CapacityConsumption>, merchant_id: i32) -> Json<utils::ApiResponse> {
+    if let Err(response) = crate::merchant_exists(&mut db, merchant_id).await {
+        return Json(response);
+    }
+
+    let mut consumption = consumption.into_inner();
+    consumption.pincode = consumption.pincode.trim().to_string();
+    if let Err(errors) = validation::validate_consumption(&consumption) {
+        return Json(validation::error_response(errors));
+    }
+
+    let (period, resets_at) = current_period(OffsetDateTime::now_utc());
+    let period = schedules::format_date(period);
+    // Usage of the previous period is kept for a day after the reset
+    let expires_at = (resets_at + Duration::days(1)).unix_timestamp();
+
+    let result: redis::RedisResult<(i32, i64, i64)> = redis.client.get_connection().and_then(|mut con| {
+        let version = index::current_version(&mut con)?;
+        redis::Script::new(CONSUME_SCRIPT)
+            .key(index::capacity_key(version, merchant_id))
+            .key(index::capacity_usage_key(&period, merchant_id))
+            .arg(&consumption.pincode)
+            .arg(consumption.orders)
+            .arg(expires_at)
+            .invoke(&mut con)
+    });
+
+    match result {
+        Ok((status, total_used, pincode_used)) => {
+            let message = match status {
+                1 => "Orders counted against the merchant capacity",
+                0 => "Merchant has reached its daily capacity",
+                _ => "Merchant has reached its daily capacity for the pincode",
+            };
+
+            Json(utils::ApiResponse {
+                status: if status == 1 { utils::ApiResponseStatus::Success } else { utils::ApiResponseStatus::Error },
+                data: json!({
+                    "ONDC_merchant_id": format!("{}", merchant_id),
+                    "pincode": consumption.pincode,
+                    "accepted": status == 1,
+                    "usage": {"total": total_used, "pincode": pincode_used},
+                    "period": period,
+                    "message": message
+                }).into(),
+            })
+        }
+        Err(err) => {
+            eprintln!("Failed to record the capacity of merchant {}: {:?}", merchant_id, err);
+            Json(utils::ApiResponse {
+                status: utils::ApiResponseStatus::Error,
+                data: json!({"message": format!("{}", err)}).into(),
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::time::Month;
+
+    use super::*;
+
+    fn utc(day: u8, hour: u8, minute: u8, second: u8) -> OffsetDateTime {
+        Date::from_calendar_date(2024, Month::January, day).unwrap().with_hms(hour, minute, second).unwrap().assume_utc()
+    }
+
+    #[test]
+    fn periods_follow_the_day_in_india() {
+        // 23:59:59 and midnight IST on January 1st and 2nd
+        let (day, resets_at) = period(utc(1, 18, 29, 59), 0);
+        assert_eq!(schedules::format_date(day), "2024-01-01");
+        assert_eq!(resets_at, utc(1, 18, 30, 0));
+
+        let (day, _) = period(utc(1, 18, 30, 0), 0);
+        assert_eq!(schedules::format_date(day), "2024-01-02");
+    }
+
+    #[test]
+    fn periods_start_at_the_reset_hour() {
+        // 05:00 IST belongs to the previous day's period when capacity resets at 06:00
+        let (day, resets_at) = period(utc(1, 23, 30, 0), 6);
+        assert_eq!(schedules::format_date(day), "2024-01-01");
+        assert_eq!(resets_at, utc(2, 0, 30, 0));
+
+        let (day, _) = period(utc(2, 0, 30, 0), 6);
+        assert_eq!(schedules::format_date(day), "2024-01-02");
+    }
+
+    #[test]
+    fn limits_replace_the_previous_ones() {
+        let mut pipe = redis::pipe();
+        add_limit_commands(&mut pipe, 3, 7, &[(TOTAL_FIELD.to_string(), 50), ("560001".to_string(), 10)]);
+        assert_eq!(
+            index::pipeline_commands(&pipe),
+            vec![vec!["DEL", "v3:capacity:7"], vec!["HMSET", "v3:capacity:7", "total", "50", "560001", "10"]],
+        );
+
+        let mut pipe = redis::pipe();
+        add_limit_commands(&mut pipe, 3, 7, &[]);
+        assert_eq!(index::pipeline_commands(&pipe), vec![vec!["DEL", "v3:capacity:7"]]);
+    }
+}
@@ -0,0 +1,552 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use rocket::form::{Form, FromForm};
+use rocket::fs::TempFile;
+use rocket::serde::json::{Json, json};
+use rocket::serde::Serialize;
+use rocket::State;
+use rocket_db_pools::Connection;
+use rocket_db_pools::diesel::{AsyncPgConnection, prelude::*};
+use rocket_db_pools::diesel::scoped_futures::ScopedFutureExt;
+
+use crate::directory::{find_column, header_key};
+use crate::ingest::RejectedRow;
+use crate::validation::FieldError;
+use crate::{directory, exclusions, models, stores, utils, validation, xlsx, Db, RedisClient};
+
+#[derive(FromForm)]
+pub(crate) struct SpreadsheetUpload<'r> {
+    #[field(name = "file")]
+    upload: TempFile<'r>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CoverageAction {
+    Add,
+    Remove,
+}
+
+// Columns of a coverage file, found by their header
+struct CoverageColumns {
+    merchant_id: usize,
+    pincode: usize,
+    action: Option<usize>,
+    delivery_time_hours: Option<usize>,
+    delivery_fee_paise: Option<usize>,
+    min_order_value_paise: Option<usize>,
+}
+
+// Files without recognizable headers are read as merchant_id, pincode, SLA in hours and fee in paise
+const POSITIONAL_COLUMNS: CoverageColumns = CoverageColumns {
+    merchant_id: 0,
+    pincode: 1,
+    action: None,
+    delivery_time_hours: Some(2),
+    delivery_fee_paise: Some(3),
+    min_order_value_paise: None,
+};
+
+// Pincode to add to or remove from a merchant, line is the line or row number in the file
+#[derive(Debug, Clone)]
+struct CoverageRow {
+    line: u64,
+    merchant_id: i32,
+    pincode: String,
+    action: CoverageAction,
+    terms: models::DeliveryTerms,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PincodeAddition {
+    pub pincode: String,
+    #[serde(flatten)]
+    pub terms: models::DeliveryTerms,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TermsChange {
+    pub pincode: String,
+    pub from: models::DeliveryTerms,
+    pub to: models::DeliveryTerms,
+}
+
+// Changes the file makes to a merchant's pincodes. Excluded pincodes are skipped like in add_pincodes,
+// not_present lists the pincodes to remove that the merchant doesn't service
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct MerchantCoverageDiff {
+    pub merchant_id: i32,
+    pub added: Vec<PincodeAddition>,
+    pub updated: Vec<TermsChange>,
+    pub removed: Vec<String>,
+    pub unchanged: usize,
+    pub excluded: Vec<String>,
+    pub not_present: Vec<String>,
+}
+
+impl MerchantCoverageDiff {
+    fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.updated.is_empty() && self.removed.is_empty()
+    }
+}
+
+fn map_columns(headers: &csv::StringRecord) -> Option<CoverageColumns> {
+    let column = |names: &[&str]| find_column(headers, names);
+
+    match (column(&["merchantid", "ondcmerchantid", "merchant"]), column(&["pincode", "pin", "postalcode"])) {
+        (Some(merchant_id), Some(pincode)) => Some(CoverageColumns {
+            merchant_id,
+            pincode,
+            action: column(&["action", "change", "operation"]),
+            delivery_time_hours: column(&["sla", "slahours", "deliverytimehours", "deliverytime"]),
+            delivery_fee_paise: column(&["fee", "feepaise", "deliveryfeepaise", "deliveryfee"]),
+            min_order_value_paise: column(&["minordervaluepaise", "minordervalue", "minorder"]),
+        }),
+        _ => None,
+    }
+}
+
+fn parse_row(record: &csv::StringRecord, columns: &CoverageColumns) -> Result<(i32, String, CoverageAction, models::DeliveryTerms), Vec<FieldError>> {
+    let mut errors = Vec::new();
+    let cell = |index: Option<usize>| index.and_then(|index| record.get(index)).map(str::trim).filter(|value| !value.is_empty());
+
+    let merchant_id = match cell(Some(columns.merchant_id)).map(|value| (value, value.parse::<i32>())) {
+        Some((_, Ok(merchant_id))) => merchant_id,
+        Some((value, Err(_))) => {
+            errors.push(FieldError::new("merchant_id", format!("{} is not a merchant ID", value)));
+            0
+        }
+        None => {
+            errors.push(FieldError::new("merchant_id", "must not be empty".to_string()));
+            0
+        }
+    };
+
+    let pincode = cell(Some(columns.pincode)).unwrap_or_default().to_string();
+    if !validation::is_valid_pincode(&pincode) {
+        errors.push(FieldError::new("pincode", format!("{} is not a valid pincode, expected 6 digits not starting with 0", pincode)));
+    }
+
+    let action = match cell(columns.action).map(str::to_lowercase).as_deref() {
+        None | Some("add") => CoverageAction::Add,
+        Some("remove") | Some("delete") => CoverageAction::Remove,
+        Some(value) => {
+            errors.push(FieldError::new("action", format!("{} is not a valid action, expected add or remove", value)));
+            CoverageAction::Add
+        }
+    };
+
+    let mut number = |index: Option<usize>, name: &str| -> Option<i32> {
+        let value = cell(index)?;
+        match value.parse::<f64>() {
+            Ok(number) if number.fract() == 0.0 && number.abs() <= i32::MAX as f64 => Some(number as i32),
+            _ => {
+                errors.push(FieldError::new(name, format!("{} is not a whole number", value)));
+                None
+            }
+        }
+    };
+    let terms = models::DeliveryTerms {
+        delivery_time_hours: number(columns.delivery_time_hours, "delivery_time_hours"),
+        delivery_fee_paise: number(columns.delivery_fee_paise, "delivery_fee_paise"),
+        min_order_value_paise: number(columns.min_order_value_paise, "min_order_value_paise"),
+    };
+    if let Err(term_errors) = validation::validate_delivery_terms(&terms) {
+        errors.extend(term_errors);
+    }
+
+    if errors.is_empty() {
+        Ok((merchant_id, pincode, action, terms))
+    } else {
+        Err(errors)
+    }
+}
+
+// Reads the records of a CSV or XLSX file along with their line or row numbers. The first one is the header
+fn read_records(data: &[u8]) -> Result<Vec<(u64, csv::StringRecord)>, String> {
+    if xlsx::is_xlsx(data) {
+        let rows = xlsx::read_rows(data)?;
+        return Ok(rows.into_iter().map(|(line, cells)| (line, csv::StringRecord::from(cells))).collect());
+    }
+
+    let mut reader = csv::ReaderBuilder::new().has_headers(false).flexible(true).from_reader(data);
+    reader
+        .records()
+        .map(|record| {
+            let record = record.map_err(|err| format!("Failed to read the CSV file: {}", err))?;
+            let line = record.position().map(|position| position.line()).unwrap_or_default();
+            Ok((line, record))
+        })
+        .collect()
+}
+
+// Reads the coverage rows of the file. Invalid rows and rows repeating a merchant and pincode are rejected
+fn parse_coverage(data: &[u8]) -> Result<(Vec<CoverageRow>, Vec<RejectedRow>), String> {
+    let mut records = read_records(data)?
+        .into_iter()
+        .filter(|(_, record)| record.iter().any(|value| !value.trim().is_empty()))
+        .peekable();
+    let Some((_, first)) = records.peek() else {
+        return Err("The file has no rows".to_string());
+    };
+
+    let columns = match map_columns(first) {
+        Some(columns) => {
+            records.next();
+            columns
+        }
+        // Without headers the first row is data, as long as it starts with a merchant ID
+        None if first.len() >= 2 && first.get(0).is_some_and(|value| value.trim().parse::<i32>().is_ok()) => POSITIONAL_COLUMNS,
+        None => {
+            let found: Vec<String> = first.iter().map(header_key).collect();
+            return Err(format!("The file must have merchant_id and pincode columns, found {}", found.join(", ")));
+        }
+    };
+
+    let mut rows = Vec::new();
+    let mut rejected = Vec::new();
+    let mut first_lines: BTreeMap<(i32, String), u64> = BTreeMap::new();
+    for (line, record) in records {
+        match parse_row(&record, &columns) {
+            Ok((merchant_id, pincode, action, terms)) => match first_lines.get(&(merchant_id, pincode.clone())) {
+                Some(first_line) => rejected.push(RejectedRow {
+                    line,
+                    errors: vec![FieldError::new("pincode", format!("{} of merchant {} is already on line {}", pincode, merchant_id, first_line))],
+                }),
+                None => {
+                    first_lines.insert((merchant_id, pincode.clone()), line);
+                    rows.push(CoverageRow { line, merchant_id, pincode, action, terms });
+                }
+            },
+            Err(errors) => rejected.push(RejectedRow { line, errors }),
+        }
+    }
+
+    Ok((rows, rejected))
+}
+
+// Moves the rows of merchants that don't exist, and the additions of pincodes missing from the pincode
+// directory unless allow_unknown_pincodes is set, to the rejected rows
+async fn reject_unknown(db: &mut AsyncPgConnection, rows: Vec<CoverageRow>, rejected: &mut Vec<RejectedRow>, allow_unknown_pincodes: bool) -> QueryResult<Vec<CoverageRow>> {
+    use crate::schema::merchants;
+
+    let merchant_ids: Vec<i32> = rows.iter().map(|row| row.merchant_id).collect::<BTreeSet<i32>>().into_iter().collect();
+    let existing: BTreeSet<i32> = merchants::table
+        .filter(merchants::id.eq_any(&merchant_ids))
+        .select(merchants::id)
+        .load::<i32>(db)
+        .await?
+        .into_iter()
+        .collect();
+
+    let unknown_pincodes: BTreeSet<String> = if allow_unknown_pincodes {
+        BTreeSet::new()
+    } else {
+        let added: Vec<String> = rows
+            .iter()
+            .filter(|row| row.action == CoverageAction::Add)
+            .map(|row| row.pincode.clone())
+            .collect::<BTreeSet<String>>()
+            .into_iter()
+            .collect();
+        directory::unknown_pincodes(db, &added).await?.into_iter().collect()
+    };
+
+    let mut known = Vec::new();
+    for row in rows {
+        if !existing.contains(&row.merchant_id) {
+            rejected.push(RejectedRow { line: row.line, errors: vec![FieldError::new("merchant_id", format!("merchant {} does not exist", row.merchant_id))] });
+        } else if row.action == CoverageAction::Add && unknown_pincodes.contains(&row.pincode) {
+            rejected.push(RejectedRow { line: row.line, errors: vec![FieldError::new("pincode", format!("{} is not in the pincode directory", row.pincode))] });
+        } else {
+            known.push(row);
+        }
+    }
+
+    Ok(known)
+}
+
+// Terms of a serviced pincode after new terms are applied, terms left out keep their current value
+fn merge_terms(current: &models::DeliveryTerms, new: &models::DeliveryTerms) -> models::DeliveryTerms {
+    models::DeliveryTerms {
+        delivery_time_hours: new.delivery_time_hours.or(current.delivery_time_hours),
+        delivery_fee_paise: new.delivery_fee_paise.or(current.delivery_fee_paise),
+        min_order_value_paise: new.min_order_value_paise.or(current.min_order_value_paise),
+    }
+}
+
+// Compares the rows with the pincodes the merchants service. With replace, the pincodes of a merchant
+// that are missing from the file are removed
+async fn plan_changes(db: &mut AsyncPgConnection, rows: &[CoverageRow], replace: bool) -> QueryResult<Vec<MerchantCoverageDiff>> {
+    use crate::schema::merchant_pincodes;
+
+    let mut requested: BTreeMap<i32, Vec<&CoverageRow>> = BTreeMap::new();
+    for row in rows {
+        requested.entry(row.merchant_id).or_default().push(row);
+    }
+
+    let merchant_ids: Vec<i32> = requested.keys().copied().collect();
+    let mut current: BTreeMap<i32, BTreeMap<String, models::DeliveryTerms>> = BTreeMap::new();
+    for serviced in merchant_pincodes::table
+        .filter(merchant_pincodes::merchant_id.eq_any(&merchant_ids))
+        .select(models::ServicedPincode::as_select())
+        .load::<models::ServicedPincode>(db)
+        .await?
+    {
+        current.entry(serviced.merchant_id).or_default().insert(serviced.pincode, serviced.terms);
+    }
+
+    let mut diffs = Vec::new();
+    for (merchant_id, merchant_rows) in requested {
+        let serviced = current.remove(&merchant_id).unwrap_or_default();
+        let additions: Vec<String> = merchant_rows
+            .iter()
+            .filter(|row| row.action == CoverageAction::Add)
+            .map(|row| row.pincode.clone())
+            .collect();
+        let excluded = exclusions::excluded_pincodes(db, merchant_id, &additions).await?;
+
+        let mut diff = MerchantCoverageDiff { merchant_id, ..Default::default() };
+        for row in &merchant_rows {
+            match (row.action, serviced.get(&row.pincode)) {
+                (CoverageAction::Add, _) if excluded.contains(&row.pincode) => diff.excluded.push(row.pincode.clone()),
+                (CoverageAction::Add, None) => diff.added.push(PincodeAddition { pincode: row.pincode.clone(), terms: row.terms.clone() }),
+                (CoverageAction::Add, Some(terms)) => {
+                    let merged = merge_terms(terms, &row.terms);
+                    if &merged == terms {
+                        diff.unchanged += 1;
+                    } else {
+                        diff.updated.push(TermsChange { pincode: row.pincode.clone(), from: terms.clone(), to: merged });
+                    }
+                }
+                (CoverageAction::Remove, Some(_)) => diff.removed.push(row.pincode.clone()),
+                (CoverageAction::Remove, None) => diff.not_present.push(row.pincode.clone()),
+            }
+        }
+
+        if replace {
+            let listed: BTreeSet<&String> = merchant_rows.iter().map(|row| &row.pincode).collect();
+            diff.removed.extend(serviced.keys().filter(|pincode| !listed.contains(pincode)).cloned());
+        }
+        diff.removed.sort();
+
+        diffs.push(diff);
+    }
+
+    Ok(diffs)
+}
+
+// Changes to the index of a merchant, applied to Redis once the import has committed
+#[derive(Debug)]
+struct IndexChanges {
+    merchant_id: i32,
+    added: Vec<String>,
+    terms: Vec<models::ServicedPincode>,
+    unserviced: Vec<String>,
+    removed: Vec<String>,
+}
+
+// Applies the changes of a merchant in Postgres the way add_pincodes and the serviceability delete do
+async fn apply_changes(db: &mut AsyncPgConnection, diff: &MerchantCoverageDiff) -> QueryResult<IndexChanges> {
+    let merchant_id = diff.merchant_id;
+
+    // Pincodes are upserted together when they share the same terms
+    let mut by_terms: Vec<(models::DeliveryTerms, Vec<String>)> = Vec::new();
+    let changes = diff
+        .added
+        .iter()
+        .map(|addition| (&addition.terms, &addition.pincode))
+        .chain(diff.updated.iter().map(|change| (&change.to, &change.pincode)));
+    for (terms, pincode) in changes {
+        match by_terms.iter_mut().find(|(group_terms, _)| group_terms == terms) {
+            Some((_, pincodes)) => pincodes.push(pincode.clone()),
+            None => by_terms.push((terms.clone(), vec![pincode.clone()])),
+        }
+    }
+
+    let mut rows = Vec::new();
+    for (terms, pincodes) in &by_terms {
+        rows.extend(crate::upsert_merchant_pincodes(db, merchant_id, pincodes, terms).await?);
+    }
+    let added: Vec<String> = diff.added.iter().map(|addition| addition.pincode.clone()).collect();
+
+    let removed = crate::remove_merchant_pincodes(db, merchant_id, &diff.removed).await?;
+    // Pincodes still serviced by one of the merchant's stores stay in the index, only their terms go
+    let unserviced = stores::unserviced_pincodes(db, merchant_id, &removed).await?;
+
+    Ok(IndexChanges { merchant_id, added, terms: rows, unserviced, removed })
+}
+
+fn index_changes(redis_client: &redis::Client, changes: &IndexChanges) -> redis::RedisResult<()> {
+    crate::store_serviceability(redis_client, changes.merchant_id, &changes.added)?;
+    crate::store_delivery_terms(redis_client, &changes.terms)?;
+    crate::delete_merchant_serviceability(redis_client, changes.merchant_id, &changes.unserviced)?;
+    crate::delete_delivery_terms(redis_client, changes.merchant_id, &changes.removed)
+}
+
+// Adds and removes pincodes of existing merchants from a long format CSV or XLSX file (file form field)
+// with merchant_id and pincode columns, and optionally action (add or remove), sla (delivery time in hours),
+// fee (delivery fee in paise) and min_order_value_paise. With replace=true the merchants in the file only keep
+// the pincodes listed for them. With preview=true the changes are returned without being applied
+#[post("/merchant/serviceability/import?<allow_unknown_pincodes>&<replace>&<preview>", data = "<form>")]
+pub(crate) async fn import_coverage(redis: &State<RedisClient>, mut db: Connection<Db>, mut form: Form<SpreadsheetUpload<'_>>, allow_unknown_pincodes: Option<bool>, replace: Option<bool>, preview: Option<bool>) -> Json<utils::ApiResponse> {
+    let replace = replace.unwrap_or(false);
+    let preview = preview.unwrap_or(false);
+
+    let data = match utils::read_upload(&mut form.upload).await {
+        Ok(data) => data,
+        Err(err) => {
+            eprintln!("Failed to persist the coverage upload: {:?}", err);
+            return Json(utils::ApiResponse {
+                status: utils::ApiResponseStatus::Error,
+                data: json!({"message": "Failed to read the uploaded file"}).into(),
+            });
+        }
+    };
+
+    let (rows, mut rejected) = match parse_coverage(&data) {
+        Ok(parsed) => parsed,
+        Err(message) => {
+            return Json(utils::ApiResponse {
+                status: utils::ApiResponseStatus::Error,
+                data: json!({"message": message}).into(),
+            });
+        }
+    };
+
+    let rows = match reject_unknown(&mut db, rows, &mut rejected, allow_unknown_pincodes.unwrap_or(false)).await {
+        Ok(rows) => rows,
+        Err(err) => {
+            eprintln!("Failed to check the coverage rows: {:?}", err);
+            return Json(utils::ApiResponse {
+                status: utils::ApiResponseStatus::Error,
+                data: json!({"message": "Failed to check the merchants and the pincode directory"}).into(),
+            });
+        }
+    };
+    rejected.sort_by_key(|row| row.line);
+
+    // The changes are planned again inside the transaction, so they match what the merchants service when applied.
+    // Redis is only written once every merchant is committed, a failing merchant would otherwise leave the
+    // merchants before it changed in the index but not in Postgres
+    let result = if preview {
+        plan_changes(&mut db, &rows, replace).await
+    } else {
+        let applied = db.transaction::<_, diesel::result::Error, _>(|conn| async move {
+            let diffs = plan_changes(conn, &rows, replace).await?;
+            let mut changes = Vec::new();
+            for diff in diffs.iter().filter(|diff| !diff.is_empty()) {
+                changes.push(apply_changes(conn, diff).await?);
+            }
+            Ok((diffs, changes))
+        }.scope_boxed()).await;
+
+        applied.map(|(diffs, changes)| {
+            for merchant_changes in &changes {
+                if let Err(err) = index_changes(&redis.client, merchant_changes) {
+                    eprintln!("Failed to index the coverage of merchant {}, the next reconciliation will repair it: {}", merchant_changes.merchant_id, err);
+                }
+            }
+            diffs
+        })
+    };
+
+    match result {
+        Ok(diffs) => {
+            let added: usize = diffs.iter().map(|diff| diff.added.len()).sum();
+            let updated: usize = diffs.iter().map(|diff| diff.updated.len()).sum();
+            let removed: usize = diffs.iter().map(|diff| diff.removed.len()).sum();
+            let message = match preview {
+                true => format!("{} pincodes would be added, {} updated and {} removed", added, updated, removed),
+                false => format!("{} pincodes added, {} updated and {} removed", added, updated, removed),
+            };
+
+            Json(utils::ApiResponse {
+                status: utils::ApiResponseStatus::Success,
+                data: json!({
+                    "preview": preview,
+                    "replace": replace,
+                    "merchants": diffs,
+                    "rejected": rejected,
+                    "message": message
+                }).into(),
+            })
+        }
+        Err(err) => {
+            eprintln!("Failed to import the merchant coverage: {}", err);
+            Json(utils::ApiResponse {
+                status: utils::ApiResponseStatus::Error,
+                data: json!({"message": format!("Failed to import the merchant coverage: {}", err)}).into(),
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(data: &str) -> (Vec<CoverageRow>, Vec<RejectedRow>) {
+        parse_coverage(data.as_bytes()).expect("the file should be readable")
+    }
+
+    #[test]
+    fn parse_coverage_matches_headers() {
+        let (rows, rejected) = parse("Pincode,ONDC Merchant ID,Action,SLA Hours,Fee (paise)\n560001,7,add,24,4000\n560002,7,Remove,,\n");
+
+        assert!(rejected.is_empty());
+        assert_eq!(rows.len(), 2);
+        assert_eq!((rows[0].line, rows[0].merchant_id, rows[0].pincode.as_str(), rows[0].action), (2, 7, "560001", CoverageAction::Add));
+        assert_eq!(rows[0].terms.delivery_time_hours, Some(24));
+        assert_eq!(rows[0].terms.delivery_fee_paise, Some(4000));
+        assert_eq!(rows[0].terms.min_order_value_paise, None);
+        assert_eq!(rows[1].action, CoverageAction::Remove);
+        assert!(rows[1].terms.is_empty());
+    }
+
+    #[test]
+    fn parse_coverage_reads_files_without_headers_by_position() {
+        let (rows, rejected) = parse("7,560001,24,4000\n8,560002\n");
+
+        assert!(rejected.is_empty());
+        assert_eq!(rows.iter().map(|row| (row.line, row.merchant_id)).collect::<Vec<(u64, i32)>>(), vec![(1, 7), (2, 8)]);
+        assert_eq!(rows[0].terms.delivery_fee_paise, Some(4000));
+        assert_eq!(rows[1].terms.delivery_time_hours, None);
+    }
+
+    #[test]
+    fn parse_coverage_rejects_invalid_and_repeated_rows() {
+        let (rows, rejected) = parse(
+            "merchant_id,pincode,action,sla,fee\n\
+             7,560001,add,24,4000\n\
+             7,560001,remove,,\n\
+             seven,056001,move,0,12.5\n\
+             8,560001,,,\n",
+        );
+
+        assert_eq!(rows.iter().map(|row| row.line).collect::<Vec<u64>>(), vec![2, 5]);
+        assert_eq!(rejected.len(), 2);
+        assert_eq!(rejected[0].line, 3);
+        assert_eq!(rejected[0].errors[0].message, "560001 of merchant 7 is already on line 2");
+        assert_eq!(rejected[1].line, 4);
+        let fields: Vec<&str> = rejected[1].errors.iter().map(|error| error.field.as_str()).collect();
+        assert_eq!(fields, vec!["merchant_id", "pincode", "action", "delivery_fee_paise", "delivery_time_hours"]);
+    }
+
+    #[test]
+    fn parse_coverage_refuses_files_without_merchant_and_pincode_columns() {
+        let err = parse_coverage(b"seller,pin\n7,560001\n").err().unwrap();
+        assert_eq!(err, "The file must have merchant_id and pincode columns, found seller, pin");
+
+        assert_eq!(parse_coverage(b"\n,,\n").err().unwrap(), "The file has no rows");
+    }
+
+    #[test]
+    fn merge_terms_keeps_the_terms_left_out() {
+        let current = models::DeliveryTerms { delivery_time_hours: Some(24), delivery_fee_paise: Some(4000), min_order_value_paise: None };
+        let new = models::DeliveryTerms { delivery_time_hours: None, delivery_fee_paise: Some(0), min_order_value_paise: Some(50000) };
+
+        let merged = merge_terms(&current, &new);
+
+        assert_eq!(merged, models::DeliveryTerms { delivery_time_hours: Some(24), delivery_fee_paise: Some(0), min_order_value_paise: Some(50000) });
+    }
+}
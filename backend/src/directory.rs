@@ -0,0 +1,499 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use rocket::form::Form;
+use rocket::serde::json::{Json, json};
+use rocket::serde::Serialize;
+use rocket::State;
+use rocket_db_pools::Connection;
+use rocket_db_pools::diesel::{AsyncPgConnection, prelude::*};
+use rocket_db_pools::diesel::upsert::excluded;
+
+use crate::{models, utils, validation, Db, RedisClient, Upload};
+
+// Number of directory rows written per INSERT statement
+const IMPORT_BATCH_SIZE: usize = 1000;
+
+// Number of skipped rows listed in the import response
+const MAX_REPORTED_ERRORS: usize = 100;
+
+// Distance in degrees around a point searched for its nearest post office, about 25 km
+const NEAREST_OFFICE_DEGREES: f64 = 0.25;
+
+pub const REGION_DISTRICT: &str = "district";
+pub const REGION_STATE: &str = "state";
+
+diesel::sql_function!(fn lower(x: diesel::sql_types::Text) -> diesel::sql_types::Text);
+
+#[derive(Debug, Serialize)]
+pub struct SkippedRow {
+    pub line: u64,
+    pub message: String,
+}
+
+// Normalizes a CSV header so "StateName", "state_name" and "statename" are treated alike
+pub(crate) fn header_key(header: &str) -> String {
+    header
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .collect::<String>()
+        .to_lowercase()
+}
+
+// Finds the column of the first header matching one of the names
+pub(crate) fn find_column(headers: &csv::StringRecord, names: &[&str]) -> Option<usize> {
+    headers
+        .iter()
+        .position(|header| names.contains(&header_key(header).as_str()))
+}
+
+// India Post publishes "NA" for offices without coordinates
+fn parse_coordinate(value: Option<&str>) -> Option<f64> {
+    value.and_then(|value| value.trim().parse().ok())
+}
+
+fn parse_directory(data: &[u8]) -> Result<(Vec<models::PincodeDirectoryEntry>, Vec<SkippedRow>), String> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(true)
+        .from_reader(data);
+
+    let headers = reader
+        .headers()
+        .map_err(|err| format!("Failed to read the CSV headers: {}", err))?
+        .clone();
+
+    let column = |names: &[&str]| find_column(&headers, names);
+    let (Some(pincode_column), Some(office_column), Some(district_column), Some(state_column)) = (
+        column(&["pincode"]),
+        column(&["officename", "office"]),
+        column(&["district", "districtname"]),
+        column(&["statename", "state"]),
+    ) else {
+        return Err("The CSV file must have pincode, officename, district and statename columns".to_string());
+    };
+    let region_column = column(&["regionname", "region"]);
+    let latitude_column = column(&["latitude", "lat"]);
+    let longitude_column = column(&["longitude", "long", "lng"]);
+
+    // Keyed by (pincode, office_name) so repeated offices in the file are written once
+    let mut entries: BTreeMap<(String, String), models::PincodeDirectoryEntry> = BTreeMap::new();
+    let mut skipped = Vec::new();
+
+    for record in reader.records() {
+        let record = match record {
+            Ok(record) => record,
+            Err(err) => {
+                let line = err.position().map(|position| position.line()).unwrap_or_default();
+                skipped.push(SkippedRow { line, message: format!("{}", err) });
+                continue;
+            }
+        };
+        let line = record.position().map(|position| position.line()).unwrap_or_default();
+        let field = |index: usize| record.get(index).unwrap_or_default().trim().to_string();
+
+        let pincode = field(pincode_column);
+        if !validation::is_valid_pincode(&pincode) {
+            skipped.push(SkippedRow { line, message: format!("{} is not a valid pincode", pincode) });
+            continue;
+        }
+
+        let office_name = field(office_column);
+        if office_name.is_empty() {
+            skipped.push(SkippedRow { line, message: "office name is empty".to_string() });
+            continue;
+        }
+
+        let entry = models::PincodeDirectoryEntry {
+            pincode: pincode.clone(),
+            office_name: office_name.clone(),
+            district: field(district_column),
+            state: field(state_column),
+            region: region_column.map(field).unwrap_or_default(),
+            latitude: parse_coordinate(latitude_column.and_then(|index| record.get(index))),
+            longitude: parse_coordinate(longitude_column.and_then(|index| record.get(index))),
+        };
+        entries.insert((pincode, office_name), entry);
+    }
+
+    Ok((entries.into_values().collect(), skipped))
+}
+
+async fn upsert_entries(db: &mut AsyncPgConnection, entries: &[models::PincodeDirectoryEntry]) -> QueryResult<()> {
+    use crate::schema::pincode_directory::dsl::*;
+
+    for batch in entries.chunks(IMPORT_BATCH_SIZE) {
+        diesel::insert_into(pincode_directory)
+            .values(batch)
+            .on_conflict((pincode, office_name))
+            .do_update()
+            .set((
+                district.eq(excluded(district)),
+                state.eq(excluded(state)),
+                region.eq(excluded(region)),
+                latitude.eq(excluded(latitude)),
+                longitude.eq(excluded(longitude)),
+            ))
+            .execute(db)
+            .await?;
+    }
+
+    Ok(())
+}
+
+// Returns the pincodes that are not in the directory. Every pincode is unknown until a directory has been imported,
+// callers that accept them anyway check allow_unknown_pincodes before calling this
+pub async fn unknown_pincodes(db: &mut AsyncPgConnection, pincodes: &[String]) -> QueryResult<Vec<String>> {
+    use crate::schema::pincode_directory;
+
+    if pincodes.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let known: BTreeSet<String> = pincode_directory::table
+        .filter(pincode_directory::pincode.eq_any(pincodes))
+        .select(pincode_directory::pincode)
+        .distinct()
+        .load::<String>(db)
+        .await?
+        .into_iter()
+        .collect();
+
+    Ok(pincodes.iter().filter(|code| !known.contains(*code)).cloned().collect())
+}
+
+// Checks the pincodes against the directory, returning the error response to send if any is unknown
+pub async fn check_pincodes(db: &mut AsyncPgConnection, field: &str, pincodes: &[String]) -> Result<(), utils::ApiResponse> {
+    match unknown_pincodes(db, pincodes).await {
+        Ok(unknown) if unknown.is_empty() => Ok(()),
+        Ok(unknown) => Err(validation::error_response(
+            unknown
+                .into_iter()
+                .map(|code| validation::FieldError::new(field, format!("{} is not in the pincode directory", code)))
+                .collect(),
+        )),
+        Err(err) => {
+            eprintln!("Error checking the pincode directory: {:?}", err);
+            Err(utils::ApiResponse {
+                status: utils::ApiResponseStatus::Error,
+                data: json!({"message": "Failed to check the pincode directory"}).into(),
+            })
+        }
+    }
+}
+
+// Centroid (latitude, longitude) of each pincode, averaged over its post offices with coordinates.
+// Pincodes without any coordinates in the directory are left out
+pub async fn pincode_centroids(db: &mut AsyncPgConnection, pincodes: &[String]) -> QueryResult<HashMap<String, (f64, f64)>> {
+    use crate::schema::pincode_directory;
+
+    let rows = pincode_directory::table
+        .filter(pincode_directory::pincode.eq_any(pincodes))
+        .filter(pincode_directory::latitude.is_not_null())
+        .filter(pincode_directory::longitude.is_not_null())
+        .group_by(pincode_directory::pincode)
+        .select((pincode_directory::pincode, diesel::dsl::avg(pincode_directory::latitude), diesel::dsl::avg(pincode_directory::longitude)))
+        .load::<(String, Option<f64>, Option<f64>)>(db)
+        .await?;
+
+    Ok(rows
+        .into_iter()
+        .filter_map(|(code, latitude, longitude)| Some((code, (latitude?, longitude?))))
+        .collect())
+}
+
+// Pincode of the office closest to the point, by equirectangular distance which is precise enough at this range
+fn nearest_office(latitude: f64, longitude: f64, offices: Vec<(String, f64, f64)>) -> Option<String> {
+    let scale = latitude.to_radians().cos();
+    let distance = |office_latitude: f64, office_longitude: f64| (office_latitude - latitude).powi(2) + ((office_longitude - longitude) * scale).powi(2);
+
+    offices
+        .into_iter()
+        .map(|(pincode, office_latitude, office_longitude)| (distance(office_latitude, office_longitude), pincode))
+        .min_by(|(a, _), (b, _)| a.total_cmp(b))
+        .map(|(_, pincode)| pincode)
+}
+
+// Pincode of the post office nearest to the point, None if the directory has no office around it
+pub async fn nearest_pincode(db: &mut AsyncPgConnection, latitude: f64, longitude: f64) -> QueryResult<Option<String>> {
+    use crate::schema::pincode_directory;
+
+    let offices = pincode_directory::table
+        .filter(pincode_directory::latitude.between(latitude - NEAREST_OFFICE_DEGREES, latitude + NEAREST_OFFICE_DEGREES))
+        .filter(pincode_directory::longitude.between(longitude - NEAREST_OFFICE_DEGREES, longitude + NEAREST_OFFICE_DEGREES))
+        .select((pincode_directory::pincode, pincode_directory::latitude.assume_not_null(), pincode_directory::longitude.assume_not_null()))
+        .load::<(String, f64, f64)>(db)
+        .await?;
+
+    Ok(nearest_office(latitude, longitude, offices))
+}
+
+// Expands districts and states (lowercased) into the pincodes of the directory.
+// Returns the pincodes along with an error for every region the directory doesn't know
+pub async fn expand_regions(db: &mut AsyncPgConnection, districts: &[String], states: &[String]) -> QueryResult<(Vec<String>, Vec<validation::FieldError>)> {
+    use crate::schema::pincode_directory;
+
+    let mut pincodes = BTreeSet::new();
+    let mut errors = Vec::new();
+
+    for district in districts {
+        let found = pincode_directory::table
+            .filter(lower(pincode_directory::district).eq(district))
+            .select(pincode_directory::pincode)
+            .distinct()
+            .load::<String>(db)
+            .await?;
+
+        if found.is_empty() {
+            errors.push(validation::FieldError::new("districts", format!("{} is not in the pincode directory", district)));
+        }
+        pincodes.extend(found);
+    }
+
+    for state in states {
+        let found = pincode_directory::table
+            .filter(lower(pincode_directory::state).eq(state))
+            .select(pincode_directory::pincode)
+            .distinct()
+            .load::<String>(db)
+            .await?;
+
+        if found.is_empty() {
+            errors.push(validation::FieldError::new("states", format!("{} is not in the pincode directory", state)));
+        }
+        pincodes.extend(found);
+    }
+
+    Ok((pincodes.into_iter().collect(), errors))
+}
+
+pub fn region_rules(merchant_id: i32, districts: &[String], states: &[String]) -> Vec<models::MerchantRegion> {
+    let rule = |region_type: &str, name: &String| models::MerchantRegion {
+        merchant_id,
+        region_type: region_type.to_string(),
+        name: name.clone(),
+    };
+
+    districts
+        .iter()
+        .map(|district| rule(REGION_DISTRICT, district))
+        .chain(states.iter().map(|state| rule(REGION_STATE, state)))
+        .collect()
+}
+
+pub async fn add_region_rules(db: &mut AsyncPgConnection, rules: &[models::MerchantRegion]) -> QueryResult<usize> {
+    use crate::schema::merchant_regions;
+
+    if rules.is_empty() {
+        return Ok(0);
+    }
+
+    diesel::insert_into(merchant_regions::table)
+        .values(rules)
+        .on_conflict_do_nothing()
+        .execute(db)
+        .await
+}
+
+pub async fn remove_region_rules(db: &mut AsyncPgConnection, merchant_id: i32, districts: &[String], states: &[String]) -> QueryResult<usize> {
+    use crate::schema::merchant_regions;
+
+    let removed_districts = diesel::delete(
+        merchant_regions::table
+            .filter(merchant_regions::merchant_id.eq(merchant_id))
+            .filter(merchant_regions::region_type.eq(REGION_DISTRICT))
+            .filter(merchant_regions::name.eq_any(districts)),
+    )
+    .execute(db)
+    .await?;
+
+    let removed_states = diesel::delete(
+        merchant_regions::table
+            .filter(merchant_regions::merchant_id.eq(merchant_id))
+            .filter(merchant_regions::region_type.eq(REGION_STATE))
+            .filter(merchant_regions::name.eq_any(states)),
+    )
+    .execute(db)
+    .await?;
+
+    Ok(removed_districts + removed_states)
+}
+
+// Adds the directory pincodes of every recorded district or state rule that the merchant doesn't service yet,
+// so pincodes newly added to the directory are covered. Excluded pincodes are skipped.
+// Only the rules of merchant_id are applied if it is set. Returns the pincodes added per merchant
+pub async fn apply_region_rules(db: &mut AsyncPgConnection, merchant_id: Option<i32>) -> QueryResult<HashMap<i32, Vec<String>>> {
+    let added = diesel::sql_query(
+        "INSERT INTO merchant_pincodes (merchant_id, pincode) \
+         SELECT DISTINCT merchant_regions.merchant_id, pincode_directory.pincode \
+         FROM merchant_regions \
+         JOIN pincode_directory ON \
+             (merchant_regions.region_type = 'district' AND LOWER(pincode_directory.district) = merchant_regions.name) \
+             OR (merchant_regions.region_type = 'state' AND LOWER(pincode_directory.state) = merchant_regions.name) \
+         WHERE ($1 IS NULL OR merchant_regions.merchant_id = $1) \
+         AND NOT EXISTS ( \
+             SELECT 1 FROM merchant_pincode_exclusions \
+             WHERE merchant_pincode_exclusions.merchant_id = merchant_regions.merchant_id \
+             AND merchant_pincode_exclusions.pincode = pincode_directory.pincode \
+         ) \
+         ON CONFLICT DO NOTHING \
+         RETURNING merchant_id, pincode",
+    )
+    .bind::<diesel::sql_types::Nullable<diesel::sql_types::Integer>, _>(merchant_id)
+    .load::<models::MerchantPincode>(db)
+    .await?;
+
+    let mut added_by_merchant: HashMap<i32, Vec<String>> = HashMap::new();
+    for row in added {
+        added_by_merchant.entry(row.merchant_id).or_default().push(row.pincode);
+    }
+
+    Ok(added_by_merchant)
+}
+
+// Loads an India Post style pincode directory CSV, replacing the details of offices already present.
+// Merchants servicing a whole district or state get the pincodes newly added to it
+#[post("/admin/pincode_directory", data = "<form>")]
+pub(crate) async fn import_directory(redis: &State<RedisClient>, mut db: Connection<Db>, mut form: Form<Upload<'_>>) -> Json<utils::ApiResponse> {
+    let data = match utils::read_upload(&mut form.upload).await {
+        Ok(data) => data,
+        Err(err) => {
+            eprintln!("Failed to persist the pincode directory upload: {:?}", err);
+            return Json(utils::ApiResponse {
+                status: utils::ApiResponseStatus::Error,
+                data: json!({"message": "Failed to read the uploaded file"}).into(),
+            });
+        }
+    };
+
+    let (entries, skipped) = match parse_directory(&data) {
+        Ok(parsed) => parsed,
+        Err(message) => {
+            return Json(utils::ApiResponse {
+                status: utils::ApiResponseStatus::Error,
+                data: json!({"message": message}).into(),
+            });
+        }
+    };
+
+    if let Err(err) = upsert_entries(&mut db, &entries).await {
+        eprintln!("Failed to import the pincode directory: {:?}", err);
+        return Json(utils::ApiResponse {
+            status: utils::ApiResponseStatus::Error,
+            data: json!({"message": format!("Failed to import the pincode directory: {}", err)}).into(),
+        });
+    }
+
+    match apply_region_rules(&mut db, None).await {
+        Ok(added_by_merchant) => {
+            for (merchant_id, pincodes) in &added_by_merchant {
+                if let Err(err) = crate::store_serviceability(&redis.client, *merchant_id, pincodes) {
+                    eprintln!("Failed to store data in Redis for merchant {}: {:?}", merchant_id, err);
+                }
+            }
+
+            Json(utils::ApiResponse {
+                status: utils::ApiResponseStatus::Success,
+                data: json!({
+                    "imported": entries.len(),
+                    "skipped": skipped.len(),
+                    "errors": skipped.iter().take(MAX_REPORTED_ERRORS).collect::<Vec<_>>(),
+                    "merchants_extended": added_by_merchant.len(),
+                    "message": "Pincode directory imported"
+                }).into(),
+            })
+        }
+        Err(err) => {
+            eprintln!("Failed to apply the district and state serviceability rules: {:?}", err);
+            Json(utils::ApiResponse {
+                status: utils::ApiResponseStatus::Error,
+                data: json!({"message": format!("Pincode directory imported but applying the district and state serviceability rules failed: {}", err)}).into(),
+            })
+        }
+    }
+}
+
+// Lists the directory entries of a state and/or district, matched case-insensitively
+#[get("/pincode_directory?<state>&<district>")]
+pub(crate) async fn get_directory(mut db: Connection<Db>, state: Option<String>, district: Option<String>) -> Json<utils::ApiResponse> {
+    use crate::schema::pincode_directory;
+
+    if state.is_none() && district.is_none() {
+        return Json(utils::ApiResponse {
+            status: utils::ApiResponseStatus::Error,
+            data: json!({"message": "Provide a state or a district"}).into(),
+        });
+    }
+
+    let mut query = pincode_directory::table.into_boxed();
+    if let Some(state) = state {
+        query = query.filter(lower(pincode_directory::state).eq(state.to_lowercase()));
+    }
+    if let Some(district) = district {
+        query = query.filter(lower(pincode_directory::district).eq(district.to_lowercase()));
+    }
+
+    match query
+        .order((pincode_directory::pincode.asc(), pincode_directory::office_name.asc()))
+        .load::<models::PincodeDirectoryEntry>(&mut db)
+        .await
+    {
+        Ok(entries) => Json(utils::ApiResponse {
+            status: utils::ApiResponseStatus::Success,
+            data: json!(entries).into(),
+        }),
+        Err(err) => Json(utils::ApiResponse {
+            status: utils::ApiResponseStatus::Error,
+            data: json!({"message": format!("Failed to read the pincode directory: {:?}", err)}).into(),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn office(pincode: &str, latitude: f64, longitude: f64) -> (String, f64, f64) {
+        (pincode.to_string(), latitude, longitude)
+    }
+
+    #[test]
+    fn parse_directory_reads_india_post_columns() {
+        let (entries, skipped) = parse_directory(
+            b"OfficeName,Pincode,RegionName,District,StateName,Latitude,Longitude\n\
+              Koramangala S.O,560034,Bangalore HQ,Bengaluru,KARNATAKA,12.9250,77.6200\n\
+              Koramangala S.O,560034,Bangalore HQ,Bengaluru,KARNATAKA,12.9250,77.6200\n\
+              Museum Road S.O,560025,Bangalore HQ,Bengaluru,KARNATAKA,NA,NA\n\
+              Nowhere B.O,5600,Bangalore HQ,Bengaluru,KARNATAKA,NA,NA\n",
+        )
+        .unwrap();
+
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].pincode, "560025");
+        assert_eq!(entries[0].latitude, None);
+        assert_eq!(entries[1].office_name, "Koramangala S.O");
+        assert_eq!((entries[1].latitude, entries[1].longitude), (Some(12.9250), Some(77.6200)));
+        assert_eq!(entries[1].region, "Bangalore HQ");
+        assert_eq!(skipped.len(), 1);
+        assert_eq!(skipped[0].line, 5);
+    }
+
+    #[test]
+    fn parse_directory_refuses_files_without_the_required_columns() {
+        assert!(parse_directory(b"pincode,district\n560034,Bengaluru\n").is_err());
+    }
+
+    #[test]
+    fn nearest_office_gives_its_pincode() {
+        let offices = vec![office("560001", 12.9760, 77.6030), office("560034", 12.9250, 77.6200), office("560095", 12.9350, 77.6140)];
+
+        assert_eq!(nearest_office(12.9340, 77.6150, offices).as_deref(), Some("560095"));
+        assert_eq!(nearest_office(12.9340, 77.6150, Vec::new()), None);
+    }
+
+    #[test]
+    fn longitude_differences_shrink_away_from_the_equator() {
+        // At 60 degrees a degree of longitude is half a degree of latitude
+        let offices = vec![office("north", 60.3, 10.0), office("east", 60.0, 10.5)];
+
+        assert_eq!(nearest_office(60.0, 10.0, offices).as_deref(), Some("east"));
+    }
+}
@@ -0,0 +1,246 @@
+use std::collections::BTreeSet;
+
+use rocket::serde::json::{Json, json};
+use rocket::State;
+use rocket_db_pools::Connection;
+use rocket_db_pools::diesel::{AsyncPgConnection, prelude::*};
+use rocket_db_pools::diesel::scoped_futures::ScopedFutureExt;
+
+use crate::{directory, index, models, stores, utils, validation, Db, RedisClient};
+
+pub async fn load_exclusions(db: &mut AsyncPgConnection, merchant_id: i32) -> QueryResult<Vec<String>> {
+    use crate::schema::merchant_pincode_exclusions;
+
+    merchant_pincode_exclusions::table
+        .filter(merchant_pincode_exclusions::merchant_id.eq(merchant_id))
+        .select(merchant_pincode_exclusions::pincode)
+        .order(merchant_pincode_exclusions::pincode.asc())
+        .load(db)
+        .await
+}
+
+// Returns the pincodes the merchant has excluded among the given ones
+pub async fn excluded_pincodes(db: &mut AsyncPgConnection, merchant_id: i32, pincodes: &[String]) -> QueryResult<BTreeSet<String>> {
+    use crate::schema::merchant_pincode_exclusions;
+
+    if pincodes.is_empty() {
+        return Ok(BTreeSet::new());
+    }
+
+    let excluded = merchant_pincode_exclusions::table
+        .filter(merchant_pincode_exclusions::merchant_id.eq(merchant_id))
+        .filter(merchant_pincode_exclusions::pincode.eq_any(pincodes))
+        .select(merchant_pincode_exclusions::pincode)
+        .load::<String>(db)
+        .await?;
+
+    Ok(excluded.into_iter().collect())
+}
+
+// Inserts the exclusions, skipping ones already recorded. Returns the newly excluded pincodes
+async fn add_exclusions(db: &mut AsyncPgConnection, merchant_id: i32, pincodes: &[String]) -> QueryResult<Vec<String>> {
+    use crate::schema::merchant_pincode_exclusions;
+
+    let rows: Vec<models::MerchantPincodeExclusion> = pincodes
+        .iter()
+        .map(|code| models::MerchantPincodeExclusion { merchant_id, pincode: code.clone() })
+        .collect();
+
+    diesel::insert_into(merchant_pincode_exclusions::table)
+        .values(&rows)
+        .on_conflict_do_nothing()
+        .returning(merchant_pincode_exclusions::pincode)
+        .get_results(db)
+        .await
+}
+
+// Removes the exclusions. Returns the pincodes that were actually excluded
+async fn remove_exclusions(db: &mut AsyncPgConnection, merchant_id: i32, pincodes: &[String]) -> QueryResult<Vec<String>> {
+    use crate::schema::merchant_pincode_exclusions;
+
+    diesel::delete(
+        merchant_pincode_exclusions::table
+            .filter(merchant_pincode_exclusions::merchant_id.eq(merchant_id))
+            .filter(merchant_pincode_exclusions::pincode.eq_any(pincodes)),
+    )
+    .returning(merchant_pincode_exclusions::pincode)
+    .get_results(db)
+    .await
+}
+
+pub fn add_exclusion_commands(pipe: &mut redis::Pipeline, version: u64, merchant_id: i32, pincodes: &[String]) {
+    for pincode in pincodes {
+        pipe.sadd(index::excluded_key(version, pincode), merchant_id).ignore();
+    }
+}
+
+pub fn remove_exclusion_commands(pipe: &mut redis::Pipeline, version: u64, merchant_id: i32, pincodes: &[String]) {
+    for pincode in pincodes {
+        pipe.srem(index::excluded_key(version, pincode), merchant_id).ignore();
+    }
+}
+
+// Lists the pincodes the merchant excluded from its serviceability
+#[get("/merchant/<merchant_id>/exclusions")]
+pub(crate) async fn get_exclusions(mut db: Connection<Db>, merchant_id: i32) -> Json<utils::ApiResponse> {
+    if let Err(response) = crate::merchant_exists(&mut db, merchant_id).await {
+        return Json(response);
+    }
+
+    match load_exclusions(&mut db, merchant_id).await {
+        Ok(excluded) => Json(utils::ApiResponse {
+            status: utils::ApiResponseStatus::Success,
+            data: json!({"ONDC_merchant_id": format!("{}", merchant_id), "excluded": excluded}).into(),
+        }),
+        Err(err) => Json(utils::ApiResponse {
+            status: utils::ApiResponseStatus::Error,
+            data: json!({"message": format!("Failed to read the exclusions: {:?}", err)}).into(),
+        }),
+    }
+}
+
+// Excludes pincodes from the merchant's serviceability, overriding its pincodes, regions, prefixes, ranges and delivery radius.
+// Explicitly serviced pincodes that are excluded are removed, from the merchant and from its stores
+#[put("/merchant/<merchant_id>/exclusions", format = "json", data = "<pincode_data>")]
+pub(crate) async fn add_merchant_exclusions(redis: &State<RedisClient>, mut db: Connection<Db>, pincode_data: Json<utils::Pincodes>, merchant_id: i32) -> Json<utils::ApiResponse> {
+    if let Err(response) = crate::merchant_exists(&mut db, merchant_id).await {
+        return Json(response);
+    }
+
+    let pincodes = utils::normalize_pincodes(pincode_data.into_inner().pincodes);
+    if let Err(errors) = validation::validate_exclusions(&pincodes) {
+        return Json(validation::error_response(errors));
+    }
+
+    let result = db.transaction::<_, utils::StoreError, _>(|conn| async move {
+        let excluded = add_exclusions(conn, merchant_id, &pincodes).await?;
+        let removed_pincodes = crate::remove_merchant_pincodes(conn, merchant_id, &excluded).await?;
+        // The exclusion applies to the merchant's stores as well
+        let removed_store_pincodes = stores::remove_merchant_store_pincodes(conn, merchant_id, &excluded).await?;
+        let unserviced = utils::normalize_pincodes(
+            removed_pincodes.iter().cloned().chain(removed_store_pincodes.iter().map(|(_, pincode)| pincode.clone())).collect(),
+        );
+
+        let mut update = index::IndexUpdate::default();
+        let (added, removed) = (excluded.clone(), unserviced.clone());
+        update.add(merchant_id, move |_, pipe, version| {
+            crate::remove_serviceability_commands(pipe, version, merchant_id, &removed);
+            stores::remove_store_commands(pipe, version, merchant_id, &removed_store_pincodes, &[]);
+            add_exclusion_commands(pipe, version, merchant_id, &added);
+            Ok(())
+        });
+
+        Ok((excluded, unserviced, update))
+    }.scope_boxed()).await;
+
+    match result {
+        Ok((excluded, removed_pincodes, update)) => {
+            update.apply(&redis.client);
+            Json(utils::ApiResponse {
+                status: utils::ApiResponseStatus::Success,
+                data: json!({
+                    "ONDC_merchant_id": format!("{}", merchant_id),
+                    "excluded": excluded,
+                    "pincodes_removed": removed_pincodes,
+                    "message": "Merchant exclusions updated"
+                }).into(),
+            })
+        }
+        Err(err) => {
+            eprintln!("Failed to add merchant exclusions: {}", err);
+            Json(utils::ApiResponse {
+                status: utils::ApiResponseStatus::Error,
+                data: json!({"message": format!("Failed to add merchant exclusions: {}", err)}).into(),
+            })
+        }
+    }
+}
+
+// Lifts exclusions. Pincodes covered by the merchant's districts or states are serviced again,
+// explicitly serviced pincodes removed by the exclusion have to be added back
+#[delete("/merchant/<merchant_id>/exclusions", format = "json", data = "<pincode_data>")]
+pub(crate) async fn delete_merchant_exclusions(redis: &State<RedisClient>, mut db: Connection<Db>, pincode_data: Json<utils::Pincodes>, merchant_id: i32) -> Json<utils::ApiResponse> {
+    if let Err(response) = crate::merchant_exists(&mut db, merchant_id).await {
+        return Json(response);
+    }
+
+    let pincodes = utils::normalize_pincodes(pincode_data.into_inner().pincodes);
+    let result = db.transaction::<_, utils::StoreError, _>(|conn| async move {
+        let removed = remove_exclusions(conn, merchant_id, &pincodes).await?;
+        // Cover the pincodes again if one of the merchant's districts or states includes them
+        let restored = directory::apply_region_rules(conn, Some(merchant_id)).await?.remove(&merchant_id).unwrap_or_default();
+
+        let mut update = index::IndexUpdate::default();
+        let (lifted, serviced) = (removed.clone(), restored.clone());
+        update.add(merchant_id, move |_, pipe, version| {
+            remove_exclusion_commands(pipe, version, merchant_id, &lifted);
+            crate::add_serviceability_commands(pipe, version, merchant_id, &serviced);
+            Ok(())
+        });
+
+        Ok((removed, restored, update))
+    }.scope_boxed()).await;
+
+    match result {
+        Ok((removed, _, _)) if removed.is_empty() => Json(utils::ApiResponse {
+            status: utils::ApiResponseStatus::Error,
+            data: json!({"message": "No exclusions were removed"}).into(),
+        }),
+        Ok((removed, restored, update)) => {
+            update.apply(&redis.client);
+            Json(utils::ApiResponse {
+                status: utils::ApiResponseStatus::Success,
+                data: json!({
+                    "ONDC_merchant_id": format!("{}", merchant_id),
+                    "removed": removed,
+                    "pincodes_restored": restored,
+                    "message": "Merchant exclusions updated"
+                }).into(),
+            })
+        }
+        Err(err) => {
+            eprintln!("Failed to remove merchant exclusions: {}", err);
+            Json(utils::ApiResponse {
+                status: utils::ApiResponseStatus::Error,
+                data: json!({"message": format!("Failed to remove merchant exclusions: {}", err)}).into(),
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pincodes(codes: &[&str]) -> Vec<String> {
+        codes.iter().map(|code| code.to_string()).collect()
+    }
+
+    #[test]
+    fn exclusions_are_added_to_the_excluded_set_of_each_pincode() {
+        let mut pipe = redis::pipe();
+        add_exclusion_commands(&mut pipe, 3, 7, &pincodes(&["560001", "560002"]));
+
+        assert_eq!(
+            index::pipeline_commands(&pipe),
+            vec![vec!["SADD", "v3:excluded:560001", "7"], vec!["SADD", "v3:excluded:560002", "7"]],
+        );
+    }
+
+    #[test]
+    fn lifted_exclusions_are_removed_from_the_excluded_sets() {
+        let mut pipe = redis::pipe();
+        remove_exclusion_commands(&mut pipe, 0, 7, &pincodes(&["560001"]));
+
+        assert_eq!(index::pipeline_commands(&pipe), vec![vec!["SREM", "excluded:560001", "7"]]);
+    }
+
+    #[test]
+    fn nothing_is_sent_without_pincodes() {
+        let mut pipe = redis::pipe();
+        add_exclusion_commands(&mut pipe, 3, 7, &[]);
+        remove_exclusion_commands(&mut pipe, 3, 7, &[]);
+
+        assert!(index::pipeline_commands(&pipe).is_empty());
+    }
+}
@@ -0,0 +1,479 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use parquet::basic::Compression;
+use parquet::data_type::{ByteArray, ByteArrayType, DoubleType, Int32Type};
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::SerializedFileWriter;
+use parquet::schema::parser::parse_message_type;
+use rocket::http::ContentType;
+use rocket::response::stream::ByteStream;
+use rocket::serde::json::Json;
+use rocket_db_pools::Connection;
+use rocket_db_pools::diesel::{AnsiTransactionManager, AsyncPgConnection, TransactionManager, prelude::*};
+
+use crate::validation::FieldError;
+use crate::{models, utils, validation, Db};
+
+// Merchants read from Postgres and written out at a time
+const PAGE_SIZE: i64 = 1000;
+
+// Columns of the CSV export, the same as read by /upload_csv. The id column is ignored on upload
+const CSV_HEADERS: [&str; 10] = [
+    "id",
+    "name",
+    "business_category",
+    "phone_number",
+    "email",
+    "pincodes_serviced",
+    "latitude",
+    "longitude",
+    "delivery_radius_km",
+    "external_ref",
+];
+
+// First field of the record ending a CSV export that stopped early, /upload_csv refuses files that have one
+pub const INCOMPLETE_MARKER: &str = "#export_incomplete";
+
+// Pages are read in one read only snapshot, so merchants changed during the export are either all old or all new
+const SNAPSHOT_SQL: &str = "BEGIN ISOLATION LEVEL REPEATABLE READ READ ONLY";
+
+// Parquet files have the columns of the CSV export, one row group per page of merchants
+const PARQUET_SCHEMA: &str = "
+message merchant {
+    REQUIRED INT32 id;
+    REQUIRED BYTE_ARRAY name (UTF8);
+    REQUIRED BYTE_ARRAY business_category (UTF8);
+    REQUIRED BYTE_ARRAY phone_number (UTF8);
+    REQUIRED BYTE_ARRAY email (UTF8);
+    REQUIRED BYTE_ARRAY pincodes_serviced (UTF8);
+    OPTIONAL DOUBLE latitude;
+    OPTIONAL DOUBLE longitude;
+    OPTIONAL DOUBLE delivery_radius_km;
+    OPTIONAL BYTE_ARRAY external_ref (UTF8);
+}
+";
+
+#[derive(Debug, Clone, Copy)]
+enum ExportFormat {
+    Csv,
+    JsonLines,
+    Parquet,
+}
+
+impl ExportFormat {
+    fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "csv" => Some(ExportFormat::Csv),
+            "jsonl" | "ndjson" => Some(ExportFormat::JsonLines),
+            "parquet" => Some(ExportFormat::Parquet),
+            _ => None,
+        }
+    }
+
+    fn content_type(&self) -> ContentType {
+        match self {
+            ExportFormat::Csv => ContentType::CSV,
+            ExportFormat::JsonLines => ContentType::new("application", "x-ndjson"),
+            ExportFormat::Parquet => ContentType::new("application", "vnd.apache.parquet"),
+        }
+    }
+}
+
+// Merchant along with the pincodes it services itself, store pincodes belong to the stores
+struct ExportedMerchant {
+    merchant: models::Merchant,
+    pincodes: Vec<String>,
+}
+
+fn optional_text<T: ToString>(value: Option<T>) -> String {
+    value.map(|value| value.to_string()).unwrap_or_default()
+}
+
+// Writes the pages in the requested format. Parquet row groups are taken out of the writer's buffer as
+// soon as they are written, the footer is only known once every page is written
+enum Encoder {
+    Csv,
+    JsonLines,
+    Parquet(Box<SerializedFileWriter<Vec<u8>>>),
+}
+
+impl Encoder {
+    fn new(format: ExportFormat) -> Result<Self, String> {
+        match format {
+            ExportFormat::Csv => Ok(Encoder::Csv),
+            ExportFormat::JsonLines => Ok(Encoder::JsonLines),
+            ExportFormat::Parquet => {
+                let schema = parse_message_type(PARQUET_SCHEMA).map_err(|err| format!("Invalid Parquet schema: {}", err))?;
+                let properties = WriterProperties::builder().set_compression(Compression::UNCOMPRESSED).build();
+                SerializedFileWriter::new(Vec::new(), Arc::new(schema), Arc::new(properties))
+                    .map(|writer| Encoder::Parquet(Box::new(writer)))
+                    .map_err(|err| format!("Failed to start the Parquet file: {}", err))
+            }
+        }
+    }
+
+    // Bytes written before the first page, the CSV header or the Parquet magic number
+    fn start(&mut self) -> Result<Vec<u8>, String> {
+        match self {
+            Encoder::Csv => csv_bytes(|writer| writer.write_record(CSV_HEADERS)),
+            Encoder::JsonLines => Ok(Vec::new()),
+            Encoder::Parquet(writer) => Ok(std::mem::take(writer.inner_mut())),
+        }
+    }
+
+    fn encode(&mut self, page: &[ExportedMerchant]) -> Result<Vec<u8>, String> {
+        match self {
+            Encoder::Csv => csv_bytes(|writer| {
+                for exported in page {
+                    let merchant = &exported.merchant;
+                    writer.write_record([
+                        merchant.id.to_string(),
+                        merchant.name.clone(),
+                        merchant.business_category.clone(),
+                        merchant.phone_number.clone(),
+                        merchant.email.clone(),
+                        exported.pincodes.join(","),
+                        optional_text(merchant.latitude),
+                        optional_text(merchant.longitude),
+                        optional_text(merchant.delivery_radius_km),
+                        optional_text(merchant.external_ref.as_ref()),
+                    ])?;
+                }
+                Ok(())
+            }),
+            Encoder::JsonLines => {
+                let mut bytes = Vec::new();
+                for exported in page {
+                    let merchant = &exported.merchant;
+                    // Same shape as the body of POST /merchant
+                    let data = utils::MerchantData {
+                        id: merchant.id,
+                        name: merchant.name.clone(),
+                        business_category: merchant.business_category.clone(),
+                        contact: utils::ContactInformation {
+                            phone_number: merchant.phone_number.clone(),
+                            email: merchant.email.clone(),
+                        },
+                        pincodes_serviced: exported.pincodes.clone(),
+                        latitude: merchant.latitude,
+                        longitude: merchant.longitude,
+                        delivery_radius_km: merchant.delivery_radius_km,
+                        external_ref: merchant.external_ref.clone(),
+                    };
+                    serde_json::to_writer(&mut bytes, &data).map_err(|err| format!("Failed to write JSON: {}", err))?;
+                    bytes.push(b'\n');
+                }
+                Ok(bytes)
+            }
+            Encoder::Parquet(writer) => {
+                write_row_group(writer, page).map_err(|err| format!("Failed to write the Parquet row group: {}", err))?;
+                Ok(std::mem::take(writer.inner_mut()))
+            }
+        }
+    }
+
+    // Bytes ending an export that failed, so it can't be mistaken for a complete one. A Parquet file without
+    // its footer can't be read at all
+    fn fail(&mut self, message: &str) -> Vec<u8> {
+        match self {
+            Encoder::Csv => csv_bytes(|writer| writer.write_record([INCOMPLETE_MARKER, message])).unwrap_or_default(),
+            Encoder::JsonLines => {
+                let mut bytes = rocket::serde::json::json!({"error": message, "incomplete": true}).to_string().into_bytes();
+                bytes.push(b'\n');
+                bytes
+            }
+            Encoder::Parquet(_) => Vec::new(),
+        }
+    }
+
+    // Bytes written after the last page, the Parquet footer
+    fn finish(&mut self) -> Result<Vec<u8>, String> {
+        match self {
+            Encoder::Csv | Encoder::JsonLines => Ok(Vec::new()),
+            Encoder::Parquet(writer) => {
+                writer.finish().map_err(|err| format!("Failed to finish the Parquet file: {}", err))?;
+                Ok(std::mem::take(writer.inner_mut()))
+            }
+        }
+    }
+}
+
+fn csv_bytes<F>(write: F) -> Result<Vec<u8>, String>
+where
+    F: FnOnce(&mut csv::Writer<Vec<u8>>) -> csv::Result<()>,
+{
+    let mut writer = csv::WriterBuilder::new().has_headers(false).from_writer(Vec::new());
+    write(&mut writer).map_err(|err| format!("Failed to write CSV: {}", err))?;
+    writer.into_inner().map_err(|err| format!("Failed to write CSV: {}", err))
+}
+
+// Text of the required text columns of the Parquet schema, by column index
+fn text_column(exported: &ExportedMerchant, index: usize) -> String {
+    let merchant = &exported.merchant;
+    match index {
+        1 => merchant.name.clone(),
+        2 => merchant.business_category.clone(),
+        3 => merchant.phone_number.clone(),
+        4 => merchant.email.clone(),
+        _ => exported.pincodes.join(","),
+    }
+}
+
+// Optional columns are written as the values that are set, along with definition levels marking the rows that have one
+fn definition_levels<T>(values: &[Option<T>]) -> Vec<i16> {
+    values.iter().map(|value| value.is_some() as i16).collect()
+}
+
+fn write_row_group(writer: &mut SerializedFileWriter<Vec<u8>>, page: &[ExportedMerchant]) -> parquet::errors::Result<()> {
+    let mut row_group = writer.next_row_group()?;
+    let mut index = 0;
+    while let Some(mut column) = row_group.next_column()? {
+        match index {
+            0 => {
+                let ids: Vec<i32> = page.iter().map(|exported| exported.merchant.id).collect();
+                column.typed::<Int32Type>().write_batch(&ids, None, None)?;
+            }
+            1..=5 => {
+                let values: Vec<ByteArray> = page.iter().map(|exported| ByteArray::from(text_column(exported, index).as_str())).collect();
+                column.typed::<ByteArrayType>().write_batch(&values, None, None)?;
+            }
+            6..=8 => {
+                let values: Vec<Option<f64>> = page
+                    .iter()
+                    .map(|exported| match index {
+                        6 => exported.merchant.latitude,
+                        7 => exported.merchant.longitude,
+                        _ => exported.merchant.delivery_radius_km,
+                    })
+                    .collect();
+                let set: Vec<f64> = values.iter().flatten().copied().collect();
+                column.typed::<DoubleType>().write_batch(&set, Some(&definition_levels(&values)), None)?;
+            }
+            _ => {
+                let values: Vec<Option<&str>> = page.iter().map(|exported| exported.merchant.external_ref.as_deref()).collect();
+                let set: Vec<ByteArray> = values.iter().flatten().map(|value| ByteArray::from(*value)).collect();
+                column.typed::<ByteArrayType>().write_batch(&set, Some(&definition_levels(&values)), None)?;
+            }
+        }
+        column.close()?;
+        index += 1;
+    }
+    row_group.close()?;
+
+    Ok(())
+}
+
+// Merchants with an ID above after_id, ordered by ID, along with their pincodes
+async fn load_page(db: &mut AsyncPgConnection, after_id: i32) -> QueryResult<Vec<ExportedMerchant>> {
+    use crate::schema::{merchant_pincodes, merchants};
+
+    let page: Vec<models::Merchant> = merchants::table
+        .filter(merchants::id.gt(after_id))
+        .order(merchants::id.asc())
+        .limit(PAGE_SIZE)
+        .select(models::Merchant::as_select())
+        .load(db)
+        .await?;
+
+    let merchant_ids: Vec<i32> = page.iter().map(|merchant| merchant.id).collect();
+    let mut pincodes: BTreeMap<i32, Vec<String>> = BTreeMap::new();
+    for (merchant_id, pincode) in merchant_pincodes::table
+        .filter(merchant_pincodes::merchant_id.eq_any(&merchant_ids))
+        .order((merchant_pincodes::merchant_id.asc(), merchant_pincodes::pincode.asc()))
+        .select((merchant_pincodes::merchant_id, merchant_pincodes::pincode))
+        .load::<(i32, String)>(db)
+        .await?
+    {
+        pincodes.entry(merchant_id).or_default().push(pincode);
+    }
+
+    Ok(page
+        .into_iter()
+        .map(|merchant| ExportedMerchant { pincodes: pincodes.remove(&merchant.id).unwrap_or_default(), merchant })
+        .collect())
+}
+
+// Streams every merchant with the pincodes it services as CSV (default, in the /upload_csv format),
+// JSON Lines (one POST /merchant body per line) or Parquet. Merchants are read a page at a time from one
+// snapshot, so the table is never loaded into memory as a whole
+#[get("/export/merchants?<format>")]
+pub(crate) async fn export_merchants(mut db: Connection<Db>, format: Option<&str>) -> Result<(ContentType, ByteStream![Vec<u8>]), Json<utils::ApiResponse>> {
+    let Some(format) = ExportFormat::parse(format.unwrap_or("csv")) else {
+        return Err(Json(validation::error_response(vec![FieldError::new("format", "must be csv, jsonl or parquet".to_string())])));
+    };
+    let mut encoder = Encoder::new(format).map_err(|message| {
+        eprintln!("{}", message);
+        Json(utils::ApiResponse {
+            status: utils::ApiResponseStatus::Error,
+            data: rocket::serde::json::json!({"message": "Failed to start the export"}).into(),
+        })
+    })?;
+
+    // The response has started once the first bytes are sent, so failures past that point end the stream
+    // with the encoder's error marker. The snapshot is left open if the client goes away mid-stream, the pool
+    // then drops the connection instead of reusing it
+    Ok((format.content_type(), ByteStream! {
+        let conn: &mut AsyncPgConnection = &mut db;
+        if let Err(err) = AnsiTransactionManager::begin_transaction_sql(conn, SNAPSHOT_SQL).await {
+            eprintln!("Failed to start the export: {}", err);
+            yield encoder.fail("Failed to read the merchants");
+            return;
+        }
+
+        match encoder.start() {
+            Ok(bytes) => yield bytes,
+            Err(err) => {
+                eprintln!("Failed to export the merchants: {}", err);
+                let _ = AnsiTransactionManager::rollback_transaction(conn).await;
+                yield encoder.fail("Failed to start the export");
+                return;
+            }
+        }
+
+        let mut after_id = i32::MIN;
+        loop {
+            let page = match load_page(conn, after_id).await {
+                Ok(page) => page,
+                Err(err) => {
+                    eprintln!("Failed to load merchants after {} for the export: {}", after_id, err);
+                    let _ = AnsiTransactionManager::rollback_transaction(conn).await;
+                    yield encoder.fail(&format!("Failed to read the merchants after {}", after_id));
+                    return;
+                }
+            };
+            let Some(last) = page.last() else {
+                break;
+            };
+            after_id = last.merchant.id;
+
+            match encoder.encode(&page) {
+                Ok(bytes) => yield bytes,
+                Err(err) => {
+                    eprintln!("Failed to export the merchants: {}", err);
+                    let _ = AnsiTransactionManager::rollback_transaction(conn).await;
+                    yield encoder.fail(&format!("Failed to write the merchants after {}", after_id));
+                    return;
+                }
+            }
+        }
+
+        if let Err(err) = AnsiTransactionManager::commit_transaction(conn).await {
+            eprintln!("Failed to end the export snapshot: {}", err);
+        }
+        match encoder.finish() {
+            Ok(bytes) => yield bytes,
+            Err(err) => {
+                eprintln!("Failed to export the merchants: {}", err);
+                yield encoder.fail("Failed to finish the export");
+            }
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+    use parquet::record::RowAccessor;
+
+    use super::*;
+
+    fn page() -> Vec<ExportedMerchant> {
+        vec![
+            ExportedMerchant {
+                merchant: models::Merchant {
+                    id: 7,
+                    name: "Fresh, Mart".to_string(),
+                    business_category: "grocery".to_string(),
+                    phone_number: "9876543210".to_string(),
+                    email: "fresh@example.com".to_string(),
+                    latitude: Some(12.97),
+                    longitude: Some(77.59),
+                    delivery_radius_km: Some(5.0),
+                    external_ref: Some("GSTIN7".to_string()),
+                },
+                pincodes: vec!["560001".to_string(), "560002".to_string()],
+            },
+            ExportedMerchant {
+                merchant: models::Merchant {
+                    id: 9,
+                    name: "Corner Store".to_string(),
+                    business_category: "grocery".to_string(),
+                    phone_number: "9876543211".to_string(),
+                    email: "corner@example.com".to_string(),
+                    latitude: None,
+                    longitude: None,
+                    delivery_radius_km: None,
+                    external_ref: None,
+                },
+                pincodes: Vec::new(),
+            },
+        ]
+    }
+
+    fn export(format: ExportFormat) -> Vec<u8> {
+        let mut encoder = Encoder::new(format).unwrap();
+        let mut bytes = encoder.start().unwrap();
+        bytes.extend(encoder.encode(&page()).unwrap());
+        bytes.extend(encoder.finish().unwrap());
+        bytes
+    }
+
+    #[test]
+    fn format_is_parsed_case_insensitively() {
+        assert!(matches!(ExportFormat::parse(" CSV "), Some(ExportFormat::Csv)));
+        assert!(matches!(ExportFormat::parse("ndjson"), Some(ExportFormat::JsonLines)));
+        assert!(matches!(ExportFormat::parse("Parquet"), Some(ExportFormat::Parquet)));
+        assert!(ExportFormat::parse("xlsx").is_none());
+    }
+
+    #[test]
+    fn csv_export_has_the_upload_columns() {
+        let text = String::from_utf8(export(ExportFormat::Csv)).unwrap();
+
+        assert_eq!(
+            text,
+            "id,name,business_category,phone_number,email,pincodes_serviced,latitude,longitude,delivery_radius_km,external_ref\n\
+             7,\"Fresh, Mart\",grocery,9876543210,fresh@example.com,\"560001,560002\",12.97,77.59,5,GSTIN7\n\
+             9,Corner Store,grocery,9876543211,corner@example.com,,,,,\n"
+        );
+    }
+
+    #[test]
+    fn failed_csv_export_ends_with_the_incomplete_marker() {
+        let mut encoder = Encoder::new(ExportFormat::Csv).unwrap();
+
+        assert_eq!(encoder.fail("Failed to read the merchants after 7"), b"#export_incomplete,Failed to read the merchants after 7\n");
+    }
+
+    #[test]
+    fn json_lines_export_has_one_merchant_body_per_line() {
+        let text = String::from_utf8(export(ExportFormat::JsonLines)).unwrap();
+        let lines: Vec<serde_json::Value> = text.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["id"], 7);
+        assert_eq!(lines[0]["contact"]["email"], "fresh@example.com");
+        assert_eq!(lines[0]["pincodes_serviced"], serde_json::json!(["560001", "560002"]));
+        assert_eq!(lines[1]["latitude"], serde_json::Value::Null);
+    }
+
+    #[test]
+    fn parquet_export_can_be_read_back() {
+        let path = std::env::temp_dir().join(format!("export-test-{}.parquet", std::process::id()));
+        std::fs::write(&path, export(ExportFormat::Parquet)).unwrap();
+        let reader = SerializedFileReader::new(std::fs::File::open(&path).unwrap()).unwrap();
+        let rows: Vec<_> = reader.get_row_iter(None).unwrap().map(|row| row.unwrap()).collect();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(reader.metadata().num_row_groups(), 1);
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].get_int(0).unwrap(), 7);
+        assert_eq!(rows[0].get_string(5).unwrap(), "560001,560002");
+        assert_eq!(rows[0].get_double(8).unwrap(), 5.0);
+        assert_eq!(rows[0].get_string(9).unwrap(), "GSTIN7");
+        assert_eq!(rows[1].get_string(1).unwrap(), "Corner Store");
+        assert!(rows[1].get_double(6).is_err());
+        assert!(rows[1].get_string(9).is_err());
+    }
+}
@@ -0,0 +1,514 @@
+use std::env;
+use std::time::Duration;
+
+use dotenvy::dotenv;
+use rocket::fairing::AdHoc;
+use rocket::form::Form;
+use rocket::http::ContentType;
+use rocket::serde::json::{Json, json};
+use rocket::serde::Serialize;
+use rocket::time::format_description::well_known::Rfc3339;
+use rocket::time::OffsetDateTime;
+use rocket::tokio::sync::Mutex;
+use rocket::State;
+use rocket_db_pools::{Connection, Database};
+use rocket_db_pools::diesel::{AsyncPgConnection, prelude::*};
+use rocket_db_pools::diesel::scoped_futures::ScopedFutureExt;
+
+use crate::ingest::{self, RejectedRow};
+use crate::{models, utils, Db, RedisClient, Upload};
+
+const QUEUED: &str = "queued";
+const RUNNING: &str = "running";
+const COMPLETED: &str = "completed";
+const FAILED: &str = "failed";
+
+const DEFAULT_BATCH_SIZE: usize = 500;
+
+// A worker renews the lease of its job after every batch. A running job whose lease expired was left by a worker
+// that stopped, and is queued again
+const LEASE_SECS: i64 = 600;
+
+// Jobs are processed one at a time, so that their batches don't race for merchant IDs
+static WORKER: Mutex<()> = Mutex::const_new(());
+
+type Pool = rocket_db_pools::diesel::PgPool;
+
+// Import job as returned by the API, with RFC 3339 timestamps
+#[derive(Debug, Serialize)]
+pub struct ImportStatus {
+    pub id: i32,
+    pub status: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file_name: Option<String>,
+    pub allow_unknown_pincodes: bool,
+    pub total_rows: i32,
+    pub processed_rows: i32,
+    pub accepted_rows: i32,
+    pub rejected_rows: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub created_at: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub started_at: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub finished_at: Option<String>,
+    pub errors_url: String,
+}
+
+fn format_timestamp(timestamp: OffsetDateTime) -> String {
+    timestamp.format(&Rfc3339).unwrap_or_default()
+}
+
+impl From<models::ImportJob> for ImportStatus {
+    fn from(job: models::ImportJob) -> Self {
+        ImportStatus {
+            id: job.id,
+            status: job.status,
+            file_name: job.file_name,
+            allow_unknown_pincodes: job.allow_unknown_pincodes,
+            total_rows: job.total_rows,
+            processed_rows: job.processed_rows,
+            accepted_rows: job.accepted_rows,
+            rejected_rows: job.rejected_rows,
+            error: job.error,
+            created_at: format_timestamp(job.created_at),
+            started_at: job.started_at.map(format_timestamp),
+            finished_at: job.finished_at.map(format_timestamp),
+            errors_url: format!("/imports/{}/errors.csv", job.id),
+        }
+    }
+}
+
+// Merchants added per transaction, IMPORT_BATCH_SIZE overrides the default of 500
+fn batch_size() -> usize {
+    env::var("IMPORT_BATCH_SIZE")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .filter(|size| *size > 0)
+        .unwrap_or(DEFAULT_BATCH_SIZE)
+}
+
+// End of a lease taken at the given time. Leases end on a whole second so they read back from Postgres unchanged,
+// and a worker recognizes its own lease by its end
+fn lease_until(now: OffsetDateTime) -> OffsetDateTime {
+    OffsetDateTime::from_unix_timestamp(now.unix_timestamp() + LEASE_SECS).unwrap_or(now)
+}
+
+async fn load_job(db: &mut AsyncPgConnection, job_id: i32) -> QueryResult<Option<models::ImportJob>> {
+    use crate::schema::import_jobs;
+
+    import_jobs::table
+        .find(job_id)
+        .select(models::ImportJob::as_select())
+        .first(db)
+        .await
+        .optional()
+}
+
+// Marks the oldest queued job as running under a new lease and returns it with its file and the lease.
+// SKIP LOCKED keeps two workers from claiming the same job
+async fn claim_job(db: &mut AsyncPgConnection) -> QueryResult<Option<(models::ImportJob, Vec<u8>, OffsetDateTime)>> {
+    use crate::schema::import_jobs;
+
+    db.transaction::<_, diesel::result::Error, _>(|conn| async move {
+        let job_id: Option<i32> = import_jobs::table
+            .select(import_jobs::id)
+            .filter(import_jobs::status.eq(QUEUED))
+            .order(import_jobs::id.asc())
+            .for_update()
+            .skip_locked()
+            .first(conn)
+            .await
+            .optional()?;
+        let Some(job_id) = job_id else {
+            return Ok(None);
+        };
+
+        let now = OffsetDateTime::now_utc();
+        let lease = lease_until(now);
+        let job = diesel::update(import_jobs::table.find(job_id))
+            .set((
+                import_jobs::status.eq(RUNNING),
+                import_jobs::started_at.eq(now),
+                import_jobs::lease_expires_at.eq(lease),
+            ))
+            .returning(models::ImportJob::as_returning())
+            .get_result(conn)
+            .await?;
+        let csv_data: Vec<u8> = import_jobs::table.find(job_id).select(import_jobs::csv_data).first(conn).await?;
+
+        Ok(Some((job, csv_data, lease)))
+    }.scope_boxed()).await
+}
+
+// Adds the processed rows to the job's counters and stores the reasons the rejected ones failed
+async fn record_progress(db: &mut AsyncPgConnection, job_id: i32, accepted: usize, rejected: &[RejectedRow]) -> QueryResult<()> {
+    use crate::schema::{import_job_errors, import_jobs};
+
+    let errors: Vec<models::ImportJobError> = rejected
+        .iter()
+        .flat_map(|row| {
+            row.errors.iter().map(move |error| models::ImportJobError {
+                job_id,
+                line: row.line as i64,
+                field: error.field.clone(),
+                message: error.message.clone(),
+            })
+        })
+        .collect();
+    let processed = (accepted + rejected.len()) as i32;
+    let accepted = accepted as i32;
+    let rejected = rejected.len() as i32;
+
+    db.transaction::<_, diesel::result::Error, _>(|conn| async move {
+        for chunk in errors.chunks(10000) {
+            diesel::insert_into(import_job_errors::table).values(chunk).execute(conn).await?;
+        }
+        diesel::update(import_jobs::table.find(job_id))
+            .set((
+                import_jobs::processed_rows.eq(import_jobs::processed_rows + processed),
+                import_jobs::accepted_rows.eq(import_jobs::accepted_rows + accepted),
+                import_jobs::rejected_rows.eq(import_jobs::rejected_rows + rejected),
+            ))
+            .execute(conn)
+            .await?;
+        Ok(())
+    }.scope_boxed()).await
+}
+
+// Extends the lease of the job if the worker still holds it. Returns the new lease, or None when the lease
+// expired and another worker took the job over
+async fn renew_lease(db: &mut AsyncPgConnection, job_id: i32, lease: OffsetDateTime) -> QueryResult<Option<OffsetDateTime>> {
+    use crate::schema::import_jobs;
+
+    let renewed = lease_until(OffsetDateTime::now_utc());
+    let updated = diesel::update(import_jobs::table.find(job_id).filter(import_jobs::lease_expires_at.eq(lease)))
+        .set(import_jobs::lease_expires_at.eq(renewed))
+        .execute(db)
+        .await?;
+
+    Ok((updated == 1).then_some(renewed))
+}
+
+fn lease_lost(job_id: i32) -> String {
+    format!("Import job {} was taken over by another worker after its lease expired", job_id)
+}
+
+// Records the outcome of the job, unless another worker took it over
+async fn finish_job(db: &mut AsyncPgConnection, job_id: i32, lease: OffsetDateTime, error: Option<String>) -> QueryResult<()> {
+    use crate::schema::import_jobs;
+
+    let status = if error.is_some() { FAILED } else { COMPLETED };
+    diesel::update(import_jobs::table.find(job_id).filter(import_jobs::lease_expires_at.eq(lease)))
+        .set((
+            import_jobs::status.eq(status),
+            import_jobs::error.eq(error),
+            import_jobs::finished_at.eq(OffsetDateTime::now_utc()),
+            import_jobs::lease_expires_at.eq(None::<OffsetDateTime>),
+            // The file is no longer needed once the job is done
+            import_jobs::csv_data.eq(Vec::<u8>::new()),
+        ))
+        .execute(db)
+        .await?;
+
+    Ok(())
+}
+
+// Parses the file and adds its merchants batch by batch. Every batch is added in the same transaction as the
+// progress it makes and the renewal of the lease, so a job interrupted by a restart resumes after the last batch
+// recorded in processed_rows, and a worker that lost its lease stops without adding the batch
+async fn process_job(db: &mut AsyncPgConnection, redis_client: &redis::Client, job: &models::ImportJob, csv_data: &[u8], lease: &mut OffsetDateTime) -> Result<(), String> {
+    use crate::schema::import_jobs;
+
+    let (rows, rejected) = ingest::parse_merchants(csv_data)?;
+
+    // The total is recorded along with the rows rejected while parsing, a job that has one already recorded them
+    let skipped = if job.total_rows > 0 {
+        (job.processed_rows as usize).saturating_sub(rejected.len())
+    } else {
+        let total_rows = (rows.len() + rejected.len()) as i32;
+        let (job_id, held) = (job.id, *lease);
+        let renewed = db.transaction::<_, diesel::result::Error, _>(|conn| async move {
+            let Some(renewed) = renew_lease(conn, job_id, held).await? else {
+                return Ok(None);
+            };
+            diesel::update(import_jobs::table.find(job_id))
+                .set(import_jobs::total_rows.eq(total_rows))
+                .execute(conn)
+                .await?;
+            record_progress(conn, job_id, 0, &rejected).await?;
+            Ok(Some(renewed))
+        }.scope_boxed())
+        .await
+        .map_err(|err| format!("Failed to record the rejected rows: {}", err))?;
+        *lease = renewed.ok_or_else(|| lease_lost(job_id))?;
+        0
+    };
+
+    let remaining = rows.get(skipped..).unwrap_or_default();
+    for batch in remaining.chunks(batch_size()) {
+        let (job_id, allow_unknown_pincodes, held) = (job.id, job.allow_unknown_pincodes, *lease);
+        let batch = batch.to_vec();
+        // The transactions of ingest become savepoints of this one
+        let written = db.transaction::<_, utils::StoreError, _>(|conn| async move {
+            let Some(renewed) = renew_lease(conn, job_id, held).await? else {
+                return Ok(None);
+            };
+            let (report, pending) = ingest::ingest(conn, batch, Vec::new(), allow_unknown_pincodes, false, None).await?;
+            record_progress(conn, job_id, report.accepted.len(), &report.rejected).await?;
+            Ok(Some((pending, renewed)))
+        }.scope_boxed())
+        .await
+        .map_err(|err| format!("Failed to add the merchants: {}", err))?;
+
+        let (pending, renewed) = written.ok_or_else(|| lease_lost(job_id))?;
+        *lease = renewed;
+        pending.apply(redis_client);
+    }
+
+    Ok(())
+}
+
+// Processes queued jobs until none are left
+async fn run_worker(pool: Pool, redis_client: redis::Client) {
+    let _guard = WORKER.lock().await;
+
+    let mut db = match pool.get().await {
+        Ok(db) => db,
+        Err(err) => {
+            eprintln!("Import worker stopped, could not get a database connection: {:?}", err);
+            return;
+        }
+    };
+
+    loop {
+        let (job, csv_data, mut lease) = match claim_job(&mut db).await {
+            Ok(Some(claimed)) => claimed,
+            Ok(None) => return,
+            Err(err) => {
+                eprintln!("Import worker stopped, failed to claim a job: {}", err);
+                return;
+            }
+        };
+
+        let error = process_job(&mut db, &redis_client, &job, &csv_data, &mut lease).await.err();
+        if let Some(err) = &error {
+            eprintln!("Import job {} failed: {}", job.id, err);
+        }
+        if let Err(err) = finish_job(&mut db, job.id, lease, error).await {
+            eprintln!("Failed to finish import job {}: {}", job.id, err);
+        }
+    }
+}
+
+// Queues a merchant CSV file (csv_file form field) for onboarding in the background and returns the job ID.
+// The file is read the same way as by /upload_csv, in batches of IMPORT_BATCH_SIZE merchants
+#[post("/imports?<allow_unknown_pincodes>", data = "<form>")]
+pub(crate) async fn create_import(pool: &State<Db>, redis: &State<RedisClient>, mut db: Connection<Db>, mut form: Form<Upload<'_>>, allow_unknown_pincodes: Option<bool>) -> Json<utils::ApiResponse> {
+    use crate::schema::import_jobs;
+
+    let file_name = form.upload.name().map(|name| name.to_string());
+    let csv_data = match utils::read_upload(&mut form.upload).await {
+        Ok(csv_data) => csv_data,
+        Err(err) => {
+            eprintln!("Failed to persist the import upload: {:?}", err);
+            return Json(utils::ApiResponse {
+                status: utils::ApiResponseStatus::Error,
+                data: json!({"message": "Failed to read the uploaded file"}).into(),
+            });
+        }
+    };
+
+    let new_job = models::NewImportJob {
+        file_name,
+        csv_data,
+        allow_unknown_pincodes: allow_unknown_pincodes.unwrap_or(false),
+    };
+    let job: models::ImportJob = match diesel::insert_into(import_jobs::table)
+        .values(&new_job)
+        .returning(models::ImportJob::as_returning())
+        .get_result(&mut db)
+        .await
+    {
+        Ok(job) => job,
+        Err(err) => {
+            eprintln!("Failed to queue the import: {}", err);
+            return Json(utils::ApiResponse {
+                status: utils::ApiResponseStatus::Error,
+                data: json!({"message": "Failed to queue the import"}).into(),
+            });
+        }
+    };
+
+    rocket::tokio::spawn(run_worker(pool.0.clone(), redis.client.clone()));
+
+    Json(utils::ApiResponse {
+        status: utils::ApiResponseStatus::Success,
+        data: json!({
+            "job_id": job.id,
+            "status_url": format!("/imports/{}", job.id),
+            "import": ImportStatus::from(job),
+            "message": "Import queued"
+        }).into(),
+    })
+}
+
+#[get("/imports/<job_id>")]
+pub(crate) async fn get_import(mut db: Connection<Db>, job_id: i32) -> Json<utils::ApiResponse> {
+    match load_job(&mut db, job_id).await {
+        Ok(Some(job)) => Json(utils::ApiResponse {
+            status: utils::ApiResponseStatus::Success,
+            data: json!(ImportStatus::from(job)).into(),
+        }),
+        Ok(None) => Json(utils::ApiResponse {
+            status: utils::ApiResponseStatus::Error,
+            data: json!({"message": "Import not found"}).into(),
+        }),
+        Err(err) => {
+            eprintln!("Failed to load import {}: {}", job_id, err);
+            Json(utils::ApiResponse {
+                status: utils::ApiResponseStatus::Error,
+                data: json!({"message": "Failed to load the import"}).into(),
+            })
+        }
+    }
+}
+
+// Rejected rows of an import as a CSV file with line, field and message columns, ordered by line
+#[get("/imports/<job_id>/errors.csv")]
+pub(crate) async fn get_import_errors(mut db: Connection<Db>, job_id: i32) -> Option<(ContentType, String)> {
+    use crate::schema::import_job_errors;
+
+    load_job(&mut db, job_id).await.ok()??;
+    let errors: Vec<models::ImportJobError> = match import_job_errors::table
+        .filter(import_job_errors::job_id.eq(job_id))
+        .order((import_job_errors::line.asc(), import_job_errors::id.asc()))
+        .select(models::ImportJobError::as_select())
+        .load(&mut db)
+        .await
+    {
+        Ok(errors) => errors,
+        Err(err) => {
+            eprintln!("Failed to load the errors of import {}: {}", job_id, err);
+            return None;
+        }
+    };
+
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    let _ = writer.write_record(["line", "field", "message"]);
+    for error in &errors {
+        let _ = writer.write_record([error.line.to_string(), error.field.clone(), error.message.clone()]);
+    }
+    let csv_data = writer.into_inner().ok()?;
+
+    Some((ContentType::CSV, String::from_utf8_lossy(&csv_data).into_owned()))
+}
+
+// Queues the running jobs whose lease expired again. Returns when the first lease still held ends, if any
+async fn requeue_expired_jobs(db: &mut AsyncPgConnection) -> QueryResult<Option<OffsetDateTime>> {
+    use crate::schema::import_jobs;
+
+    let now = OffsetDateTime::now_utc();
+    // Jobs that were running before leases were recorded have none, they are queued again like expired ones
+    diesel::update(
+        import_jobs::table
+            .filter(import_jobs::status.eq(RUNNING))
+            .filter(import_jobs::lease_expires_at.le(now).or(import_jobs::lease_expires_at.is_null())),
+    )
+    .set((import_jobs::status.eq(QUEUED), import_jobs::lease_expires_at.eq(None::<OffsetDateTime>)))
+    .execute(db)
+    .await?;
+
+    import_jobs::table
+        .filter(import_jobs::status.eq(RUNNING))
+        .select(diesel::dsl::min(import_jobs::lease_expires_at))
+        .first(db)
+        .await
+}
+
+// Picks up the jobs queued before a restart once the server has launched. Jobs that were running when the
+// server stopped are queued again once their lease expires, and resume after the last batch they recorded.
+// Jobs running under a lease that is still held, by another server or by this one before it restarted,
+// are checked again when the lease ends
+pub fn stage() -> AdHoc {
+    AdHoc::on_liftoff("Resume Merchant Imports", |rocket| Box::pin(async move {
+        dotenv().ok();
+
+        let (Some(db), Some(redis)) = (Db::fetch(rocket), rocket.state::<RedisClient>()) else {
+            eprintln!("Imports not resumed: database or Redis client unavailable");
+            return;
+        };
+
+        let pool = db.0.clone();
+        let redis_client = redis.client.clone();
+
+        rocket::tokio::spawn(async move {
+            loop {
+                let held_until = match pool.get().await {
+                    Ok(mut con) => match requeue_expired_jobs(&mut con).await {
+                        Ok(held_until) => held_until,
+                        Err(err) => {
+                            eprintln!("Failed to queue the interrupted imports again: {}", err);
+                            None
+                        }
+                    },
+                    Err(err) => {
+                        eprintln!("Imports not resumed, could not get a database connection: {:?}", err);
+                        return;
+                    }
+                };
+
+                run_worker(pool.clone(), redis_client.clone()).await;
+
+                let Some(held_until) = held_until else {
+                    return;
+                };
+                let wait = (held_until - OffsetDateTime::now_utc()).whole_seconds().max(0) as u64 + 1;
+                rocket::tokio::time::sleep(Duration::from_secs(wait)).await;
+            }
+        });
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn job(started_at: Option<OffsetDateTime>) -> models::ImportJob {
+        models::ImportJob {
+            id: 7,
+            status: RUNNING.to_string(),
+            file_name: Some("merchants.csv".to_string()),
+            allow_unknown_pincodes: false,
+            total_rows: 10,
+            processed_rows: 4,
+            accepted_rows: 3,
+            rejected_rows: 1,
+            error: None,
+            created_at: OffsetDateTime::from_unix_timestamp(1_700_000_000).unwrap(),
+            started_at,
+            finished_at: None,
+        }
+    }
+
+    #[test]
+    fn leases_end_on_a_whole_second_after_the_lease_time() {
+        let now = OffsetDateTime::from_unix_timestamp(1_700_000_000).unwrap() + rocket::time::Duration::milliseconds(750);
+        let lease = lease_until(now);
+
+        assert_eq!(lease.nanosecond(), 0);
+        assert_eq!(lease.unix_timestamp(), 1_700_000_000 + LEASE_SECS);
+        assert_eq!(lease_until(now + rocket::time::Duration::milliseconds(100)), lease);
+    }
+
+    #[test]
+    fn import_status_formats_timestamps_and_links_the_errors() {
+        let status = ImportStatus::from(job(Some(OffsetDateTime::from_unix_timestamp(1_700_000_060).unwrap())));
+
+        assert_eq!(status.created_at, "2023-11-14T22:13:20Z");
+        assert_eq!(status.started_at.as_deref(), Some("2023-11-14T22:14:20Z"));
+        assert_eq!(status.finished_at, None);
+        assert_eq!(status.errors_url, "/imports/7/errors.csv");
+    }
+}
@@ -0,0 +1,906 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::env;
+use std::time::Duration;
+
+use dotenvy::dotenv;
+use redis::Commands;
+use rocket::fairing::AdHoc;
+use rocket::serde::json::{Json, json};
+use rocket::serde::Serialize;
+use rocket::State;
+use rocket::time::OffsetDateTime;
+use rocket_db_pools::{Connection, Database};
+use rocket_db_pools::diesel::{AsyncPgConnection, prelude::*};
+
+use crate::{capacity, models, pauses, rules, schedules, stores, utils, Db, RedisClient};
+
+// The serviceability index in Redis is versioned. Readers use the keys of the version stored in
+// VERSION_KEY, a reindex builds the next version next to it and swaps VERSION_KEY once it is complete.
+// Version 0 uses the unprefixed keys so an index written before versioning keeps working.
+const VERSION_KEY: &str = "serviceability:version";
+
+// Version currently being built by a reindex. Writes go to both versions while it exists
+const BUILDING_KEY: &str = "serviceability:building";
+
+// Counter used to hand out new index versions
+const VERSION_COUNTER_KEY: &str = "serviceability:version_counter";
+
+// Versions replaced by a reindex, scored by the time they were replaced. They are deleted once readers that
+// looked up the version before the swap are done with it
+const RETIRED_KEY: &str = "serviceability:retired";
+
+// Time a replaced version is kept for the readers still using it
+const RETIRED_GRACE_SECS: u64 = 300;
+
+// A reindex renews its lock after every batch, one that hasn't renewed it within this time is considered
+// dead and another one may start
+const BUILD_TIMEOUT_SECS: u64 = 600;
+
+// KEYS[1] is BUILDING_KEY, ARGV the version being built and the lock timeout. Extends the lock if it is
+// still held by the version, returns 0 when it expired or was taken by another reindex
+const RENEW_BUILD_SCRIPT: &str = r"
+if redis.call('GET', KEYS[1]) ~= ARGV[1] then
+    return 0
+end
+redis.call('EXPIRE', KEYS[1], ARGV[2])
+return 1
+";
+
+// KEYS[1] is BUILDING_KEY, ARGV[1] the version being built. Releases the lock if the version still holds it
+const RELEASE_BUILD_SCRIPT: &str = r"
+if redis.call('GET', KEYS[1]) == ARGV[1] then
+    redis.call('DEL', KEYS[1])
+end
+return 1
+";
+
+// KEYS[1] is BUILDING_KEY and KEYS[2] VERSION_KEY, ARGV[1] the version that was built. Promotes the version
+// and releases the lock, unless the lock was lost: writes made since then never reached the new version
+const SWAP_VERSION_SCRIPT: &str = r"
+if redis.call('GET', KEYS[1]) ~= ARGV[1] then
+    return 0
+end
+redis.call('SET', KEYS[2], ARGV[1])
+redis.call('DEL', KEYS[1])
+return 1
+";
+
+// Number of merchants read from Postgres and written to Redis per pipeline
+const REINDEX_BATCH_SIZE: i64 = 1000;
+
+// Number of keys deleted per command when removing an old index version
+const CLEANUP_BATCH_SIZE: usize = 1000;
+
+fn prefix(version: u64) -> String {
+    if version == 0 {
+        String::new()
+    } else {
+        format!("v{}:", version)
+    }
+}
+
+// Set of merchant IDs servicing the pincode
+pub fn pincode_key(version: u64, pincode: &str) -> String {
+    format!("{}pincodes:{}", prefix(version), pincode)
+}
+
+// Set of pincodes serviced by the merchant
+pub fn merchant_pincodes_key(version: u64, merchant_id: i32) -> String {
+    format!("{}merchant:{}:pincodes", prefix(version), merchant_id)
+}
+
+// Set of the stores servicing the pincode, members are "<merchant_id>:<store_id>"
+pub fn stores_key(version: u64, pincode: &str) -> String {
+    format!("{}stores:{}", prefix(version), pincode)
+}
+
+pub fn stores_pattern(version: u64) -> String {
+    stores_key(version, "*")
+}
+
+// Returns the pincode of a key matched by stores_pattern
+pub fn parse_stores_key(version: u64, key: &str) -> Option<String> {
+    key.strip_prefix(&stores_key(version, "")).map(|pincode| pincode.to_string())
+}
+
+pub fn store_member(merchant_id: i32, store_id: i32) -> String {
+    format!("{}:{}", merchant_id, store_id)
+}
+
+// Returns the merchant and store ID of a member of a stores set
+pub fn parse_store_member(member: &str) -> Option<(i32, i32)> {
+    let (merchant_id, store_id) = member.split_once(':')?;
+    Some((merchant_id.parse().ok()?, store_id.parse().ok()?))
+}
+
+// Hash of the delivery terms set for the pincode, merchant ID to JSON encoded terms
+pub fn terms_key(version: u64, pincode: &str) -> String {
+    format!("{}terms:{}", prefix(version), pincode)
+}
+
+pub fn terms_pattern(version: u64) -> String {
+    terms_key(version, "*")
+}
+
+// Returns the pincode of a key matched by terms_pattern
+pub fn parse_terms_key(version: u64, key: &str) -> Option<String> {
+    key.strip_prefix(&terms_key(version, "")).map(|pincode| pincode.to_string())
+}
+
+// Set of merchant IDs that excluded the pincode
+pub fn excluded_key(version: u64, pincode: &str) -> String {
+    format!("{}excluded:{}", prefix(version), pincode)
+}
+
+pub fn excluded_pattern(version: u64) -> String {
+    excluded_key(version, "*")
+}
+
+// Returns the pincode of a key matched by excluded_pattern
+pub fn parse_excluded_key(version: u64, key: &str) -> Option<String> {
+    key.strip_prefix(&excluded_key(version, "")).map(|pincode| pincode.to_string())
+}
+
+// JSON encoded opening hours and holidays of the merchant, only set for merchants with a schedule
+pub fn schedule_key(version: u64, merchant_id: i32) -> String {
+    format!("{}schedule:{}", prefix(version), merchant_id)
+}
+
+pub fn schedule_pattern(version: u64) -> String {
+    format!("{}schedule:*", prefix(version))
+}
+
+// Returns the merchant ID of a key matched by schedule_pattern
+pub fn parse_schedule_key(version: u64, key: &str) -> Option<i32> {
+    key.strip_prefix(&format!("{}schedule:", prefix(version)))?.parse().ok()
+}
+
+// Set of merchant IDs paused for the pincode. Members stay until a lookup finds their pause marker expired
+pub fn paused_key(version: u64, pincode: &str) -> String {
+    format!("{}paused:{}", prefix(version), pincode)
+}
+
+// Prefix of the pause markers of the pincode, the marker of a merchant is the prefix followed by its ID
+pub fn pause_marker_prefix(version: u64, pincode: &str) -> String {
+    format!("{}pause:{}:", prefix(version), pincode)
+}
+
+// Key expiring when the pause of the merchant for the pincode ends
+pub fn pause_marker_key(version: u64, pincode: &str, merchant_id: i32) -> String {
+    format!("{}{}", pause_marker_prefix(version, pincode), merchant_id)
+}
+
+// Hash of the daily capacity of the merchant, "total" for the overall limit and one field per pincode limit
+pub fn capacity_key(version: u64, merchant_id: i32) -> String {
+    format!("{}capacity:{}", prefix(version), merchant_id)
+}
+
+pub fn capacity_pattern(version: u64) -> String {
+    format!("{}capacity:*", prefix(version))
+}
+
+// Returns the merchant ID of a key matched by capacity_pattern
+pub fn parse_capacity_key(version: u64, key: &str) -> Option<i32> {
+    key.strip_prefix(&format!("{}capacity:", prefix(version)))?.parse().ok()
+}
+
+// Orders taken by the merchant during a capacity period, "total" and one field per pincode.
+// Usage is live state rather than something rebuilt from Postgres, so it isn't versioned
+pub fn capacity_usage_key(period: &str, merchant_id: i32) -> String {
+    format!("capacity_usage:{}:{}", period, merchant_id)
+}
+
+// Set of merchant IDs in the business category
+pub fn category_key(version: u64, category: &str) -> String {
+    format!("{}category:{}", prefix(version), utils::normalize_category(category))
+}
+
+pub fn category_pattern(version: u64) -> String {
+    format!("{}category:*", prefix(version))
+}
+
+// Returns the category of a key matched by category_pattern
+pub fn parse_category_key(version: u64, key: &str) -> Option<String> {
+    key.strip_prefix(&format!("{}category:", prefix(version))).map(|category| category.to_string())
+}
+
+// Scratch key for intermediate results of a lookup, deleted in the same pipeline
+// Merchants written to since the reindex building the version read its current batch, see build_version
+fn changes_key(version: u64) -> String {
+    format!("{}changes", prefix(version))
+}
+
+pub fn temp_key(version: u64) -> String {
+    format!("{}tmp:{}", prefix(version), rand::random::<u64>())
+}
+
+// Hash with the details of the merchant
+pub fn merchant_key(version: u64, merchant_id: i32) -> String {
+    format!("{}merchant:{}", prefix(version), merchant_id)
+}
+
+// Geo set with the store location of every merchant that has a delivery radius
+pub fn geo_key(version: u64) -> String {
+    format!("{}geo:merchants", prefix(version))
+}
+
+// Sorted set of merchant IDs scored by their delivery radius in km
+pub fn radius_key(version: u64) -> String {
+    format!("{}geo:radius", prefix(version))
+}
+
+// Sorted set of the prefix and range rules covering any pincode of a bucket, scored by the lowest pincode they cover.
+// A bucket holds a thousand pincodes, those sharing their first three digits, so a lookup only reads the
+// rules around its pincode
+pub fn rules_key(version: u64, bucket: u32) -> String {
+    format!("{}rules:{}", prefix(version), bucket)
+}
+
+pub fn rule_bucket(pincode: u32) -> u32 {
+    pincode / 1000
+}
+
+pub fn rules_pattern(version: u64) -> String {
+    format!("{}rules:*", prefix(version))
+}
+
+// Member of the rule set, "<merchant_id>:<rule_type>:<value>"
+pub fn rule_member(rule: &models::MerchantPincodeRule) -> String {
+    format!("{}:{}:{}", rule.merchant_id, rule.rule_type, rule.value)
+}
+
+pub fn parse_rule_member(member: &str) -> Option<models::MerchantPincodeRule> {
+    let mut parts = member.splitn(3, ':');
+
+    Some(models::MerchantPincodeRule {
+        merchant_id: parts.next()?.parse().ok()?,
+        rule_type: parts.next()?.to_string(),
+        value: parts.next()?.to_string(),
+    })
+}
+
+pub fn pincode_pattern(version: u64) -> String {
+    pincode_key(version, "*")
+}
+
+pub fn merchant_pincodes_pattern(version: u64) -> String {
+    format!("{}merchant:*:pincodes", prefix(version))
+}
+
+// Returns the pincode of a key matched by pincode_pattern
+pub fn parse_pincode_key(version: u64, key: &str) -> Option<String> {
+    key.strip_prefix(&pincode_key(version, "")).map(|pincode| pincode.to_string())
+}
+
+pub fn merchant_pattern(version: u64) -> String {
+    format!("{}merchant:*", prefix(version))
+}
+
+// Returns the merchant ID of a key matched by merchant_pattern, None for the merchant's pincode set
+pub fn parse_merchant_key(version: u64, key: &str) -> Option<i32> {
+    key.strip_prefix(&format!("{}merchant:", prefix(version)))?
+        .parse()
+        .ok()
+}
+
+// Set of merchant JSON blobs written by earlier releases, replaced by the merchant hashes
+pub fn legacy_merchants_key(version: u64) -> String {
+    format!("{}merchants", prefix(version))
+}
+
+// Fields of the merchant hash that are only present for merchants with a location
+pub const LOCATION_FIELDS: [&str; 3] = ["latitude", "longitude", "delivery_radius_km"];
+
+// Latitude, longitude and delivery radius of the merchant, if it has a location
+pub fn merchant_location(merchant: &models::Merchant) -> Option<(f64, f64, f64)> {
+    Some((merchant.latitude?, merchant.longitude?, merchant.delivery_radius_km?))
+}
+
+// Fields stored in the merchant hash
+pub fn merchant_fields(merchant: &models::Merchant) -> Vec<(&'static str, String)> {
+    let mut fields = vec![
+        ("id", merchant.id.to_string()),
+        ("name", merchant.name.clone()),
+        ("business_category", merchant.business_category.clone()),
+        ("phone_number", merchant.phone_number.clone()),
+        ("email", merchant.email.clone()),
+    ];
+
+    if let Some((latitude, longitude, delivery_radius_km)) = merchant_location(merchant) {
+        fields.push(("latitude", latitude.to_string()));
+        fields.push(("longitude", longitude.to_string()));
+        fields.push(("delivery_radius_km", delivery_radius_km.to_string()));
+    }
+    if let Some(external_ref) = &merchant.external_ref {
+        fields.push(("external_ref", external_ref.clone()));
+    }
+
+    fields
+}
+
+// Builds the merchant back from the fields of its hash, None if the hash is missing or incomplete
+pub fn merchant_from_fields(mut fields: HashMap<String, String>) -> Option<models::Merchant> {
+    let mut optional_field = |name: &str| -> Option<Option<f64>> {
+        match fields.remove(name) {
+            Some(value) => Some(Some(value.parse().ok()?)),
+            None => Some(None),
+        }
+    };
+
+    let latitude = optional_field("latitude")?;
+    let longitude = optional_field("longitude")?;
+    let delivery_radius_km = optional_field("delivery_radius_km")?;
+
+    Some(models::Merchant {
+        id: fields.remove("id")?.parse().ok()?,
+        name: fields.remove("name")?,
+        business_category: fields.remove("business_category")?,
+        phone_number: fields.remove("phone_number")?,
+        email: fields.remove("email")?,
+        latitude,
+        longitude,
+        delivery_radius_km,
+        external_ref: fields.remove("external_ref"),
+    })
+}
+
+// Delivery terms are stored as JSON in the terms hashes
+pub fn encode_terms(terms: &models::DeliveryTerms) -> String {
+    serde_json::to_string(terms).unwrap_or_default()
+}
+
+pub fn decode_terms(value: &str) -> Option<models::DeliveryTerms> {
+    serde_json::from_str(value).ok()
+}
+
+// Adds the merchant to the geo index, or removes it if it has no location
+pub fn add_location_commands(pipe: &mut redis::Pipeline, version: u64, merchant: &models::Merchant) {
+    match merchant_location(merchant) {
+        Some((latitude, longitude, delivery_radius_km)) => {
+            pipe.cmd("GEOADD").arg(geo_key(version)).arg(longitude).arg(latitude).arg(merchant.id).ignore();
+            pipe.zadd(radius_key(version), merchant.id, delivery_radius_km).ignore();
+        }
+        None => {
+            pipe.zrem(geo_key(version), merchant.id).ignore();
+            pipe.zrem(radius_key(version), merchant.id).ignore();
+        }
+    }
+}
+
+// Adds the merchant's hash, category and location to the index
+pub fn add_merchant_commands(pipe: &mut redis::Pipeline, version: u64, merchant: &models::Merchant) {
+    pipe.hset_multiple(merchant_key(version, merchant.id), &merchant_fields(merchant)).ignore();
+    pipe.sadd(category_key(version, &merchant.business_category), merchant.id).ignore();
+    if merchant_location(merchant).is_some() {
+        add_location_commands(pipe, version, merchant);
+    }
+}
+
+// Returns the merchant ID of a key matched by merchant_pincodes_pattern
+pub fn parse_merchant_pincodes_key(version: u64, key: &str) -> Option<i32> {
+    key.strip_prefix(&format!("{}merchant:", prefix(version)))?
+        .strip_suffix(":pincodes")?
+        .parse()
+        .ok()
+}
+
+// Patterns matching every key that belongs to a version of the index
+fn version_patterns(version: u64) -> Vec<String> {
+    vec![
+        pincode_pattern(version),
+        // Matches the merchant hashes and the merchant pincode sets
+        merchant_pattern(version),
+        category_pattern(version),
+        format!("{}geo:*", prefix(version)),
+        // Also matches the single rule set used before rules were split into buckets
+        format!("{}rules*", prefix(version)),
+        excluded_pattern(version),
+        stores_pattern(version),
+        terms_pattern(version),
+        schedule_pattern(version),
+        format!("{}paused:*", prefix(version)),
+        format!("{}pause:*", prefix(version)),
+        capacity_pattern(version),
+        format!("{}tmp:*", prefix(version)),
+        changes_key(version),
+        legacy_merchants_key(version),
+    ]
+}
+
+// Version of the index readers should use
+pub fn current_version(con: &mut redis::Connection) -> redis::RedisResult<u64> {
+    let version: Option<u64> = con.get(VERSION_KEY)?;
+    Ok(version.unwrap_or(0))
+}
+
+// Versions writers have to update: the current one and the one being built, if any
+fn write_versions(con: &mut redis::Connection) -> redis::RedisResult<Vec<u64>> {
+    let (current, building): (Option<u64>, Option<u64>) = redis::pipe()
+        .get(VERSION_KEY)
+        .get(BUILDING_KEY)
+        .query(con)?;
+
+    let mut versions = vec![current.unwrap_or(0)];
+    if let Some(building) = building {
+        versions.push(building);
+    }
+
+    Ok(versions)
+}
+
+// Writes to every version writers have to update, with the commands add_commands adds for each of them.
+// The versions are read under WATCH and the commands sent in one MULTI, so a reindex starting or swapping
+// in between makes the write start over with the new versions. The merchants written to are marked as changed
+// in the version being built, so the reindex doesn't overwrite them with rows it read before the write
+pub fn write<F>(con: &mut redis::Connection, merchant_ids: &[i32], mut add_commands: F) -> redis::RedisResult<()>
+where
+    F: FnMut(&mut redis::Connection, &mut redis::Pipeline, u64) -> redis::RedisResult<()>,
+{
+    redis::transaction(con, &[VERSION_KEY, BUILDING_KEY], |con, pipe| {
+        let versions = write_versions(con)?;
+        for &version in &versions {
+            add_commands(con, pipe, version)?;
+        }
+        if let (Some(&building), false) = (versions.get(1), merchant_ids.is_empty()) {
+            pipe.sadd(changes_key(building), merchant_ids).ignore();
+        }
+
+        pipe.query(con)
+    })
+}
+
+type IndexStep = Box<dyn FnMut(&mut redis::Connection, &mut redis::Pipeline, u64) -> redis::RedisResult<()> + Send>;
+
+// Index writes collected while a Postgres transaction runs, sent in one MULTI once it has committed.
+// Postgres is the source of truth: Redis written inside the transaction would keep writes whose commit failed,
+// while a write failing after the commit is only logged and left to the reconciliation task
+#[derive(Default)]
+pub struct IndexUpdate {
+    merchant_ids: BTreeSet<i32>,
+    steps: Vec<IndexStep>,
+}
+
+impl IndexUpdate {
+    // Adds the commands of a write to the merchant, step is called for every version written
+    pub fn add<F>(&mut self, merchant_id: i32, step: F)
+    where
+        F: FnMut(&mut redis::Connection, &mut redis::Pipeline, u64) -> redis::RedisResult<()> + Send + 'static,
+    {
+        self.merchant_ids.insert(merchant_id);
+        self.steps.push(Box::new(step));
+    }
+
+    pub fn write(&mut self, con: &mut redis::Connection) -> redis::RedisResult<()> {
+        if self.steps.is_empty() {
+            return Ok(());
+        }
+
+        let merchant_ids: Vec<i32> = self.merchant_ids.iter().copied().collect();
+        let steps = &mut self.steps;
+        write(con, &merchant_ids, |con, pipe, version| {
+            for step in steps.iter_mut() {
+                step(con, pipe, version)?;
+            }
+            Ok(())
+        })
+    }
+
+    // Writes the update once the transaction it was collected in has committed
+    pub fn apply(mut self, redis_client: &redis::Client) {
+        if let Err(err) = redis_client.get_connection().and_then(|mut con| self.write(&mut con)) {
+            eprintln!("Failed to update merchants {:?} in Redis, the next reconciliation will repair them: {:?}", self.merchant_ids, err);
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReindexReport {
+    pub version: u64,
+    pub merchants_indexed: usize,
+    pub pincodes_indexed: usize,
+}
+
+fn delete_version(con: &mut redis::Connection, version: u64) -> redis::RedisResult<()> {
+    for pattern in version_patterns(version) {
+        let keys: Vec<String> = con.scan_match(&pattern)?.collect();
+
+        for batch in keys.chunks(CLEANUP_BATCH_SIZE) {
+            con.del::<_, ()>(batch)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn lock_lost() -> redis::RedisError {
+    redis::RedisError::from((redis::ErrorKind::ResponseError, "The reindex lock expired before the new index version was complete"))
+}
+
+// Extends the reindex lock held by the version
+fn renew_build(con: &mut redis::Connection, version: u64) -> redis::RedisResult<()> {
+    let renewed: i32 = redis::Script::new(RENEW_BUILD_SCRIPT)
+        .key(BUILDING_KEY)
+        .arg(version)
+        .arg(BUILD_TIMEOUT_SECS)
+        .invoke(con)?;
+
+    if renewed == 1 {
+        Ok(())
+    } else {
+        Err(lock_lost())
+    }
+}
+
+// Rows of a batch of merchants read from Postgres, to write to the version being built
+struct IndexBatch {
+    merchants: Vec<models::Merchant>,
+    pincode_rows: Vec<models::ServicedPincode>,
+    rule_rows: Vec<models::MerchantPincodeRule>,
+    exclusion_rows: Vec<(i32, String)>,
+    merchant_schedules: HashMap<i32, schedules::Schedule>,
+    active_pauses: Vec<models::MerchantPause>,
+    capacity_limits: BTreeMap<i32, Vec<(String, i32)>>,
+    store_pincodes: Vec<stores::ServicedStorePincode>,
+}
+
+async fn load_batch(db: &mut AsyncPgConnection, merchants: Vec<models::Merchant>) -> QueryResult<IndexBatch> {
+    use crate::schema::{merchant_pincode_exclusions, merchant_pincode_rules, merchant_pincodes};
+
+    let merchant_ids: Vec<i32> = merchants.iter().map(|merchant| merchant.id).collect();
+    let pincode_rows = merchant_pincodes::table
+        .filter(merchant_pincodes::merchant_id.eq_any(&merchant_ids))
+        .select(models::ServicedPincode::as_select())
+        .order((merchant_pincodes::merchant_id.asc(), merchant_pincodes::pincode.asc()))
+        .load(db)
+        .await?;
+
+    let rule_rows = merchant_pincode_rules::table
+        .filter(merchant_pincode_rules::merchant_id.eq_any(&merchant_ids))
+        .select(models::MerchantPincodeRule::as_select())
+        .load(db)
+        .await?;
+
+    let exclusion_rows = merchant_pincode_exclusions::table
+        .filter(merchant_pincode_exclusions::merchant_id.eq_any(&merchant_ids))
+        .select((merchant_pincode_exclusions::merchant_id, merchant_pincode_exclusions::pincode))
+        .load::<(i32, String)>(db)
+        .await?;
+
+    Ok(IndexBatch {
+        merchant_schedules: schedules::load_schedules(db, Some(&merchant_ids)).await?,
+        active_pauses: pauses::load_active_pauses(db, &merchant_ids).await?,
+        capacity_limits: capacity::load_limits(db, Some(&merchant_ids)).await?,
+        store_pincodes: stores::load_store_pincodes(db, Some(&merchant_ids)).await?,
+        merchants,
+        pincode_rows,
+        rule_rows,
+        exclusion_rows,
+    })
+}
+
+// Adds the batch to the version, leaving out the skipped merchants
+fn add_batch_commands(pipe: &mut redis::Pipeline, version: u64, batch: &IndexBatch, skipped: &HashSet<i32>) {
+    let written = |merchant_id: &i32| !skipped.contains(merchant_id);
+
+    for merchant in batch.merchants.iter().filter(|merchant| written(&merchant.id)) {
+        add_merchant_commands(pipe, version, merchant);
+    }
+    for row in batch.pincode_rows.iter().filter(|row| written(&row.merchant_id)) {
+        pipe.sadd(pincode_key(version, &row.pincode), row.merchant_id).ignore();
+        pipe.sadd(merchant_pincodes_key(version, row.merchant_id), &row.pincode).ignore();
+        if !row.terms.is_empty() {
+            pipe.hset(terms_key(version, &row.pincode), row.merchant_id, encode_terms(&row.terms)).ignore();
+        }
+    }
+    for rule in batch.rule_rows.iter().filter(|rule| written(&rule.merchant_id)) {
+        rules::add_rule_commands(pipe, version, rule);
+    }
+    for (merchant_id, pincode) in batch.exclusion_rows.iter().filter(|(merchant_id, _)| written(merchant_id)) {
+        pipe.sadd(excluded_key(version, pincode), *merchant_id).ignore();
+    }
+    for (merchant_id, schedule) in batch.merchant_schedules.iter().filter(|(merchant_id, _)| written(merchant_id)) {
+        pipe.set(schedule_key(version, *merchant_id), schedule.encode()).ignore();
+    }
+    for pause in batch.active_pauses.iter().filter(|pause| written(&pause.merchant_id)) {
+        pauses::add_pause_commands(pipe, version, pause);
+    }
+    for (merchant_id, store_id, pincode) in batch.store_pincodes.iter().filter(|(merchant_id, _, _)| written(merchant_id)) {
+        stores::add_store_commands(pipe, version, *merchant_id, *store_id, pincode);
+    }
+    for (merchant_id, limits) in batch.capacity_limits.iter().filter(|(merchant_id, _)| written(merchant_id)) {
+        pipe.hset_multiple(capacity_key(version, *merchant_id), limits).ignore();
+    }
+}
+
+// Writes the batch to the version being built, except for the merchants a writer changed since the changes
+// set was cleared before the batch was read: their rows may be older than what the writer wrote. Returns them
+fn write_batch(con: &mut redis::Connection, version: u64, batch: &IndexBatch) -> redis::RedisResult<HashSet<i32>> {
+    let merchant_ids: Vec<i32> = batch.merchants.iter().map(|merchant| merchant.id).collect();
+
+    redis::transaction(con, &[changes_key(version)], |con, pipe| {
+        let changed: Vec<bool> = redis::cmd("SMISMEMBER").arg(changes_key(version)).arg(&merchant_ids).query(con)?;
+        let changed: HashSet<i32> = merchant_ids.iter().zip(changed).filter(|(_, changed)| *changed).map(|(merchant_id, _)| *merchant_id).collect();
+        add_batch_commands(pipe, version, batch, &changed);
+
+        Ok(pipe.query::<Option<()>>(con)?.map(|()| changed))
+    })
+}
+
+async fn build_version(db: &mut AsyncPgConnection, con: &mut redis::Connection, version: u64) -> Result<ReindexReport, utils::StoreError> {
+    use crate::schema::merchants;
+
+    let mut report = ReindexReport { version, merchants_indexed: 0, pincodes_indexed: 0 };
+    let mut last_id = 0;
+
+    // Page through the merchants by id so the table is never loaded into memory at once
+    loop {
+        let mut merchants = merchants::table
+            .filter(merchants::id.gt(last_id))
+            .order(merchants::id.asc())
+            .limit(REINDEX_BATCH_SIZE)
+            .load::<models::Merchant>(db)
+            .await?;
+
+        let Some(last) = merchants.last() else {
+            break;
+        };
+        last_id = last.id;
+
+        // Merchants written to while their batch was read are read again, until a batch gets in between writes
+        while !merchants.is_empty() {
+            con.del::<_, ()>(changes_key(version))?;
+            let batch = load_batch(db, merchants).await?;
+            let changed = write_batch(con, version, &batch)?;
+
+            report.merchants_indexed += batch.merchants.len() - changed.len();
+            report.pincodes_indexed += batch.pincode_rows.iter().filter(|row| !changed.contains(&row.merchant_id)).count();
+
+            let changed: Vec<i32> = changed.into_iter().collect();
+            merchants = if changed.is_empty() {
+                Vec::new()
+            } else {
+                merchants::table
+                    .filter(merchants::id.eq_any(&changed))
+                    .order(merchants::id.asc())
+                    .load::<models::Merchant>(db)
+                    .await?
+            };
+        }
+        renew_build(con, version)?;
+    }
+
+    Ok(report)
+}
+
+fn retire_version(con: &mut redis::Connection, version: u64) -> redis::RedisResult<()> {
+    con.zadd(RETIRED_KEY, version, OffsetDateTime::now_utc().unix_timestamp())
+}
+
+// Deletes the replaced versions whose grace period is over
+fn delete_retired_versions(con: &mut redis::Connection) -> redis::RedisResult<()> {
+    let retired_before = OffsetDateTime::now_utc().unix_timestamp() - RETIRED_GRACE_SECS as i64;
+    let versions: Vec<u64> = con.zrangebyscore(RETIRED_KEY, "-inf", retired_before)?;
+
+    for version in versions {
+        delete_version(con, version)?;
+        con.zrem::<_, _, ()>(RETIRED_KEY, version)?;
+    }
+
+    Ok(())
+}
+
+// Rebuilds the Redis serviceability index from Postgres into a new version and swaps it in once complete.
+// Returns None if another reindex is already running
+pub async fn rebuild(db: &mut AsyncPgConnection, redis_client: &redis::Client) -> Result<Option<ReindexReport>, utils::StoreError> {
+    let mut con = redis_client.get_connection()?;
+
+    let version: u64 = con.incr(VERSION_COUNTER_KEY, 1)?;
+    let acquired: Option<String> = redis::cmd("SET")
+        .arg(BUILDING_KEY)
+        .arg(version)
+        .arg("NX")
+        .arg("EX")
+        .arg(BUILD_TIMEOUT_SECS)
+        .query(&mut con)?;
+
+    if acquired.is_none() {
+        return Ok(None);
+    }
+
+    // Versions left over by a server that stopped during their grace period
+    if let Err(err) = delete_retired_versions(&mut con) {
+        eprintln!("Failed to remove replaced serviceability index versions: {:?}", err);
+    }
+
+    let report = match build_version(db, &mut con, version).await {
+        Ok(report) => report,
+        Err(err) => {
+            // Drop the half built version, readers never saw it. The lock is only released if it is still ours
+            let _ = redis::Script::new(RELEASE_BUILD_SCRIPT)
+                .key(BUILDING_KEY)
+                .arg(version)
+                .invoke::<i32>(&mut con);
+            let _ = delete_version(&mut con, version);
+            return Err(err);
+        }
+    };
+
+    let old_version = current_version(&mut con)?;
+    let swapped: i32 = redis::Script::new(SWAP_VERSION_SCRIPT)
+        .key(BUILDING_KEY)
+        .key(VERSION_KEY)
+        .arg(version)
+        .invoke(&mut con)?;
+    if swapped == 0 {
+        let _ = delete_version(&mut con, version);
+        return Err(lock_lost().into());
+    }
+
+    // Lookups that read the version before the swap may still be using the old one
+    if let Err(err) = retire_version(&mut con, old_version) {
+        eprintln!("Failed to retire serviceability index version {}: {:?}", old_version, err);
+    }
+    let redis_client = redis_client.clone();
+    rocket::tokio::spawn(async move {
+        rocket::tokio::time::sleep(Duration::from_secs(RETIRED_GRACE_SECS)).await;
+        if let Err(err) = redis_client.get_connection().and_then(|mut con| delete_retired_versions(&mut con)) {
+            eprintln!("Failed to remove serviceability index version {}: {:?}", old_version, err);
+        }
+    });
+
+    Ok(Some(report))
+}
+
+// Rebuilds the Redis serviceability index from Postgres
+#[post("/admin/reindex")]
+pub(crate) async fn reindex(redis: &State<RedisClient>, mut db: Connection<Db>) -> Json<utils::ApiResponse> {
+    match rebuild(&mut db, &redis.client).await {
+        Ok(Some(report)) => Json(utils::ApiResponse {
+            status: utils::ApiResponseStatus::Success,
+            data: json!(report).into(),
+        }),
+        Ok(None) => Json(utils::ApiResponse {
+            status: utils::ApiResponseStatus::Error,
+            data: json!({"message": "A reindex is already running"}).into(),
+        }),
+        Err(err) => {
+            eprintln!("Failed to rebuild the Redis index: {}", err);
+            Json(utils::ApiResponse {
+                status: utils::ApiResponseStatus::Error,
+                data: json!({"message": format!("Failed to rebuild the Redis index: {}", err)}).into(),
+            })
+        }
+    }
+}
+
+// Rebuilds the Redis index in the background once the server has launched.
+// Set REINDEX_ON_STARTUP=false to skip it
+pub fn stage() -> AdHoc {
+    AdHoc::on_liftoff("Redis Reindex on Startup", |rocket| Box::pin(async move {
+        dotenv().ok();
+
+        if env::var("REINDEX_ON_STARTUP").map(|value| value == "false").unwrap_or(false) {
+            return;
+        }
+
+        let (Some(db), Some(redis)) = (Db::fetch(rocket), rocket.state::<RedisClient>()) else {
+            eprintln!("Reindex skipped: database or Redis client unavailable");
+            return;
+        };
+
+        let pool = db.0.clone();
+        let redis_client = redis.client.clone();
+
+        rocket::tokio::spawn(async move {
+            let mut con = match pool.get().await {
+                Ok(con) => con,
+                Err(err) => {
+                    eprintln!("Reindex skipped, could not get a database connection: {:?}", err);
+                    return;
+                }
+            };
+
+            match rebuild(&mut con, &redis_client).await {
+                Ok(Some(report)) => println!("Rebuilt the Redis index: {:?}", report),
+                Ok(None) => println!("Reindex skipped, another reindex is already running"),
+                Err(err) => eprintln!("Failed to rebuild the Redis index: {}", err),
+            }
+        });
+    }))
+}
+
+// Commands of the pipeline with their arguments as strings, for tests checking what a write sends
+#[cfg(test)]
+pub fn pipeline_commands(pipe: &redis::Pipeline) -> Vec<Vec<String>> {
+    pipe.cmd_iter()
+        .map(|cmd| {
+            cmd.args_iter()
+                .map(|arg| match arg {
+                    redis::Arg::Simple(bytes) => String::from_utf8_lossy(bytes).to_string(),
+                    redis::Arg::Cursor => "<cursor>".to_string(),
+                })
+                .collect()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn merchant(location: Option<(f64, f64, f64)>, external_ref: Option<&str>) -> models::Merchant {
+        models::Merchant {
+            id: 7,
+            name: "Corner Store".to_string(),
+            business_category: "Grocery".to_string(),
+            phone_number: "9876543210".to_string(),
+            email: "store@example.com".to_string(),
+            latitude: location.map(|(latitude, _, _)| latitude),
+            longitude: location.map(|(_, longitude, _)| longitude),
+            delivery_radius_km: location.map(|(_, _, radius)| radius),
+            external_ref: external_ref.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn version_zero_keys_are_unprefixed() {
+        assert_eq!(pincode_key(0, "560001"), "pincodes:560001");
+        assert_eq!(pincode_key(3, "560001"), "v3:pincodes:560001");
+        assert_eq!(merchant_pincodes_key(3, 7), "v3:merchant:7:pincodes");
+        assert_eq!(changes_key(3), "v3:changes");
+    }
+
+    #[test]
+    fn keys_parse_back_for_their_version_only() {
+        assert_eq!(parse_pincode_key(3, &pincode_key(3, "560001")).as_deref(), Some("560001"));
+        assert_eq!(parse_pincode_key(3, &pincode_key(4, "560001")), None);
+        assert_eq!(parse_merchant_key(3, &merchant_key(3, 7)), Some(7));
+        assert_eq!(parse_merchant_key(3, &merchant_pincodes_key(3, 7)), None);
+        assert_eq!(parse_stores_key(0, &stores_key(0, "560001")).as_deref(), Some("560001"));
+    }
+
+    #[test]
+    fn every_key_of_a_version_is_matched_by_its_patterns() {
+        let patterns = version_patterns(3);
+        let matches = |key: &str| {
+            patterns.iter().any(|pattern| match pattern.strip_suffix('*') {
+                Some(start) => key.starts_with(start),
+                None => key == pattern,
+            })
+        };
+
+        for key in [pincode_key(3, "560001"), merchant_key(3, 7), merchant_pincodes_key(3, 7), rules_key(3, 560), changes_key(3), temp_key(3), radius_key(3)] {
+            assert!(matches(&key), "{} is not matched", key);
+        }
+    }
+
+    #[test]
+    fn store_and_rule_members_parse_back() {
+        assert_eq!(parse_store_member(&store_member(7, 12)), Some((7, 12)));
+        assert_eq!(parse_store_member("7"), None);
+
+        let rule = models::MerchantPincodeRule { merchant_id: 7, rule_type: "range".to_string(), value: "560001-560100".to_string() };
+        let parsed = parse_rule_member(&rule_member(&rule)).expect("the member should parse");
+        assert_eq!((parsed.merchant_id, parsed.rule_type, parsed.value), (7, "range".to_string(), "560001-560100".to_string()));
+    }
+
+    #[test]
+    fn merchants_round_trip_through_their_hash() {
+        for merchant in [merchant(None, None), merchant(Some((12.97, 77.59, 5.0)), Some("ERP-7"))] {
+            let fields = merchant_fields(&merchant).into_iter().map(|(name, value)| (name.to_string(), value)).collect();
+
+            assert_eq!(merchant_from_fields(fields), Some(merchant));
+        }
+    }
+
+    #[test]
+    fn incomplete_merchant_hashes_are_rejected() {
+        let mut fields: HashMap<String, String> = merchant_fields(&merchant(None, None)).into_iter().map(|(name, value)| (name.to_string(), value)).collect();
+        fields.insert("latitude".to_string(), "north".to_string());
+        assert_eq!(merchant_from_fields(fields.clone()), None);
+
+        fields.remove("latitude");
+        fields.remove("name");
+        assert_eq!(merchant_from_fields(fields), None);
+    }
+}
@@ -0,0 +1,834 @@
+use std::collections::{BTreeSet, HashMap};
+use std::env;
+use std::fs::File;
+use std::io::Read;
+
+use rocket::form::Form;
+use rocket::serde::json::{Json, json};
+use rocket::serde::Serialize;
+use rocket::State;
+use rocket_db_pools::Connection;
+use rocket_db_pools::diesel::{AsyncPgConnection, prelude::*};
+use rocket_db_pools::diesel::scoped_futures::ScopedFutureExt;
+
+use crate::directory::{find_column, header_key};
+use crate::validation::FieldError;
+use crate::{directory, exclusions, export, index, models, stores, utils, validation, Db, RedisClient, Upload};
+
+// Rows added per transaction when the file isn't added all or nothing
+const INSERT_BATCH_SIZE: usize = 500;
+
+// Rows per INSERT statement, keeping the bind parameters under the PostgreSQL limit of 65535
+const MERCHANT_CHUNK_SIZE: usize = 1000;
+const PINCODE_CHUNK_SIZE: usize = 10000;
+
+// Columns of the merchant CSV, found by their header
+struct MerchantColumns {
+    name: usize,
+    business_category: usize,
+    phone_number: usize,
+    email: usize,
+    pincodes: usize,
+    latitude: Option<usize>,
+    longitude: Option<usize>,
+    delivery_radius_km: Option<usize>,
+    external_ref: Option<usize>,
+}
+
+// Files without recognizable headers are read in the original column order: name, business category,
+// phone number, email and comma separated pincodes
+const LEGACY_COLUMNS: MerchantColumns = MerchantColumns {
+    name: 0,
+    business_category: 1,
+    phone_number: 2,
+    email: 3,
+    pincodes: 4,
+    latitude: None,
+    longitude: None,
+    delivery_radius_km: None,
+    external_ref: None,
+};
+
+// How an upsert treats the pincodes of a merchant that already exists: replace drops the pincodes missing
+// from the row, merge adds the row's pincodes to the ones already serviced
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PincodeMode {
+    Replace,
+    Merge,
+}
+
+impl PincodeMode {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "replace" => Some(PincodeMode::Replace),
+            "merge" => Some(PincodeMode::Merge),
+            _ => None,
+        }
+    }
+}
+
+// Merchant read from a row of the CSV, line is the line number in the file. existing_id is the merchant
+// with the same external reference, updated instead of inserted when uploading with upsert=true
+#[derive(Debug, Clone)]
+pub struct ParsedRow {
+    pub line: u64,
+    pub merchant: utils::MerchantData,
+    pub existing_id: Option<i32>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RowAction {
+    Inserted,
+    Updated,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AcceptedRow {
+    pub line: u64,
+    pub merchant_id: i32,
+    pub name: String,
+    pub action: RowAction,
+    // Pincodes of the row the updated merchant excluded, they are skipped like through PUT /merchant/serviceability
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub pincodes_excluded: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RejectedRow {
+    pub line: u64,
+    pub errors: Vec<FieldError>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct IngestReport {
+    pub all_or_nothing: bool,
+    pub upsert: bool,
+    pub accepted: Vec<AcceptedRow>,
+    pub rejected: Vec<RejectedRow>,
+}
+
+fn map_columns(headers: &csv::StringRecord) -> Result<MerchantColumns, String> {
+    let column = |names: &[&str]| find_column(headers, names);
+    let columns = (
+        column(&["name", "merchantname", "merchant"]),
+        column(&["businesscategory", "category"]),
+        column(&["phonenumber", "phone", "mobile", "contactnumber"]),
+        column(&["email", "emailid", "emailaddress"]),
+        column(&["pincodesserviced", "pincodes", "pincode"]),
+    );
+
+    match columns {
+        (Some(name), Some(business_category), Some(phone_number), Some(email), Some(pincodes)) => Ok(MerchantColumns {
+            name,
+            business_category,
+            phone_number,
+            email,
+            pincodes,
+            latitude: column(&["latitude", "lat"]),
+            longitude: column(&["longitude", "long", "lng"]),
+            delivery_radius_km: column(&["deliveryradiuskm", "deliveryradius", "radiuskm"]),
+            external_ref: column(&["externalref", "externalreference", "sellerid", "gstin", "merchantref"]),
+        }),
+        (None, None, None, None, None) if headers.len() >= 5 => Ok(LEGACY_COLUMNS),
+        _ => {
+            let found: Vec<String> = headers.iter().map(header_key).collect();
+            Err(format!(
+                "The CSV file must have name, business_category, phone_number, email and pincodes_serviced columns, found {}",
+                found.join(", ")
+            ))
+        }
+    }
+}
+
+fn parse_row(record: &csv::StringRecord, columns: &MerchantColumns) -> Result<utils::MerchantData, Vec<FieldError>> {
+    let mut errors = Vec::new();
+
+    let mut field = |index: usize, name: &str| match record.get(index) {
+        Some(value) => value.trim().to_string(),
+        None => {
+            errors.push(FieldError::new(name, "column is missing from the row".to_string()));
+            String::new()
+        }
+    };
+    let name = field(columns.name, "name");
+    let business_category = field(columns.business_category, "business_category");
+    let phone_number = field(columns.phone_number, "contact.phone_number");
+    let email = field(columns.email, "contact.email");
+    let pincodes = field(columns.pincodes, "pincodes_serviced");
+
+    let mut number = |index: Option<usize>, name: &str| -> Option<f64> {
+        let value = index.and_then(|index| record.get(index)).map(str::trim).filter(|value| !value.is_empty())?;
+        match value.parse() {
+            Ok(number) => Some(number),
+            Err(_) => {
+                errors.push(FieldError::new(name, format!("{} is not a number", value)));
+                None
+            }
+        }
+    };
+    let latitude = number(columns.latitude, "latitude");
+    let longitude = number(columns.longitude, "longitude");
+    let delivery_radius_km = number(columns.delivery_radius_km, "delivery_radius_km");
+    let external_ref = columns.external_ref.and_then(|index| record.get(index)).map(str::to_string);
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    let merchant = utils::MerchantData {
+        id: 0,
+        name,
+        business_category,
+        contact: utils::ContactInformation { phone_number, email },
+        pincodes_serviced: utils::normalize_pincodes(pincodes.split(',').map(|s| s.to_string()).collect()),
+        latitude,
+        longitude,
+        delivery_radius_km,
+        external_ref: utils::normalize_external_ref(external_ref),
+    };
+    validation::validate_merchant(&merchant)?;
+
+    Ok(merchant)
+}
+
+// Reads the merchants of a CSV file. Rows that can't be read or fail validation are returned as rejected
+pub fn parse_merchants<R: Read>(file: R) -> Result<(Vec<ParsedRow>, Vec<RejectedRow>), String> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(true)
+        .flexible(true)
+        .from_reader(file);
+
+    let headers = reader
+        .headers()
+        .map_err(|err| format!("Failed to read the CSV headers: {}", err))?
+        .clone();
+    let columns = map_columns(&headers)?;
+
+    let mut parsed = Vec::new();
+    let mut rejected = Vec::new();
+    for record in reader.records() {
+        let record = match record {
+            Ok(record) => record,
+            Err(err) => {
+                let line = err.position().map(|position| position.line()).unwrap_or_default();
+                rejected.push(RejectedRow { line, errors: vec![FieldError::new("row", format!("{}", err))] });
+                continue;
+            }
+        };
+        let line = record.position().map(|position| position.line()).unwrap_or_default();
+        if record.iter().all(|value| value.trim().is_empty()) {
+            continue;
+        }
+        if record.get(0).map(str::trim) == Some(export::INCOMPLETE_MARKER) {
+            return Err(format!("The file is an export that stopped early at line {}, export the merchants again", line));
+        }
+
+        match parse_row(&record, &columns) {
+            Ok(merchant) => parsed.push(ParsedRow { line, merchant, existing_id: None }),
+            Err(errors) => rejected.push(RejectedRow { line, errors }),
+        }
+    }
+
+    Ok((parsed, rejected))
+}
+
+// Moves the rows with pincodes missing from the pincode directory to the rejected rows
+async fn reject_unknown_pincodes(db: &mut AsyncPgConnection, rows: Vec<ParsedRow>, rejected: &mut Vec<RejectedRow>) -> QueryResult<Vec<ParsedRow>> {
+    let pincodes: Vec<String> = rows
+        .iter()
+        .flat_map(|row| row.merchant.pincodes_serviced.iter().cloned())
+        .collect::<BTreeSet<String>>()
+        .into_iter()
+        .collect();
+    let unknown: BTreeSet<String> = directory::unknown_pincodes(db, &pincodes).await?.into_iter().collect();
+    if unknown.is_empty() {
+        return Ok(rows);
+    }
+
+    let (known, with_unknown): (Vec<ParsedRow>, Vec<ParsedRow>) = rows
+        .into_iter()
+        .partition(|row| row.merchant.pincodes_serviced.iter().all(|pincode| !unknown.contains(pincode)));
+    rejected.extend(with_unknown.into_iter().map(|row| {
+        let errors = row
+            .merchant
+            .pincodes_serviced
+            .iter()
+            .filter(|pincode| unknown.contains(*pincode))
+            .map(|pincode| FieldError::new("pincodes_serviced", format!("{} is not in the pincode directory", pincode)))
+            .collect();
+        RejectedRow { line: row.line, errors }
+    }));
+
+    Ok(known)
+}
+
+// Rejects the rows repeating an external reference of an earlier row, and matches the others with the
+// merchants having the same reference. With upsert the matched rows update their merchant, otherwise
+// they are rejected as the reference is taken
+async fn match_external_refs(db: &mut AsyncPgConnection, rows: Vec<ParsedRow>, rejected: &mut Vec<RejectedRow>, upsert: bool) -> QueryResult<Vec<ParsedRow>> {
+    let mut first_lines: HashMap<String, u64> = HashMap::new();
+    let mut unique_rows = Vec::new();
+    for row in rows {
+        let Some(external_ref) = row.merchant.external_ref.clone() else {
+            unique_rows.push(row);
+            continue;
+        };
+        match first_lines.get(&external_ref) {
+            Some(first_line) => rejected.push(RejectedRow {
+                line: row.line,
+                errors: vec![FieldError::new("external_ref", format!("{} is already used on line {}", external_ref, first_line))],
+            }),
+            None => {
+                first_lines.insert(external_ref, row.line);
+                unique_rows.push(row);
+            }
+        }
+    }
+
+    let external_refs: Vec<String> = first_lines.into_keys().collect();
+    if external_refs.is_empty() {
+        return Ok(unique_rows);
+    }
+    let existing = crate::find_external_refs(db, &external_refs).await?;
+
+    let mut matched = Vec::new();
+    for mut row in unique_rows {
+        let existing_id = row.merchant.external_ref.as_ref().and_then(|external_ref| existing.get(external_ref));
+        match existing_id {
+            Some(existing_id) if !upsert => rejected.push(RejectedRow {
+                line: row.line,
+                errors: vec![FieldError::new(
+                    "external_ref",
+                    format!("{} is already used by merchant {}, upload with upsert=true to update it", row.merchant.external_ref.as_deref().unwrap_or_default(), existing_id),
+                )],
+            }),
+            _ => {
+                if let Some(existing_id) = existing_id {
+                    row.merchant.id = *existing_id;
+                    row.existing_id = Some(*existing_id);
+                }
+                matched.push(row);
+            }
+        }
+    }
+
+    Ok(matched)
+}
+
+fn store_merchants(redis_client: &redis::Client, rows: &[&ParsedRow]) -> redis::RedisResult<()> {
+    let merchant_ids: Vec<i32> = rows.iter().map(|row| row.merchant.id).collect();
+    let mut con = redis_client.get_connection()?;
+    index::write(&mut con, &merchant_ids, |_, pipe, version| {
+        for row in rows {
+            let merchant: models::Merchant = row.merchant.clone().into();
+            index::add_merchant_commands(pipe, version, &merchant);
+            for pincode in &row.merchant.pincodes_serviced {
+                pipe.sadd(index::pincode_key(version, pincode), merchant.id).ignore();
+                pipe.sadd(index::merchant_pincodes_key(version, merchant.id), pincode).ignore();
+            }
+        }
+        Ok(())
+    })
+}
+
+// Inserts the new merchants and their pincodes with consecutive IDs after the highest one, in multi-row inserts
+async fn insert_merchants(db: &mut AsyncPgConnection, rows: &mut [&mut ParsedRow]) -> QueryResult<()> {
+    use crate::schema::{merchant_pincodes, merchants};
+
+    if rows.is_empty() {
+        return Ok(());
+    }
+
+    let first_id = crate::generate_merchant_id(db).await;
+    for (offset, row) in rows.iter_mut().enumerate() {
+        row.merchant.id = first_id + offset as i32;
+    }
+
+    let new_merchants: Vec<models::Merchant> = rows.iter().map(|row| row.merchant.clone().into()).collect();
+    for chunk in new_merchants.chunks(MERCHANT_CHUNK_SIZE) {
+        diesel::insert_into(merchants::table).values(chunk).execute(db).await?;
+    }
+
+    let pincodes: Vec<models::MerchantPincode> = rows
+        .iter()
+        .flat_map(|row| {
+            row.merchant.pincodes_serviced.iter().map(|pincode| models::MerchantPincode {
+                merchant_id: row.merchant.id,
+                pincode: pincode.clone(),
+            })
+        })
+        .collect();
+    for chunk in pincodes.chunks(PINCODE_CHUNK_SIZE) {
+        diesel::insert_into(merchant_pincodes::table).values(chunk).on_conflict_do_nothing().execute(db).await?;
+    }
+
+    Ok(())
+}
+
+// Changes to the index of a merchant updated by its row
+#[derive(Debug)]
+struct MerchantUpdate {
+    merchant: models::Merchant,
+    added: Vec<String>,
+    unserviced: Vec<String>,
+    removed: Vec<String>,
+    excluded: Vec<String>,
+}
+
+// Updates the details of the merchant matched by the row's external reference and replaces or merges its
+// pincodes. The location is left unchanged when the row has none, like PUT /merchant/<merchant_id>.
+// Pincodes the merchant excluded are skipped
+async fn update_merchant(db: &mut AsyncPgConnection, row: &ParsedRow, pincode_mode: PincodeMode) -> QueryResult<MerchantUpdate> {
+    use crate::schema::{merchant_pincodes, merchants};
+
+    let data = &row.merchant;
+    let merchant_id = data.id;
+    let update_data = models::UpdateMerchantData {
+        name: data.name.clone(),
+        business_category: data.business_category.clone(),
+        phone_number: data.contact.phone_number.clone(),
+        email: data.contact.email.clone(),
+        latitude: data.latitude,
+        longitude: data.longitude,
+        delivery_radius_km: data.delivery_radius_km,
+        external_ref: data.external_ref.clone(),
+    };
+    let merchant = diesel::update(merchants::table.find(merchant_id))
+        .set(&update_data)
+        .returning(models::Merchant::as_returning())
+        .get_result(db)
+        .await?;
+
+    let excluded = exclusions::excluded_pincodes(db, merchant_id, &data.pincodes_serviced).await?;
+    let pincodes: Vec<String> = data.pincodes_serviced.iter().filter(|code| !excluded.contains(*code)).cloned().collect();
+
+    let removed: Vec<String> = match pincode_mode {
+        PincodeMode::Merge => Vec::new(),
+        PincodeMode::Replace => {
+            diesel::delete(
+                merchant_pincodes::table
+                    .filter(merchant_pincodes::merchant_id.eq(merchant_id))
+                    .filter(merchant_pincodes::pincode.ne_all(&pincodes)),
+            )
+            .returning(merchant_pincodes::pincode)
+            .get_results(db)
+            .await?
+        }
+    };
+    let added = crate::insert_merchant_pincodes(db, merchant_id, &pincodes).await?;
+    // Pincodes still serviced by one of the merchant's stores stay in the index, only their terms go
+    let unserviced = stores::unserviced_pincodes(db, merchant_id, &removed).await?;
+
+    Ok(MerchantUpdate { merchant, added, unserviced, removed, excluded: excluded.into_iter().collect() })
+}
+
+// Inserts the new merchants of the rows and updates the ones matched by external reference in Postgres
+async fn write_merchants(db: &mut AsyncPgConnection, rows: &mut [ParsedRow], pincode_mode: PincodeMode) -> QueryResult<Vec<MerchantUpdate>> {
+    let (mut new_rows, existing_rows): (Vec<&mut ParsedRow>, Vec<&mut ParsedRow>) = rows.iter_mut().partition(|row| row.existing_id.is_none());
+
+    insert_merchants(db, &mut new_rows).await?;
+    let mut updates = Vec::new();
+    for row in existing_rows {
+        updates.push(update_merchant(db, row, pincode_mode).await?);
+    }
+
+    Ok(updates)
+}
+
+// Merchants written to Postgres by an ingest, to add to Redis once every transaction they were written in has
+// committed. Written any earlier, a rolled back row would leave its merchant in the index under an ID that
+// a retry hands to another row
+#[derive(Debug, Default)]
+pub struct PendingIndex {
+    new_rows: Vec<ParsedRow>,
+    updates: Vec<MerchantUpdate>,
+}
+
+impl PendingIndex {
+    fn add(&mut self, rows: &[ParsedRow], updates: Vec<MerchantUpdate>) {
+        self.new_rows.extend(rows.iter().filter(|row| row.existing_id.is_none()).cloned());
+        self.updates.extend(updates);
+    }
+
+    fn write(&self, redis_client: &redis::Client) -> redis::RedisResult<()> {
+        let new_rows: Vec<&ParsedRow> = self.new_rows.iter().collect();
+        for chunk in new_rows.chunks(INSERT_BATCH_SIZE) {
+            store_merchants(redis_client, chunk)?;
+        }
+
+        for update in &self.updates {
+            let merchant_id = update.merchant.id;
+            crate::store_merchant_details(redis_client, &update.merchant)?;
+            crate::store_serviceability(redis_client, merchant_id, &update.added)?;
+            crate::delete_merchant_serviceability(redis_client, merchant_id, &update.unserviced)?;
+            crate::delete_delivery_terms(redis_client, merchant_id, &update.removed)?;
+        }
+
+        Ok(())
+    }
+
+    // Adds the merchants to Redis. Postgres stays the source of truth when Redis fails: the rows are
+    // accepted and the reconciliation task repairs the index
+    pub fn apply(&self, redis_client: &redis::Client) {
+        if let Err(err) = self.write(redis_client) {
+            eprintln!(
+                "Failed to index {} merchants in Redis, the next reconciliation will add them: {}",
+                self.new_rows.len() + self.updates.len(),
+                err
+            );
+        }
+    }
+}
+
+// Writes the rows in one transaction, the merchants to index are added to pending once it commits
+async fn write_rows(db: &mut AsyncPgConnection, pending: &mut PendingIndex, rows: Vec<ParsedRow>, pincode_mode: PincodeMode) -> QueryResult<Vec<AcceptedRow>> {
+    let (rows, updates) = db.transaction::<_, diesel::result::Error, _>(|conn| async move {
+        let mut rows = rows;
+        let updates = write_merchants(conn, &mut rows, pincode_mode).await?;
+        Ok((rows, updates))
+    }.scope_boxed()).await?;
+    let accepted = accepted_rows(&rows, &updates);
+    pending.add(&rows, updates);
+
+    Ok(accepted)
+}
+
+// Writes a batch of merchants in one transaction. If the batch fails, its rows are retried in a transaction
+// each so that only the failing rows are rejected
+async fn write_batch(db: &mut AsyncPgConnection, pending: &mut PendingIndex, rows: Vec<ParsedRow>, pincode_mode: PincodeMode) -> (Vec<AcceptedRow>, Vec<RejectedRow>) {
+    match write_rows(db, pending, rows.clone(), pincode_mode).await {
+        Ok(accepted) => return (accepted, Vec::new()),
+        Err(err) if rows.len() > 1 => eprintln!("Failed to add a batch of {} merchants, adding them one by one: {}", rows.len(), err),
+        Err(err) => {
+            eprintln!("Failed to add the merchant on line {}: {}", rows[0].line, err);
+            return (Vec::new(), vec![RejectedRow { line: rows[0].line, errors: vec![FieldError::new("row", format!("{}", err))] }]);
+        }
+    }
+
+    let mut accepted = Vec::new();
+    let mut rejected = Vec::new();
+    for row in rows {
+        let line = row.line;
+        match write_rows(db, pending, vec![row], pincode_mode).await {
+            Ok(rows) => accepted.extend(rows),
+            Err(err) => {
+                eprintln!("Failed to add the merchant on line {}: {}", line, err);
+                rejected.push(RejectedRow { line, errors: vec![FieldError::new("row", format!("{}", err))] });
+            }
+        }
+    }
+
+    (accepted, rejected)
+}
+
+fn accepted_rows(rows: &[ParsedRow], updates: &[MerchantUpdate]) -> Vec<AcceptedRow> {
+    let excluded: HashMap<i32, &Vec<String>> = updates.iter().map(|update| (update.merchant.id, &update.excluded)).collect();
+
+    rows.iter()
+        .map(|row| AcceptedRow {
+            line: row.line,
+            merchant_id: row.merchant.id,
+            name: row.merchant.name.clone(),
+            action: if row.existing_id.is_some() { RowAction::Updated } else { RowAction::Inserted },
+            pincodes_excluded: excluded.get(&row.merchant.id).map(|pincodes| pincodes.to_vec()).unwrap_or_default(),
+        })
+        .collect()
+}
+
+// With all_or_nothing, a file with a rejected row adds none of its merchants
+fn refuse_all(mut rejected: Vec<RejectedRow>, upsert: bool) -> IngestReport {
+    rejected.sort_by_key(|row| row.line);
+    IngestReport { all_or_nothing: true, upsert, accepted: Vec::new(), rejected }
+}
+
+// Adds the parsed merchants to Postgres. Rows are added in batches with a transaction each and a failing row is rejected,
+// unless all_or_nothing is set: then nothing is added if any row was rejected, and the rows are added in one transaction.
+// With an upsert pincode mode, rows whose external reference belongs to a merchant update that merchant.
+// The merchants are returned to index in Redis once the caller has committed, as ingest may run inside a transaction
+pub async fn ingest(db: &mut AsyncPgConnection, rows: Vec<ParsedRow>, mut rejected: Vec<RejectedRow>, allow_unknown_pincodes: bool, all_or_nothing: bool, upsert: Option<PincodeMode>) -> Result<(IngestReport, PendingIndex), utils::StoreError> {
+    let rows = if allow_unknown_pincodes {
+        rows
+    } else {
+        reject_unknown_pincodes(db, rows, &mut rejected).await?
+    };
+    let rows = match_external_refs(db, rows, &mut rejected, upsert.is_some()).await?;
+    let pincode_mode = upsert.unwrap_or(PincodeMode::Merge);
+    let mut pending = PendingIndex::default();
+
+    if all_or_nothing {
+        if !rejected.is_empty() {
+            return Ok((refuse_all(rejected, upsert.is_some()), pending));
+        }
+
+        let accepted = write_rows(db, &mut pending, rows, pincode_mode).await?;
+
+        return Ok((IngestReport { all_or_nothing, upsert: upsert.is_some(), accepted, rejected }, pending));
+    }
+
+    let mut accepted = Vec::new();
+    for batch in rows.chunks(INSERT_BATCH_SIZE) {
+        let (batch_accepted, batch_rejected) = write_batch(db, &mut pending, batch.to_vec(), pincode_mode).await;
+        accepted.extend(batch_accepted);
+        rejected.extend(batch_rejected);
+    }
+    rejected.sort_by_key(|row| row.line);
+
+    Ok((IngestReport { all_or_nothing, upsert: upsert.is_some(), accepted, rejected }, pending))
+}
+
+// Onboards the merchants of a CSV file (csv_file form field). Columns are matched by their header, every row
+// is validated on its own and the response lists the accepted rows with their IDs and the rejected rows with the reasons.
+// With all_or_nothing=true no merchant is added unless every row is valid, and the file is added in one transaction.
+// With upsert=true rows whose external_ref belongs to a merchant update it, and pincode_mode=replace (default)
+// or merge decides whether its pincodes are replaced by the row's or extended with them
+#[post("/upload_csv?<allow_unknown_pincodes>&<all_or_nothing>&<upsert>&<pincode_mode>", data = "<form>")]
+pub(crate) async fn upload_csv(redis: &State<RedisClient>, mut db: Connection<Db>, mut form: Form<Upload<'_>>, allow_unknown_pincodes: Option<bool>, all_or_nothing: Option<bool>, upsert: Option<bool>, pincode_mode: Option<&str>) -> Json<utils::ApiResponse> {
+    let pincode_mode = match pincode_mode.map(PincodeMode::parse) {
+        None => PincodeMode::Replace,
+        Some(Some(pincode_mode)) => pincode_mode,
+        Some(None) => {
+            return Json(validation::error_response(vec![FieldError::new("pincode_mode", "must be replace or merge".to_string())]));
+        }
+    };
+    let upsert = upsert.unwrap_or(false).then_some(pincode_mode);
+
+    let filepath = format!("{}/merchants-{}.csv", env::temp_dir().to_str().unwrap(), rand::random::<u64>());
+    if let Err(err) = form.upload.persist_to(&filepath).await {
+        eprintln!("Failed to persist the merchant upload: {:?}", err);
+        return Json(utils::ApiResponse {
+            status: utils::ApiResponseStatus::Error,
+            data: json!({"message": "Failed to read the uploaded file"}).into(),
+        });
+    }
+
+    let parsed = File::open(&filepath)
+        .map_err(|err| format!("Failed to read the CSV file: {}", err))
+        .and_then(parse_merchants);
+    let _ = std::fs::remove_file(&filepath);
+    let (rows, rejected) = match parsed {
+        Ok(parsed) => parsed,
+        Err(message) => {
+            return Json(utils::ApiResponse {
+                status: utils::ApiResponseStatus::Error,
+                data: json!({"message": message}).into(),
+            });
+        }
+    };
+
+    match ingest(&mut db, rows, rejected, allow_unknown_pincodes.unwrap_or(false), all_or_nothing.unwrap_or(false), upsert).await {
+        Ok((report, pending)) => {
+            pending.apply(&redis.client);
+            let merchant_ids: Vec<i32> = report.accepted.iter().map(|row| row.merchant_id).collect();
+            let updated = report.accepted.iter().filter(|row| matches!(row.action, RowAction::Updated)).count();
+            let written = match updated {
+                0 => format!("{} merchants added", report.accepted.len()),
+                updated => format!("{} merchants added, {} updated", report.accepted.len() - updated, updated),
+            };
+            let (status, message) = match (report.accepted.len(), report.rejected.len()) {
+                (0, 0) => (utils::ApiResponseStatus::Error, "No merchants added".to_string()),
+                (0, rejected) => (utils::ApiResponseStatus::Error, format!("No merchants added, {} rows were rejected", rejected)),
+                (_, 0) => (utils::ApiResponseStatus::Success, format!("{} successfully", written)),
+                (_, rejected) => (utils::ApiResponseStatus::Success, format!("{}, {} rows were rejected", written, rejected)),
+            };
+
+            Json(utils::ApiResponse {
+                status,
+                data: json!({
+                    "merchant_ids": merchant_ids,
+                    "accepted": report.accepted,
+                    "rejected": report.rejected,
+                    "all_or_nothing": report.all_or_nothing,
+                    "upsert": report.upsert,
+                    "message": message
+                }).into(),
+            })
+        }
+        Err(err) => {
+            eprintln!("Failed to add the merchants of the CSV file: {}", err);
+            Json(utils::ApiResponse {
+                status: utils::ApiResponseStatus::Error,
+                data: json!({"message": format!("Failed to add the merchants: {}", err)}).into(),
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(line: &str) -> csv::StringRecord {
+        csv::StringRecord::from(line.split(',').collect::<Vec<&str>>())
+    }
+
+    fn parse(data: &str) -> (Vec<ParsedRow>, Vec<RejectedRow>) {
+        parse_merchants(data.as_bytes()).expect("the file should be readable")
+    }
+
+    fn error_fields(row: &RejectedRow) -> Vec<&str> {
+        row.errors.iter().map(|error| error.field.as_str()).collect()
+    }
+
+    #[test]
+    fn map_columns_matches_headers_in_any_order_and_case() {
+        let columns = map_columns(&headers("Email,Pincodes Serviced,Merchant_Name,phone,Category,Seller ID,lat,lng,Delivery Radius (km)")).unwrap();
+
+        assert_eq!(columns.name, 2);
+        assert_eq!(columns.business_category, 4);
+        assert_eq!(columns.phone_number, 3);
+        assert_eq!(columns.email, 0);
+        assert_eq!(columns.pincodes, 1);
+        assert_eq!(columns.external_ref, Some(5));
+        assert_eq!(columns.latitude, Some(6));
+        assert_eq!(columns.longitude, Some(7));
+        assert_eq!(columns.delivery_radius_km, Some(8));
+    }
+
+    #[test]
+    fn map_columns_falls_back_to_the_legacy_order() {
+        let columns = map_columns(&headers("a,b,c,d,e")).unwrap();
+
+        assert_eq!((columns.name, columns.business_category, columns.phone_number, columns.email, columns.pincodes), (0, 1, 2, 3, 4));
+        assert_eq!(columns.latitude, None);
+        assert_eq!(columns.external_ref, None);
+    }
+
+    #[test]
+    fn map_columns_refuses_partial_headers() {
+        let err = map_columns(&headers("name,category,phone,something,else")).err().unwrap();
+
+        assert!(err.contains("found name, category, phone, something, else"), "{}", err);
+        assert!(map_columns(&headers("a,b,c")).is_err());
+    }
+
+    #[test]
+    fn parse_merchants_reads_valid_rows() {
+        let (rows, rejected) = parse(
+            "name,business_category,phone_number,email,pincodes_serviced,external_ref\n\
+             Fresh Mart,Grocery,9876543210,owner@freshmart.in,\"560002, 560001,560001\", SELLER-1 \n",
+        );
+
+        assert!(rejected.is_empty());
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].line, 2);
+        assert_eq!(rows[0].existing_id, None);
+        assert_eq!(rows[0].merchant.name, "Fresh Mart");
+        assert_eq!(rows[0].merchant.pincodes_serviced, vec!["560001", "560002"]);
+        assert_eq!(rows[0].merchant.external_ref.as_deref(), Some("SELLER-1"));
+    }
+
+    #[test]
+    fn parse_merchants_reads_legacy_files_by_position() {
+        let (rows, rejected) = parse("seller,type,contact,mail,areas\nFresh Mart,Grocery,9876543210,owner@freshmart.in,560001\n");
+
+        assert!(rejected.is_empty());
+        assert_eq!(rows[0].merchant.business_category, "Grocery");
+        assert_eq!(rows[0].merchant.contact.email, "owner@freshmart.in");
+    }
+
+    #[test]
+    fn parse_merchants_rejects_short_rows() {
+        let (rows, rejected) = parse(
+            "name,business_category,phone_number,email,pincodes_serviced\n\
+             Fresh Mart,Grocery,9876543210\n",
+        );
+
+        assert!(rows.is_empty());
+        assert_eq!(rejected.len(), 1);
+        assert_eq!(rejected[0].line, 2);
+        assert_eq!(error_fields(&rejected[0]), vec!["contact.email", "pincodes_serviced"]);
+        assert!(rejected[0].errors.iter().all(|error| error.message == "column is missing from the row"));
+    }
+
+    #[test]
+    fn parse_merchants_rejects_invalid_rows_on_their_own() {
+        let (rows, rejected) = parse(
+            "name,business_category,phone_number,email,pincodes_serviced,latitude,longitude,delivery_radius_km\n\
+             Fresh Mart,Grocery,9876543210,owner@freshmart.in,560001,,,\n\
+             ,Grocery,12345,not-an-email,056001,,,\n\
+             Corner Store,Grocery,9876543210,corner@store.in,560001,north,77.59,5\n\
+             Daily Needs,Grocery,+919876543210,daily@needs.in,560001,12.97,77.59,5\n",
+        );
+
+        assert_eq!(rows.iter().map(|row| row.line).collect::<Vec<u64>>(), vec![2, 5]);
+        assert_eq!(rows[1].merchant.delivery_radius_km, Some(5.0));
+        assert_eq!(rejected.len(), 2);
+        assert_eq!(rejected[0].line, 3);
+        assert_eq!(error_fields(&rejected[0]), vec!["name", "contact.phone_number", "contact.email", "pincodes_serviced"]);
+        assert_eq!(rejected[1].line, 4);
+        assert_eq!(error_fields(&rejected[1]), vec!["latitude"]);
+    }
+
+    #[test]
+    fn parse_merchants_refuses_incomplete_exports() {
+        let err = parse_merchants(
+            "id,name,business_category,phone_number,email,pincodes_serviced\n\
+             1,Fresh Mart,Grocery,9876543210,owner@freshmart.in,560001\n\
+             #export_incomplete,Failed to read the merchants after 1\n"
+                .as_bytes(),
+        )
+        .err()
+        .unwrap();
+
+        assert!(err.contains("stopped early at line 3"), "{}", err);
+    }
+
+    #[test]
+    fn accepted_rows_list_the_pincodes_updated_merchants_excluded() {
+        let (mut rows, _) = parse(
+            "name,business_category,phone_number,email,pincodes_serviced,external_ref\n\
+             Fresh Mart,Grocery,9876543210,owner@freshmart.in,\"560001,560002\",SELLER-1\n\
+             Sharma Stores,Grocery,9876543211,sharma@stores.in,560003,SELLER-2\n",
+        );
+        rows[0].merchant.id = 7;
+        rows[0].existing_id = Some(7);
+        rows[1].merchant.id = 8;
+
+        let update = MerchantUpdate {
+            merchant: models::Merchant {
+                id: 7,
+                name: "Fresh Mart".to_string(),
+                business_category: "Grocery".to_string(),
+                phone_number: "9876543210".to_string(),
+                email: "owner@freshmart.in".to_string(),
+                latitude: None,
+                longitude: None,
+                delivery_radius_km: None,
+                external_ref: Some("SELLER-1".to_string()),
+            },
+            added: vec!["560001".to_string()],
+            unserviced: Vec::new(),
+            removed: Vec::new(),
+            excluded: vec!["560002".to_string()],
+        };
+
+        let accepted = accepted_rows(&rows, &[update]);
+        assert_eq!(accepted[0].pincodes_excluded, vec!["560002"]);
+        assert!(matches!(accepted[0].action, RowAction::Updated));
+        assert!(accepted[1].pincodes_excluded.is_empty());
+        assert!(matches!(accepted[1].action, RowAction::Inserted));
+    }
+
+    #[test]
+    fn refuse_all_reports_every_rejected_row_and_accepts_none() {
+        let rejected = vec![
+            RejectedRow { line: 7, errors: vec![FieldError::new("name", "must not be empty".to_string())] },
+            RejectedRow { line: 3, errors: vec![FieldError::new("pincodes_serviced", "560001 is not in the pincode directory".to_string())] },
+        ];
+
+        let report = refuse_all(rejected, true);
+
+        assert!(report.all_or_nothing);
+        assert!(report.upsert);
+        assert!(report.accepted.is_empty());
+        assert_eq!(report.rejected.iter().map(|row| row.line).collect::<Vec<u64>>(), vec![3, 7]);
+    }
+
+    #[test]
+    fn pincode_mode_parses_case_insensitively() {
+        assert_eq!(PincodeMode::parse(" Replace "), Some(PincodeMode::Replace));
+        assert_eq!(PincodeMode::parse("MERGE"), Some(PincodeMode::Merge));
+        assert_eq!(PincodeMode::parse("append"), None);
+    }
+}
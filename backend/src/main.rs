@@ -1,7 +1,8 @@
 #[macro_use] extern crate rocket;
 
 use rocket_db_pools::{Database, Connection};
-use rocket_db_pools::diesel::{ PgPool, prelude::*};
+use rocket_db_pools::diesel::{ PgPool, AsyncPgConnection, prelude::*};
+use rocket_db_pools::diesel::scoped_futures::ScopedFutureExt;
 
 use rocket::serde::json::{Json, json};
 use rocket::fairing::AdHoc;
@@ -9,17 +10,30 @@ use rocket::State;
 
 use redis::Commands;
 use serde_json::Value;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 
 use rocket::fs::TempFile;
-use rocket::form::Form;
-use std::env;
 
 pub mod schema;
 pub mod models;
 pub mod cors;
 pub mod email;
 pub mod utils;
+pub mod reconcile;
+pub mod index;
+pub mod validation;
+pub mod directory;
+pub mod rules;
+pub mod exclusions;
+pub mod schedules;
+pub mod pauses;
+pub mod capacity;
+pub mod stores;
+pub mod ingest;
+pub mod imports;
+pub mod xlsx;
+pub mod coverage;
+pub mod export;
 
 #[derive(Database)]
 #[database("pincode-serviceability")]
@@ -40,7 +54,6 @@ struct Upload<'r> {
 impl From<utils::MerchantData> for models::Merchant {
     fn from(merchant_data: utils::MerchantData) -> Self {
         let contact_info = &merchant_data.contact;
-        let formatted_pincodes = merchant_data.pincodes_serviced.join(", ");
 
         models::Merchant {
             id: merchant_data.id,
@@ -48,120 +61,106 @@ impl From<utils::MerchantData> for models::Merchant {
             business_category: merchant_data.business_category,
             phone_number: contact_info.phone_number.clone(),
             email: contact_info.email.clone(),
-            pincodes_serviced: formatted_pincodes,
-        }
-    }
-}
-
-#[post("/upload_csv", data = "<form>")]
-async fn upload_csv(redis: &State<RedisClient>, mut db: Connection<Db>, mut form: Form<Upload<'_>>) -> Json<utils::ApiResponse> {
-    // let mut result = Vec::new();
-    let mut added_merchant_ids = Vec::new(); // Track the IDs of added merchants
-
-    // Persist the uploaded file to a temporary location
-    // println!("The file path to write is {:?}", env::temp_dir().to_str().unwrap());
-    let filepath = format!("{}/csv_file.csv", env::temp_dir().to_str().unwrap());
-    println!("The file path to write is {:?}", filepath);
-    form.upload.persist_to(&filepath).await.unwrap();
-
-    // Read the content of the CSV file
-    let mut reader = csv::ReaderBuilder::new()
-        .has_headers(true)
-        .from_path(filepath)
-        .unwrap();
-
-    // Process each record in the CSV file
-    for record in reader.records() {
-        if let Ok(record) = record {
-            let merchant_name = record.get(0).unwrap().to_string();
-            let business_category = record.get(1).unwrap().to_string();
-            let phone_number = record.get(2).unwrap().to_string();
-            let email = record.get(3).unwrap().to_string();
-            let pincodes_str = record.get(4).unwrap().to_string();
-            let pincodes: Vec<String> = pincodes_str.split(", ").map(|s| s.to_string()).collect();
-
-            let new_merchant_id = generate_merchant_id(&mut db).await;
-
-            let merchant_data = utils::MerchantData {
-                id: new_merchant_id,
-                name: merchant_name,
-                business_category: business_category,
-                contact: utils::ContactInformation { phone_number: phone_number, email: email },
-                pincodes_serviced: pincodes,
-            };
+            latitude: merchant_data.latitude,
+            longitude: merchant_data.longitude,
+            delivery_radius_km: merchant_data.delivery_radius_km,
+            external_ref: merchant_data.external_ref,
+        }
+    }
+}
 
-            if add_merchant_to_db(&mut db, merchant_data.clone()).await {
-                // Store data in Redis
-                match store_data(&redis.client, &merchant_data) {
-                    Ok(_) => {
-                        // let _ = email::send_email(merchant_data.contact.email, new_merchant_id).await;
-                        added_merchant_ids.push(new_merchant_id); // Store the ID of added merchant
-                    }
-                    Err(err) => {
-                        eprintln!("Failed to store data in Redis: {:?}", err);
-                        // Return an error response
-                        return Json(utils::ApiResponse {
-                            status: utils::ApiResponseStatus::Error,
-                            data: json!({"message": format!("{}", err)}).into(),
-                        });
-                    }
-                }
-            } else {
-                // Log an error and return an error response
-                eprintln!("Failed to add merchant to PostgreSQL");
-                return Json(utils::ApiResponse {
-                    status: utils::ApiResponseStatus::Error,
-                    data: json!({"message": "Failed to add merchant to PostgreSQL"}).into(),
-                });
-            }
+fn store_data(redis_client: &redis::Client, data: &utils::MerchantData) -> redis::RedisResult<()> {
+    let merchant: models::Merchant = data.clone().into();
+
+    store_merchant_details(redis_client, &merchant)?;
+    store_serviceability(redis_client, data.id, &data.pincodes_serviced)
+}
+
+fn store_merchant_details(redis_client: &redis::Client, merchant: &models::Merchant) -> redis::RedisResult<()> {
+    let mut con = redis_client.get_connection()?;
+
+    // Write to the index being rebuilt as well, if any, so the update isn't lost when it is swapped in
+    index::write(&mut con, &[merchant.id], |con, pipe, version| add_merchant_details_commands(con, pipe, version, merchant))
+}
+
+fn add_merchant_details_commands(con: &mut redis::Connection, pipe: &mut redis::Pipeline, version: u64, merchant: &models::Merchant) -> redis::RedisResult<()> {
+    // Move the merchant out of its previous category if the category changed
+    let previous_category: Option<String> = con.hget(index::merchant_key(version, merchant.id), "business_category")?;
+    if let Some(previous_category) = previous_category {
+        if utils::normalize_category(&previous_category) != utils::normalize_category(&merchant.business_category) {
+            pipe.srem(index::category_key(version, &previous_category), merchant.id).ignore();
         }
     }
 
-    println!("The added merchant ids are {:?}", added_merchant_ids);
+    // Store merchant data in a Redis Hash, overwriting the previous details
+    pipe.hset_multiple(index::merchant_key(version, merchant.id), &index::merchant_fields(merchant)).ignore();
+    pipe.sadd(index::category_key(version, &merchant.business_category), merchant.id).ignore();
 
-    // If any merchants were added, return their IDs
-    if !added_merchant_ids.is_empty() {
-        Json(utils::ApiResponse {
-            status: utils::ApiResponseStatus::Success,
-            data: json!({"merchant_ids": added_merchant_ids, "message": "Merchants added successfully"}).into(),
-        })
-    } else {
-        // If no merchants were added, return an error response
-        Json(utils::ApiResponse {
-            status: utils::ApiResponseStatus::Error,
-            data: json!({"message": "No merchants added"}).into(),
-        })
+    // Keep the geo index in line with the merchant's location
+    index::add_location_commands(pipe, version, merchant);
+    if index::merchant_location(merchant).is_none() {
+        pipe.hdel(index::merchant_key(version, merchant.id), &index::LOCATION_FIELDS).ignore();
+    }
+    if merchant.external_ref.is_none() {
+        pipe.hdel(index::merchant_key(version, merchant.id), "external_ref").ignore();
     }
-}
 
+    Ok(())
+}
 
+fn store_serviceability(redis_client: &redis::Client, merchant_id: i32, pincodes: &[String]) -> redis::RedisResult<()> {
+    if pincodes.is_empty() {
+        return Ok(());
+    }
 
-fn store_data(redis_client: &redis::Client, data: &utils::MerchantData) -> redis::RedisResult<()> {
     let mut con = redis_client.get_connection()?;
-    let json_data = serde_json::to_string(data).unwrap_or_default();
+    index::write(&mut con, &[merchant_id], |_, pipe, version| {
+        add_serviceability_commands(pipe, version, merchant_id, pincodes);
+        Ok(())
+    })
+}
 
-    // Store merchant data in a Redis Set
-    con.sadd("merchants", json_data)?;
+// Adds the merchant to each pincode's set of merchants, and the pincode to the merchant's set of pincodes
+fn add_serviceability_commands(pipe: &mut redis::Pipeline, version: u64, merchant_id: i32, pincodes: &[String]) {
+    for pincode in pincodes {
+        pipe.sadd(index::pincode_key(version, pincode), merchant_id).ignore();
+        pipe.sadd(index::merchant_pincodes_key(version, merchant_id), pincode).ignore();
+    }
+}
 
-    // Store mapping from each pincode to merchant ID in a Redis Hash
-    for pincode in &data.pincodes_serviced {
-        con.sadd(format!("pincodes:{}", pincode), data.id)?;
+// Stores the delivery terms of each pincode in the pincode's terms hash, keyed by merchant ID
+fn store_delivery_terms(redis_client: &redis::Client, rows: &[models::ServicedPincode]) -> redis::RedisResult<()> {
+    let rows: Vec<&models::ServicedPincode> = rows.iter().filter(|row| !row.terms.is_empty()).collect();
+    if rows.is_empty() {
+        return Ok(());
     }
 
-    Ok(())
+    let merchant_ids: Vec<i32> = rows.iter().map(|row| row.merchant_id).collect::<BTreeSet<i32>>().into_iter().collect();
+    let mut con = redis_client.get_connection()?;
+    index::write(&mut con, &merchant_ids, |_, pipe, version| {
+        for row in &rows {
+            pipe.hset(index::terms_key(version, &row.pincode), row.merchant_id, index::encode_terms(&row.terms)).ignore();
+        }
+        Ok(())
+    })
 }
 
 async fn add_merchant_to_db(db: &mut Connection<Db>, merchant_data: utils::MerchantData) -> bool {
     use self::schema::merchants::dsl::merchants;
-    
+
+    let merchant_id = merchant_data.id;
+    let pincodes = merchant_data.pincodes_serviced.clone();
     let merchant: models::Merchant = merchant_data.into();
 
-    println!("The merchant to be added is {:?}\n", merchant);
+    // Insert the merchant and its pincodes together so a failure leaves neither behind
+    let result = db.transaction::<_, diesel::result::Error, _>(|conn| async move {
+        diesel::insert_into(merchants)
+            .values(&merchant)
+            .execute(conn)
+            .await?;
 
-    let result = diesel::insert_into(merchants)
-        .values(&merchant)
-        .execute( db)
-        .await;
+        insert_merchant_pincodes(conn, merchant_id, &pincodes).await
+    }.scope_boxed()).await;
 
     match result {
         Ok(_) => true, // Insert successful
@@ -172,7 +171,7 @@ async fn add_merchant_to_db(db: &mut Connection<Db>, merchant_data: utils::Merch
     }
 }
 
-async fn generate_merchant_id(db: &mut Connection<Db>) -> i32 {
+async fn generate_merchant_id(db: &mut AsyncPgConnection) -> i32 {
     use self::schema::merchants::dsl::{id, merchants};
 
     let result: Option<i32> = merchants
@@ -186,18 +185,272 @@ async fn generate_merchant_id(db: &mut Connection<Db>) -> i32 {
     result.unwrap_or(0) + 1
 }
 
-fn retrieve_merchant_ids(redis_client: &redis::Client, pincode: &str) -> redis::RedisResult<Vec<u32>> {
+fn retrieve_merchant_ids(redis_client: &redis::Client, pincode: &str, category: Option<&str>) -> redis::RedisResult<Vec<u32>> {
+    let mut con = redis_client.get_connection()?;
+    let version = index::current_version(&mut con)?;
+
+    // Merchants that excluded the pincode or are paused for it are dropped with SDIFF, after restricting to the category if one is given
+    let merchant_ids: Vec<u32> = match category {
+        Some(category) => {
+            let category_members_key = index::temp_key(version);
+            let (merchant_ids,): (Vec<u32>,) = redis::pipe()
+                .atomic()
+                .sinterstore(&category_members_key, &[index::pincode_key(version, pincode), index::category_key(version, category)]).ignore()
+                .sdiff(&[category_members_key.clone(), index::excluded_key(version, pincode), index::paused_key(version, pincode)])
+                .del(&category_members_key).ignore()
+                .query(&mut con)?;
+            merchant_ids
+        }
+        None => con.sdiff(&[index::pincode_key(version, pincode), index::excluded_key(version, pincode), index::paused_key(version, pincode)])?,
+    };
+
+    Ok(merchant_ids)
+}
+
+// Merchants servicing all (SINTER) or any (SUNION) of the pincodes
+fn retrieve_combined_merchant_ids(redis_client: &redis::Client, pincodes: &[String], all: bool, category: Option<&str>) -> redis::RedisResult<Vec<u32>> {
     let mut con = redis_client.get_connection()?;
-    let merchant_ids: Vec<u32> = con.smembers(format!("pincodes:{}", pincode))?;
+    let version = index::current_version(&mut con)?;
+
+    // Each pincode's merchants minus the ones that excluded it or are paused for it are stored in scratch keys,
+    // which are then combined (and intersected with the category) in the same round trip
+    let mut pipe = redis::pipe();
+    pipe.atomic();
+
+    let mut keys: Vec<String> = Vec::new();
+    for pincode in pincodes {
+        let key = index::temp_key(version);
+        pipe.sdiffstore(&key, &[index::pincode_key(version, pincode), index::excluded_key(version, pincode), index::paused_key(version, pincode)]).ignore();
+        keys.push(key);
+    }
+    let scratch_keys = keys.clone();
 
+    if all {
+        // The category set is intersected along with the pincode sets
+        if let Some(category) = category {
+            keys.push(index::category_key(version, category));
+        }
+        pipe.sinter(keys);
+    } else if let Some(category) = category {
+        let union_key = index::temp_key(version);
+        pipe.sunionstore(&union_key, keys).ignore()
+            .sinter(&[union_key.clone(), index::category_key(version, category)])
+            .del(&union_key).ignore();
+    } else {
+        pipe.sunion(keys);
+    }
+    pipe.del(&scratch_keys).ignore();
+
+    let (mut merchant_ids,): (Vec<u32>,) = pipe.query(&mut con)?;
+
+    merchant_ids.sort();
     Ok(merchant_ids)
 }
 
+// Merchants whose delivery radius covers each of the points (latitude, longitude), with their distance in km
+fn retrieve_nearby_merchant_ids(redis_client: &redis::Client, points: &[(f64, f64)], category: Option<&str>) -> redis::RedisResult<Vec<Vec<(u32, f64)>>> {
+    let mut con = redis_client.get_connection()?;
+    let version = index::current_version(&mut con)?;
+
+    // The largest delivery radius bounds the search, each candidate is then checked against its own radius
+    let largest: Vec<(u32, f64)> = con.zrevrange_withscores(index::radius_key(version), 0, 0)?;
+    let Some((_, max_radius_km)) = largest.into_iter().next() else {
+        return Ok(vec![Vec::new(); points.len()]);
+    };
+
+    let mut results = Vec::new();
+    for &(latitude, longitude) in points {
+        let candidates: Vec<(u32, f64)> = redis::cmd("GEOSEARCH")
+            .arg(index::geo_key(version))
+            .arg("FROMLONLAT").arg(longitude).arg(latitude)
+            .arg("BYRADIUS").arg(max_radius_km).arg("km")
+            .arg("WITHDIST")
+            .query(&mut con)?;
+
+        if candidates.is_empty() {
+            results.push(Vec::new());
+            continue;
+        }
+
+        let candidate_ids: Vec<u32> = candidates.iter().map(|&(merchant_id, _)| merchant_id).collect();
+        let radii: Vec<Option<f64>> = redis::cmd("ZMSCORE").arg(index::radius_key(version)).arg(&candidate_ids).query(&mut con)?;
+        let in_category: Vec<bool> = match category {
+            Some(category) => redis::cmd("SMISMEMBER").arg(index::category_key(version, category)).arg(&candidate_ids).query(&mut con)?,
+            None => vec![true; candidate_ids.len()],
+        };
+
+        let mut nearby: Vec<(u32, f64)> = candidates
+            .into_iter()
+            .zip(radii)
+            .zip(in_category)
+            .filter(|(((_, distance_km), radius_km), in_category)| *in_category && radius_km.is_some_and(|radius_km| *distance_km <= radius_km))
+            .map(|(candidate, _)| candidate.0)
+            .collect();
+        nearby.sort_by_key(|&(merchant_id, _)| merchant_id);
+        results.push(nearby);
+    }
+
+    Ok(results)
+}
+
+// Merchants matched by a rule (prefix, range or delivery radius) for each pincode, along with the rule that matched
+type RuleMatches = HashMap<String, Vec<(u32, utils::RuleMatch)>>;
+
+// Drops the matches of merchants that excluded the pincode or are paused for it
+fn remove_excluded<T>(con: &mut redis::Connection, version: u64, pincode: &str, matches: &mut Vec<(u32, T)>) -> redis::RedisResult<()> {
+    if matches.is_empty() {
+        return Ok(());
+    }
+
+    let merchant_ids: Vec<u32> = matches.iter().map(|(merchant_id, _)| *merchant_id).collect();
+    let (excluded, paused): (Vec<bool>, Vec<bool>) = redis::pipe()
+        .cmd("SMISMEMBER").arg(index::excluded_key(version, pincode)).arg(&merchant_ids)
+        .cmd("SMISMEMBER").arg(index::paused_key(version, pincode)).arg(&merchant_ids)
+        .query(con)?;
+
+    let mut dropped = excluded.into_iter().zip(paused).map(|(excluded, paused)| excluded || paused);
+    matches.retain(|_| !dropped.next().unwrap_or(false));
+
+    Ok(())
+}
+
+// Drops the rule matches of merchants that excluded the pincode or are paused for it
+fn remove_excluded_matches(redis_client: &redis::Client, rule_matches: &mut RuleMatches) -> redis::RedisResult<()> {
+    let mut con = redis_client.get_connection()?;
+    let version = index::current_version(&mut con)?;
+
+    for (pincode, matches) in rule_matches.iter_mut() {
+        remove_excluded(&mut con, version, pincode, matches)?;
+    }
+
+    Ok(())
+}
+
+// Drops the merchants near a point that excluded the point's pincode or are paused for it
+fn remove_excluded_nearby(redis_client: &redis::Client, pincode: &str, nearby: &mut Vec<(u32, f64)>) -> redis::RedisResult<()> {
+    pauses::expire_pauses(redis_client, &[pincode.to_string()])?;
+
+    let mut con = redis_client.get_connection()?;
+    let version = index::current_version(&mut con)?;
+    remove_excluded(&mut con, version, pincode, nearby)
+}
+
+fn rule_match_ids<'a>(rule_matches: &'a RuleMatches, pincode: &str) -> impl Iterator<Item = u32> + 'a {
+    rule_matches.get(pincode).into_iter().flatten().map(|(merchant_id, _)| *merchant_id)
+}
+
+// Merchants servicing all or any of the pincodes, either explicitly or through one of the rule matches
+fn retrieve_combined_with_rules(redis_client: &redis::Client, pincodes: &[String], all: bool, category: Option<&str>, rule_matches: &RuleMatches) -> redis::RedisResult<Vec<u32>> {
+    let rule_ids: BTreeSet<u32> = pincodes.iter().flat_map(|pincode| rule_match_ids(rule_matches, pincode)).collect();
+    if rule_ids.is_empty() {
+        return retrieve_combined_merchant_ids(redis_client, pincodes, all, category);
+    }
+
+    if !all {
+        let mut merchant_ids: BTreeSet<u32> = retrieve_combined_merchant_ids(redis_client, pincodes, false, category)?.into_iter().collect();
+        merchant_ids.extend(rule_ids);
+        return Ok(merchant_ids.into_iter().collect());
+    }
+
+    // Each pincode may be covered explicitly or by a rule, so the sets are intersected one pincode at a time
+    let mut combined: Option<BTreeSet<u32>> = None;
+    for pincode in pincodes {
+        let mut merchant_ids: BTreeSet<u32> = retrieve_merchant_ids(redis_client, pincode, category)?.into_iter().collect();
+        merchant_ids.extend(rule_match_ids(rule_matches, pincode));
+
+        combined = Some(match combined {
+            Some(combined) => combined.intersection(&merchant_ids).cloned().collect(),
+            None => merchant_ids,
+        });
+    }
+
+    Ok(combined.unwrap_or_default().into_iter().collect())
+}
+
+// Lists for each merchant the explicit pincode matches and the rules through which it services the pincodes
+fn explain_matches(redis_client: &redis::Client, pincodes: &[String], merchant_ids: &[u32], rule_matches: &RuleMatches) -> redis::RedisResult<BTreeMap<u32, Vec<utils::RuleMatch>>> {
+    let mut explain: BTreeMap<u32, Vec<utils::RuleMatch>> = BTreeMap::new();
+    if merchant_ids.is_empty() {
+        return Ok(explain);
+    }
+
+    let mut con = redis_client.get_connection()?;
+    let version = index::current_version(&mut con)?;
+
+    for pincode in pincodes {
+        let explicit: Vec<bool> = redis::cmd("SMISMEMBER").arg(index::pincode_key(version, pincode)).arg(merchant_ids).query(&mut con)?;
+        for (&merchant_id, _) in merchant_ids.iter().zip(explicit).filter(|(_, explicit)| *explicit) {
+            explain.entry(merchant_id).or_default().push(utils::RuleMatch {
+                pincode: Some(pincode.clone()),
+                rule: "pincode".to_string(),
+                value: pincode.clone(),
+            });
+        }
+
+        for (merchant_id, rule_match) in rule_matches.get(pincode).into_iter().flatten() {
+            if merchant_ids.contains(merchant_id) {
+                explain.entry(*merchant_id).or_default().push(rule_match.clone());
+            }
+        }
+    }
+
+    Ok(explain)
+}
+
+// Delivery terms the matched merchants set for each of the pincodes. None when none of them set any
+fn retrieve_delivery_terms(redis_client: &redis::Client, pincodes: &[String], merchant_ids: &[u32]) -> redis::RedisResult<Option<BTreeMap<u32, Vec<utils::PincodeDeliveryTerms>>>> {
+    let mut delivery: BTreeMap<u32, Vec<utils::PincodeDeliveryTerms>> = BTreeMap::new();
+    if merchant_ids.is_empty() {
+        return Ok(None);
+    }
+
+    let mut con = redis_client.get_connection()?;
+    let version = index::current_version(&mut con)?;
+
+    for pincode in pincodes {
+        let values: Vec<Option<String>> = redis::cmd("HMGET").arg(index::terms_key(version, pincode)).arg(merchant_ids).query(&mut con)?;
+        for (&merchant_id, value) in merchant_ids.iter().zip(values) {
+            if let Some(terms) = value.as_deref().and_then(index::decode_terms) {
+                delivery.entry(merchant_id).or_default().push(utils::PincodeDeliveryTerms { pincode: pincode.clone(), terms });
+            }
+        }
+    }
+
+    Ok((!delivery.is_empty()).then_some(delivery))
+}
+
+fn retrieve_merchant_pincodes(redis_client: &redis::Client, merchant_id: i32) -> redis::RedisResult<Vec<String>> {
+    let mut con = redis_client.get_connection()?;
+    let version = index::current_version(&mut con)?;
+    let mut pincodes: Vec<String> = con.smembers(index::merchant_pincodes_key(version, merchant_id))?;
+    pincodes.sort();
+
+    Ok(pincodes)
+}
+
 // Adds a new merchant to the Postgres and Redis database
-#[post("/merchant", format = "json", data = "<merchant>")]
-async fn add_merchant(redis: &State<RedisClient>,  mut db: Connection<Db>, merchant: Json<utils::MerchantData>) -> Json<utils::ApiResponse> {
+// Pincodes missing from the pincode directory are rejected unless allow_unknown_pincodes=true
+#[post("/merchant?<allow_unknown_pincodes>", format = "json", data = "<merchant>")]
+async fn add_merchant(redis: &State<RedisClient>,  mut db: Connection<Db>, merchant: Json<utils::MerchantData>, allow_unknown_pincodes: Option<bool>) -> Json<utils::ApiResponse> {
     let mut merchant_data = merchant.into_inner();
 
+    merchant_data.pincodes_serviced = utils::normalize_pincodes(merchant_data.pincodes_serviced);
+    merchant_data.external_ref = utils::normalize_external_ref(merchant_data.external_ref);
+
+    if let Err(errors) = validation::validate_merchant(&merchant_data) {
+        return Json(validation::error_response(errors));
+    }
+
+    if let Some(error) = external_ref_conflict(&mut db, merchant_data.external_ref.as_ref(), None).await {
+        return Json(validation::error_response(vec![error]));
+    }
+
+    if !allow_unknown_pincodes.unwrap_or(false) {
+        if let Err(response) = directory::check_pincodes(&mut db, "pincodes_serviced", &merchant_data.pincodes_serviced).await {
+            return Json(response);
+        }
+    }
+
     let new_merchant_id = generate_merchant_id(&mut db).await;
     merchant_data.id = new_merchant_id;
 
@@ -258,21 +511,302 @@ async fn get_all_merchants(db: Connection<Db>) -> Json<utils::ApiResponse> {
     })
 }
 // Returns a List for merchants serviceable for the given list of pincodes (Redis call only)
-#[get("/merchant/serviceability?<pincode_data..>")]
-fn get_merchants_by_pincode(redis: &State<RedisClient>, pincode_data: String) -> Json<utils::ApiResponse> {
-    let mut result: HashMap<String, utils::MerchantServiceability> = HashMap::new();
+// With mode=all or mode=any a single de-duplicated list of the merchants servicing all or any of the pincodes is returned
+// With expand=merchant each entry also carries the merchant details, read from Redis with a Postgres fallback
+// With category=<name> only the merchants of that business category are returned
+// Merchants whose prefix or range rules cover a pincode, or whose delivery radius covers its centroid, are returned
+// along with the explicit matches, and lat=<latitude>&lng=<longitude> returns the merchants whose delivery radius covers that point.
+// With explain=true each entry lists the rules through which the merchants matched
+// With at=<RFC 3339 timestamp> only the merchants open at that time, going by their opening hours and holidays, are returned
+#[get("/merchant/serviceability?<query..>")]
+async fn get_merchants_by_pincode(redis: &State<RedisClient>, mut db: Connection<Db>, query: utils::ServiceabilityQuery) -> Json<utils::ApiResponse> {
+    let expand_merchants = match query.expand.as_deref() {
+        None => false,
+        Some("merchant") => true,
+        Some(other) => {
+            return Json(utils::ApiResponse {
+                status: utils::ApiResponseStatus::Error,
+                data: json!({"message": format!("Unsupported expand value {}", other)}).into(),
+            });
+        }
+    };
+
+    let mode = query.mode.clone().unwrap_or_else(|| "each".to_string());
+    if !["each", "all", "any"].contains(&mode.as_str()) {
+        return Json(utils::ApiResponse {
+            status: utils::ApiResponseStatus::Error,
+            data: json!({"message": format!("Unsupported mode {}, expected all, any or each", mode)}).into(),
+        });
+    }
+
+    let at = match query.at.as_deref() {
+        None => None,
+        Some(at) => match schedules::parse_timestamp(at) {
+            Some(at) => Some(at),
+            None => {
+                return Json(validation::error_response(vec![validation::FieldError::new(
+                    "at",
+                    format!("{} is not a valid timestamp, expected RFC 3339 such as 2026-10-18T21:30:00+05:30", at),
+                )]));
+            }
+        },
+    };
+
+    let available_only = query.available_only.unwrap_or(false);
+
+    let pincodes: Vec<String> = query.pincodes
+        .as_deref()
+        .map(|pincodes| pincodes.split(',').map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect())
+        .unwrap_or_default();
+
+    match (query.lat, query.lng) {
+        (None, None) if !pincodes.is_empty() => {}
+        (Some(latitude), Some(longitude)) if pincodes.is_empty() => {
+            let mut errors = Vec::new();
+            validation::validate_coordinates(&mut errors, latitude, longitude);
+            if !errors.is_empty() {
+                return Json(validation::error_response(errors));
+            }
+
+            // Exclusions, pauses and pincode capacity limits apply to the pincode of the nearest post office
+            let point_pincodes: Vec<String> = match directory::nearest_pincode(&mut db, latitude, longitude).await {
+                Ok(pincode) => pincode.into_iter().collect(),
+                Err(err) => {
+                    eprintln!("Error finding the pincode of {}, {}: {:?}", latitude, longitude, err);
+                    return Json(utils::ApiResponse {
+                        status: utils::ApiResponseStatus::Error,
+                        data: json!({"message": "Failed to read the pincode directory"}).into(),
+                    });
+                }
+            };
+
+            let nearby = retrieve_nearby_merchant_ids(&redis.client, &[(latitude, longitude)], query.category.as_deref()).and_then(|mut results| {
+                let mut nearby = results.pop().unwrap_or_default();
+                for pincode in &point_pincodes {
+                    remove_excluded_nearby(&redis.client, pincode, &mut nearby)?;
+                }
+                let open = schedules::open_merchant_ids(&redis.client, nearby.iter().map(|(merchant_id, _)| *merchant_id).collect(), at)?;
+                let open = capacity::available_merchant_ids(&redis.client, open, &point_pincodes, false, available_only)?;
+                nearby.retain(|(merchant_id, _)| open.contains(merchant_id));
+                Ok(nearby)
+            });
+            let nearby = match nearby {
+                Ok(nearby) => nearby,
+                Err(err) => {
+                    eprintln!("Error retrieving merchants near {}, {}: {:?}", latitude, longitude, err);
+                    return Json(utils::ApiResponse {
+                        status: utils::ApiResponseStatus::Error,
+                        data: json!({"message": format!("{}", err)}).into(),
+                    });
+                }
+            };
+
+            let explain = query.explain.unwrap_or(false).then(|| {
+                nearby
+                    .iter()
+                    .map(|&(merchant_id, distance_km)| (merchant_id, vec![radius_match(None, distance_km)]))
+                    .collect()
+            });
+
+            let mut result = utils::LocationServiceability {
+                latitude,
+                longitude,
+                serviceability: utils::MerchantServiceability {
+                    merchant_ids: nearby.into_iter().map(|(merchant_id, _)| merchant_id).collect(),
+                    merchants: None,
+                    explain,
+                    delivery: None,
+                    stores: None,
+                },
+            };
+
+            if expand_merchants {
+                if let Err(err) = expand_merchant_details(&redis.client, &mut db, [&mut result.serviceability]).await {
+                    eprintln!("Error retrieving merchant details: {}", err);
+                    return Json(utils::ApiResponse {
+                        status: utils::ApiResponseStatus::Error,
+                        data: json!({"message": format!("{}", err)}).into(),
+                    });
+                }
+            }
 
-    let pincodes: Vec<String> = pincode_data.split(',').map(|s| s.trim().to_string()).collect(); //.into_inner().pincodes;
-    println!("The pincodes received are {:?}", pincodes);
+            return Json(utils::ApiResponse {
+                status: utils::ApiResponseStatus::Success,
+                data: json!(result).into(),
+            });
+        }
+        _ => {
+            return Json(utils::ApiResponse {
+                status: utils::ApiResponseStatus::Error,
+                data: json!({"message": "Pass either pincodes or both lat and lng"}).into(),
+            });
+        }
+    }
+
+    let mut rule_matches: RuleMatches = match rules::retrieve_rule_matches(&redis.client, &pincodes, query.category.as_deref()) {
+        Ok(rule_matches) => rule_matches,
+        Err(err) => {
+            eprintln!("Error retrieving the pincode rules matching {:?}: {:?}", pincodes, err);
+            return Json(utils::ApiResponse {
+                status: utils::ApiResponseStatus::Error,
+                data: json!({"message": format!("{}", err)}).into(),
+            });
+        }
+    };
+
+    // Resolve the pincodes to their centroids to find the merchants delivering there by radius
+    match directory::pincode_centroids(&mut db, &pincodes).await {
+        Ok(centroids) => {
+            let located: Vec<(&String, (f64, f64))> = pincodes
+                .iter()
+                .filter_map(|pincode| centroids.get(pincode).map(|&centroid| (pincode, centroid)))
+                .collect();
+            let points: Vec<(f64, f64)> = located.iter().map(|&(_, centroid)| centroid).collect();
+
+            match retrieve_nearby_merchant_ids(&redis.client, &points, query.category.as_deref()) {
+                Ok(results) => {
+                    for ((pincode, _), nearby) in located.into_iter().zip(results) {
+                        rule_matches.entry(pincode.clone()).or_default().extend(
+                            nearby.into_iter().map(|(merchant_id, distance_km)| (merchant_id, radius_match(Some(pincode), distance_km))),
+                        );
+                    }
+                }
+                Err(err) => {
+                    eprintln!("Error retrieving merchants near pincodes {:?}: {:?}", pincodes, err);
+                    return Json(utils::ApiResponse {
+                        status: utils::ApiResponseStatus::Error,
+                        data: json!({"message": format!("{}", err)}).into(),
+                    });
+                }
+            }
+        }
+        Err(err) => {
+            eprintln!("Error reading pincode centroids: {:?}", err);
+            return Json(utils::ApiResponse {
+                status: utils::ApiResponseStatus::Error,
+                data: json!({"message": "Failed to read the pincode directory"}).into(),
+            });
+        }
+    };
+
+    // Exclusions and pauses override the rules as well as the explicit pincodes
+    let removed = pauses::expire_pauses(&redis.client, &pincodes)
+        .and_then(|_| remove_excluded_matches(&redis.client, &mut rule_matches));
+    if let Err(err) = removed {
+        eprintln!("Error retrieving the exclusions and pauses of pincodes {:?}: {:?}", pincodes, err);
+        return Json(utils::ApiResponse {
+            status: utils::ApiResponseStatus::Error,
+            data: json!({"message": format!("{}", err)}).into(),
+        });
+    }
+
+    if mode != "each" {
+        let merchant_ids = retrieve_combined_with_rules(&redis.client, &pincodes, mode == "all", query.category.as_deref(), &rule_matches)
+            .and_then(|merchant_ids| schedules::open_merchant_ids(&redis.client, merchant_ids, at))
+            .and_then(|merchant_ids| capacity::available_merchant_ids(&redis.client, merchant_ids, &pincodes, mode == "all", available_only));
+        let merchant_ids = match merchant_ids {
+            Ok(merchant_ids) => merchant_ids,
+            Err(err) => {
+                eprintln!("Error retrieving data for pincodes {:?}: {:?}", pincodes, err);
+                return Json(utils::ApiResponse {
+                    status: utils::ApiResponseStatus::Error,
+                    data: json!({"message": format!("{}", err)}).into(),
+                });
+            }
+        };
+
+        let explain = if query.explain.unwrap_or(false) {
+            match explain_matches(&redis.client, &pincodes, &merchant_ids, &rule_matches) {
+                Ok(explain) => Some(explain),
+                Err(err) => {
+                    eprintln!("Error explaining the matches for pincodes {:?}: {:?}", pincodes, err);
+                    return Json(utils::ApiResponse {
+                        status: utils::ApiResponseStatus::Error,
+                        data: json!({"message": format!("{}", err)}).into(),
+                    });
+                }
+            }
+        } else {
+            None
+        };
+
+        let details = retrieve_delivery_terms(&redis.client, &pincodes, &merchant_ids)
+            .and_then(|delivery| Ok((delivery, stores::retrieve_store_matches(&redis.client, &pincodes, &merchant_ids)?)));
+        let (delivery, store_matches) = match details {
+            Ok(details) => details,
+            Err(err) => {
+                eprintln!("Error retrieving the delivery terms and stores for pincodes {:?}: {:?}", pincodes, err);
+                return Json(utils::ApiResponse {
+                    status: utils::ApiResponseStatus::Error,
+                    data: json!({"message": format!("{}", err)}).into(),
+                });
+            }
+        };
+
+        let mut result = utils::CombinedServiceability {
+            mode,
+            pincodes,
+            serviceability: utils::MerchantServiceability { merchant_ids, merchants: None, explain, delivery, stores: store_matches },
+        };
+
+        if expand_merchants {
+            if let Err(err) = expand_merchant_details(&redis.client, &mut db, [&mut result.serviceability]).await {
+                eprintln!("Error retrieving merchant details: {}", err);
+                return Json(utils::ApiResponse {
+                    status: utils::ApiResponseStatus::Error,
+                    data: json!({"message": format!("{}", err)}).into(),
+                });
+            }
+        }
+
+        return Json( utils::ApiResponse {
+            status: utils::ApiResponseStatus::Success,
+            data: json!(result).into(),
+        });
+    }
+
+    let mut result: HashMap<String, utils::MerchantServiceability> = HashMap::new();
 
     for pincode in pincodes.iter() {
-        match retrieve_merchant_ids(&redis.client, &pincode) {
+        let merchant_ids = retrieve_merchant_ids(&redis.client, pincode, query.category.as_deref()).and_then(|merchant_ids| {
+            let merchant_ids: Vec<u32> = merchant_ids
+                .into_iter()
+                .chain(rule_match_ids(&rule_matches, pincode))
+                .collect::<BTreeSet<u32>>()
+                .into_iter()
+                .collect();
+            let merchant_ids = schedules::open_merchant_ids(&redis.client, merchant_ids, at)?;
+            capacity::available_merchant_ids(&redis.client, merchant_ids, std::slice::from_ref(pincode), true, available_only)
+        });
+
+        match merchant_ids {
             Ok(merchant_ids) => {
-                result.insert(pincode.clone(), utils::MerchantServiceability { merchant_ids });
+
+                let explain = query.explain
+                    .unwrap_or(false)
+                    .then(|| explain_matches(&redis.client, std::slice::from_ref(pincode), &merchant_ids, &rule_matches))
+                    .transpose();
+                let details = explain.and_then(|explain| {
+                    let delivery = retrieve_delivery_terms(&redis.client, std::slice::from_ref(pincode), &merchant_ids)?;
+                    Ok((explain, delivery, stores::retrieve_store_matches(&redis.client, std::slice::from_ref(pincode), &merchant_ids)?))
+                });
+
+                match details {
+                    Ok((explain, delivery, store_matches)) => {
+                        result.insert(pincode.clone(), utils::MerchantServiceability { merchant_ids, merchants: None, explain, delivery, stores: store_matches });
+                    }
+                    Err(err) => {
+                        eprintln!("Error retrieving the match details for pincode {}: {:?}", pincode, err);
+                        return Json(utils::ApiResponse {
+                            status: utils::ApiResponseStatus::Error,
+                            data: json!({"message": format!("{}", err)}).into(),
+                        });
+                    }
+                }
             }
             Err(err) => {
                 eprintln!("Error retrieving data for pincode {}: {:?}", pincode, err);
-                Json(utils::ApiResponse {
+                return Json(utils::ApiResponse {
                     status: utils::ApiResponseStatus::Error,
                     data: json!({"message": format!("{}", err)}).into(),
                 });
@@ -280,6 +814,16 @@ fn get_merchants_by_pincode(redis: &State<RedisClient>, pincode_data: String) ->
         }
     }
 
+    if expand_merchants {
+        if let Err(err) = expand_merchant_details(&redis.client, &mut db, result.values_mut()).await {
+            eprintln!("Error retrieving merchant details: {}", err);
+            return Json(utils::ApiResponse {
+                status: utils::ApiResponseStatus::Error,
+                data: json!({"message": format!("{}", err)}).into(),
+            });
+        }
+    }
+
     Json( utils::ApiResponse {
         status: utils::ApiResponseStatus::Success,
         data: json!(result).into(),
@@ -287,16 +831,109 @@ fn get_merchants_by_pincode(redis: &State<RedisClient>, pincode_data: String) ->
 
 }
 
+fn radius_match(pincode: Option<&String>, distance_km: f64) -> utils::RuleMatch {
+    utils::RuleMatch {
+        pincode: pincode.cloned(),
+        rule: "radius".to_string(),
+        value: format!("{:.2} km", distance_km),
+    }
+}
+
+// Fills in the merchant and store details of the serviceability entries, fetching each merchant and store only once
+async fn expand_merchant_details<'a, I>(redis_client: &redis::Client, db: &mut Connection<Db>, entries: I) -> Result<(), utils::StoreError>
+where
+    I: IntoIterator<Item = &'a mut utils::MerchantServiceability>,
+{
+    let entries: Vec<&mut utils::MerchantServiceability> = entries.into_iter().collect();
+
+    let merchant_ids: BTreeSet<i32> = entries
+        .iter()
+        .flat_map(|serviceability| serviceability.merchant_ids.iter().map(|&merchant_id| merchant_id as i32))
+        .collect();
+    let store_ids: Vec<i32> = entries
+        .iter()
+        .flat_map(|serviceability| serviceability.stores.iter().flatten().map(|store_match| store_match.store_id))
+        .collect::<BTreeSet<i32>>()
+        .into_iter()
+        .collect();
+
+    let details = retrieve_merchant_details(redis_client, db, &merchant_ids).await?;
+    let store_details = if store_ids.is_empty() {
+        HashMap::new()
+    } else {
+        stores::load_stores_by_id(db, &store_ids).await?
+    };
+
+    for serviceability in entries {
+        for store_match in serviceability.stores.iter_mut().flatten() {
+            store_match.store = store_details
+                .get(&store_match.store_id)
+                .map(|store| utils::StoreSummary::new(store, details.get(&(store_match.merchant_id as i32))));
+        }
+
+        serviceability.merchants = Some(
+            serviceability
+                .merchant_ids
+                .iter()
+                .filter_map(|&merchant_id| details.get(&(merchant_id as i32)))
+                .map(utils::MerchantSummary::from)
+                .collect(),
+        );
+    }
+
+    Ok(())
+}
+
+// Reads the merchant hashes in a single pipeline and loads the ones Redis doesn't have from Postgres in one query
+async fn retrieve_merchant_details(redis_client: &redis::Client, db: &mut Connection<Db>, merchant_ids: &BTreeSet<i32>) -> Result<HashMap<i32, models::Merchant>, utils::StoreError> {
+    use self::schema::merchants;
+
+    let mut con = redis_client.get_connection()?;
+    let version = index::current_version(&mut con)?;
+
+    let mut pipe = redis::pipe();
+    for merchant_id in merchant_ids {
+        pipe.hgetall(index::merchant_key(version, *merchant_id));
+    }
+    let stored: Vec<HashMap<String, String>> = pipe.query(&mut con)?;
+
+    let mut details: HashMap<i32, models::Merchant> = stored
+        .into_iter()
+        .filter_map(index::merchant_from_fields)
+        .map(|merchant| (merchant.id, merchant))
+        .collect();
+
+    let missing_ids: Vec<i32> = merchant_ids
+        .iter()
+        .filter(|merchant_id| !details.contains_key(merchant_id))
+        .cloned()
+        .collect();
+
+    if !missing_ids.is_empty() {
+        let rows = merchants::table
+            .filter(merchants::id.eq_any(&missing_ids))
+            .load::<models::Merchant>(db)
+            .await?;
+        details.extend(rows.into_iter().map(|merchant| (merchant.id, merchant)));
+    }
+
+    Ok(details)
+}
+
 // Return the merchant information based on the merchant id (Postgres call only)
 #[get("/merchant/<merchant_id>", format = "json")]
 async fn get_merchant_info(mut db: Connection<Db>, merchant_id: i32) -> Json<utils::ApiResponse> {
     use self::schema::merchants;
 
     match merchants::table.find(merchant_id).first::<models::Merchant>(&mut db).await {
-        Ok(merchant) => Json( utils::ApiResponse {
-            status: utils::ApiResponseStatus::Success,
-            data: json!(merchant).into()
-        }),
+        Ok(merchant) => {
+            let pincodes_serviced = load_merchant_pincodes(&mut db, merchant_id).await.unwrap_or_default();
+
+            Json( utils::ApiResponse {
+                status: utils::ApiResponseStatus::Success,
+                data: json!(utils::MerchantInfo { merchant, pincodes_serviced }).into()
+            })
+        },
         Err(err) => Json( utils::ApiResponse { 
             status: utils::ApiResponseStatus::Error, 
             data: json!({"message": format!("Failed to update merchant information: {:?}", err)}).into() 
@@ -304,30 +941,70 @@ async fn get_merchant_info(mut db: Connection<Db>, merchant_id: i32) -> Json<uti
     }
 }
 
+// Returns the pincodes serviced by the merchant (Redis call, falls back to Postgres)
+#[get("/merchant/<merchant_id>/serviceability")]
+async fn get_merchant_serviceability(redis: &State<RedisClient>, mut db: Connection<Db>, merchant_id: i32) -> Json<utils::ApiResponse> {
+    match retrieve_merchant_pincodes(&redis.client, merchant_id) {
+        Ok(pincodes) if !pincodes.is_empty() => {
+            return Json(utils::ApiResponse {
+                status: utils::ApiResponseStatus::Success,
+                data: json!(pincodes).into(),
+            });
+        }
+        Ok(_) => {}
+        Err(err) => eprintln!("Error retrieving pincodes for merchant {} from Redis: {:?}", merchant_id, err),
+    }
+
+    // Nothing in Redis for the merchant, read the coverage from Postgres instead
+    match get_serviced_pincodes(&mut db, merchant_id).await {
+        Ok(pincodes) => Json(utils::ApiResponse {
+            status: utils::ApiResponseStatus::Success,
+            data: json!(pincodes).into(),
+        }),
+        Err(err) => {
+            eprintln!("Error fetching serviced pincodes: {}", err);
+            Json(utils::ApiResponse {
+                status: utils::ApiResponseStatus::Error,
+                data: json!({"message": format!("{}", err)}).into(),
+            })
+        }
+    }
+}
 
 // Updates the Merchant data in the Postgres table
 #[put("/merchant/<merchant_id>", format = "json", data = "<update_data>")]
-async fn update_merchant_info(mut db: Connection<Db>, update_data: Json<models::UpdateMerchantData>, merchant_id: i32) -> Json<utils::ApiResponse> {
+async fn update_merchant_info(redis: &State<RedisClient>, mut db: Connection<Db>, update_data: Json<models::UpdateMerchantData>, merchant_id: i32) -> Json<utils::ApiResponse> {
     use self::schema::merchants;
-    use self::schema::merchants::dsl::{name, business_category, phone_number, email};
 
-    // Returns the number of rows affected
+    let mut update_data = update_data.into_inner();
+    update_data.external_ref = utils::normalize_external_ref(update_data.external_ref);
+
+    if let Err(errors) = validation::validate_update(&update_data) {
+        return Json(validation::error_response(errors));
+    }
+
+    if let Some(error) = external_ref_conflict(&mut db, update_data.external_ref.as_ref(), Some(merchant_id)).await {
+        return Json(validation::error_response(vec![error]));
+    }
+
+    // Returns the updated row, the location columns are only set when they were sent
     let result = diesel::update(merchants::table.filter(merchants::id.eq(merchant_id)))
-        .set((
-            name.eq(&update_data.name),
-            business_category.eq(&update_data.business_category),
-            phone_number.eq(&update_data.phone_number),
-            email.eq(&update_data.email)
-        ))
-        .execute(&mut db)
+        .set(&update_data)
+        .returning(models::Merchant::as_returning())
+        .get_result(&mut db)
         .await;
-    
-    println!("Result received is {:?}", result);
+
     match result {
-        Ok(1) => Json( utils::ApiResponse {
-            status: utils::ApiResponseStatus::Success,
-            data: json!({"message": "Merchant Information updated successfully"}).into()
-        }),
+        Ok(updated_merchant) => {
+            if let Err(err) = store_merchant_details(&redis.client, &updated_merchant) {
+                eprintln!("Failed to update merchant {} in Redis: {:?}", merchant_id, err);
+            }
+
+            Json( utils::ApiResponse {
+                status: utils::ApiResponseStatus::Success,
+                data: json!({"message": "Merchant Information updated successfully"}).into()
+            })
+        },
         _ => Json( utils::ApiResponse { 
             status: utils::ApiResponseStatus::Error, 
             data: json!({"message": format!("Failed to update merchant information")}).into() 
@@ -336,176 +1013,355 @@ async fn update_merchant_info(mut db: Connection<Db>, update_data: Json<models::
 
 }
 
-async fn get_serviced_pincodes(db: &mut Connection<Db>, merchant_id: i32) -> Result<String, String> {
-    use self::schema::merchants;
+// Pincodes the merchant services itself, leaving out the ones serviced through its stores
+async fn load_own_pincodes(db: &mut AsyncPgConnection, merchant_id: i32) -> QueryResult<Vec<String>> {
+    use self::schema::merchant_pincodes;
 
-    match merchants::table
-        .find(merchant_id)
-        .first::<models::Merchant>(db)
+    merchant_pincodes::table
+        .filter(merchant_pincodes::merchant_id.eq(merchant_id))
+        .select(merchant_pincodes::pincode)
+        .load::<String>(db)
         .await
-    {
-        Ok(result) => Ok(result.pincodes_serviced),
-        Err(err) => Err(format!("Error fetching serviced pincodes: {:?}", err)),
+}
+
+// Pincodes the merchant services, itself or through one of its stores
+async fn load_merchant_pincodes(db: &mut AsyncPgConnection, merchant_id: i32) -> QueryResult<Vec<String>> {
+    let direct = load_own_pincodes(db, merchant_id).await?;
+    let through_stores = stores::load_store_pincodes(db, Some(&[merchant_id])).await?;
+
+    Ok(direct
+        .into_iter()
+        .chain(through_stores.into_iter().map(|(_, _, pincode)| pincode))
+        .collect::<BTreeSet<String>>()
+        .into_iter()
+        .collect())
+}
+
+// IDs of the merchants with the given external references
+async fn find_external_refs(db: &mut AsyncPgConnection, external_refs: &[String]) -> QueryResult<HashMap<String, i32>> {
+    use self::schema::merchants;
+
+    let rows: Vec<(i32, Option<String>)> = merchants::table
+        .filter(merchants::external_ref.eq_any(external_refs))
+        .select((merchants::id, merchants::external_ref))
+        .load(db)
+        .await?;
+
+    Ok(rows.into_iter().filter_map(|(merchant_id, external_ref)| Some((external_ref?, merchant_id))).collect())
+}
+
+// Error for an external reference already used by a merchant other than merchant_id
+async fn external_ref_conflict(db: &mut AsyncPgConnection, external_ref: Option<&String>, merchant_id: Option<i32>) -> Option<validation::FieldError> {
+    let external_ref = external_ref?;
+    let existing = find_external_refs(db, std::slice::from_ref(external_ref)).await.ok()?;
+    let existing_id = *existing.get(external_ref)?;
+
+    (Some(existing_id) != merchant_id).then(|| {
+        validation::FieldError::new("external_ref", format!("{} is already used by merchant {}", external_ref, existing_id))
+    })
+}
+
+async fn get_serviced_pincodes(db: &mut Connection<Db>, merchant_id: i32) -> Result<Vec<String>, String> {
+    use self::schema::merchants;
+
+    // Make sure the merchant exists before reading its pincodes
+    if let Err(err) = merchants::table.find(merchant_id).select(merchants::id).first::<i32>(db).await {
+        return Err(format!("Error fetching serviced pincodes: {:?}", err));
     }
+
+    load_merchant_pincodes(db, merchant_id)
+        .await
+        .map_err(|err| format!("Error fetching serviced pincodes: {:?}", err))
 }
 
-async fn update_merchant_serviceability(db: &mut Connection<Db>, merchant_id: i32, formatted_pincodes: String) -> bool {
+// Checks the merchant exists, returning the response to send when it doesn't
+async fn merchant_exists(db: &mut AsyncPgConnection, merchant_id: i32) -> Result<(), utils::ApiResponse> {
     use self::schema::merchants;
-    use self::schema::merchants::dsl::pincodes_serviced;
-    
-    let updated_merchant = models::Merchant {
-        id: merchant_id,
-        pincodes_serviced: formatted_pincodes,
-        name: String::new(),
-        email: String::new(),
-        business_category: String::new(),
-        phone_number: String::new(),
-    };
 
-    println!("The merchant to be added is {:?}", updated_merchant);
+    match merchants::table.find(merchant_id).select(merchants::id).first::<i32>(db).await.optional() {
+        Ok(Some(_)) => Ok(()),
+        Ok(None) => Err(utils::ApiResponse {
+            status: utils::ApiResponseStatus::Error,
+            data: json!({"message": format!("Merchant {} not found", merchant_id)}).into(),
+        }),
+        Err(err) => {
+            eprintln!("Error checking merchant {} exists: {:?}", merchant_id, err);
+            Err(utils::ApiResponse {
+                status: utils::ApiResponseStatus::Error,
+                data: json!({"message": "Failed to read the merchant"}).into(),
+            })
+        }
+    }
+}
 
-    let result = diesel::update(merchants::table.filter(merchants::id.eq(merchant_id)))
-        .set(pincodes_serviced.eq(&updated_merchant.pincodes_serviced))
-        .execute(db)
-        .await;
+// Inserts the pincodes for a merchant, skipping ones already serviced. Returns the newly added pincodes
+async fn insert_merchant_pincodes(db: &mut AsyncPgConnection, merchant_id: i32, pincodes: &[String]) -> QueryResult<Vec<String>> {
+    use self::schema::merchant_pincodes;
 
-    match result {
-        Ok(_) => true, // Insert successful
-        Err(_) => false, // Insert failed
+    let rows: Vec<models::MerchantPincode> = pincodes
+        .iter()
+        .map(|code| models::MerchantPincode { merchant_id, pincode: code.clone() })
+        .collect();
+
+    if rows.is_empty() {
+        return Ok(Vec::new());
     }
+
+    diesel::insert_into(merchant_pincodes::table)
+        .values(&rows)
+        .on_conflict_do_nothing()
+        .returning(merchant_pincodes::pincode)
+        .get_results(db)
+        .await
 }
 
-fn create_merchant_data(merchant_id: i32, pincodes: Vec<String>) -> utils::MerchantData {
+// Inserts the pincodes for a merchant along with their delivery terms. Pincodes already serviced get the
+// terms that were sent, terms left out keep their current value. Returns the inserted and updated rows
+async fn upsert_merchant_pincodes(db: &mut AsyncPgConnection, merchant_id: i32, pincodes: &[String], terms: &models::DeliveryTerms) -> QueryResult<Vec<models::ServicedPincode>> {
+    use self::schema::merchant_pincodes;
 
-    utils::MerchantData {
-        id: merchant_id,
-        name: String::new(),
-        business_category: String::new(),
-        contact: utils::ContactInformation { phone_number: String::new(), email: String::new() },
-        pincodes_serviced: pincodes,
+    let rows: Vec<models::ServicedPincode> = pincodes
+        .iter()
+        .map(|code| models::ServicedPincode { merchant_id, pincode: code.clone(), terms: terms.clone() })
+        .collect();
+
+    if rows.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    // An update without any column to set is rejected by Diesel, so without terms this is a plain insert
+    if terms.is_empty() {
+        return diesel::insert_into(merchant_pincodes::table)
+            .values(&rows)
+            .on_conflict_do_nothing()
+            .returning(models::ServicedPincode::as_returning())
+            .get_results(db)
+            .await;
     }
 
+    diesel::insert_into(merchant_pincodes::table)
+        .values(&rows)
+        .on_conflict((merchant_pincodes::merchant_id, merchant_pincodes::pincode))
+        .do_update()
+        .set(terms)
+        .returning(models::ServicedPincode::as_returning())
+        .get_results(db)
+        .await
+}
+
+// Removes the pincodes for a merchant. Returns the pincodes that were actually removed
+async fn remove_merchant_pincodes(db: &mut AsyncPgConnection, merchant_id: i32, pincodes: &[String]) -> QueryResult<Vec<String>> {
+    use self::schema::merchant_pincodes;
+
+    diesel::delete(
+        merchant_pincodes::table
+            .filter(merchant_pincodes::merchant_id.eq(merchant_id))
+            .filter(merchant_pincodes::pincode.eq_any(pincodes)),
+    )
+    .returning(merchant_pincodes::pincode)
+    .get_results(db)
+    .await
 }
 
 // Add additional servicealble pincodes to the database (Postgres and Redis)
-// TODO: Storing redundant pincodes currently debug this!!
-#[put("/merchant/serviceability/<merchant_id>", format = "json", data = "<pincode_data>")]
-async fn add_pincodes(redis: &State<RedisClient>, mut db: Connection<Db>, pincode_data: Json<utils::Pincodes>, merchant_id: i32) -> Json<utils::ApiResponse> {
-    match get_serviced_pincodes(&mut db, merchant_id).await {
-        Ok(serviced_pincodes) => {
-            println!("Response from get_serviced_pincodes is {:?}", serviced_pincodes);
-            let new_serviceable_pincodes = pincode_data.into_inner().pincodes;
+// Districts and states are expanded into their pincodes from the pincode directory and recorded as rules,
+// so pincodes later added to the directory for them are serviced as well.
+// Prefixes ("56") and ranges ({"from": "560001", "to": "560100"}) are stored as rules evaluated by the lookup.
+// Pincodes missing from the pincode directory are rejected unless allow_unknown_pincodes=true
+// The delivery time, fee and minimum order value sent along apply to the pincodes, districts and states of the request
+#[put("/merchant/serviceability/<merchant_id>?<allow_unknown_pincodes>", format = "json", data = "<pincode_data>")]
+async fn add_pincodes(redis: &State<RedisClient>, mut db: Connection<Db>, pincode_data: Json<utils::Pincodes>, merchant_id: i32, allow_unknown_pincodes: Option<bool>) -> Json<utils::ApiResponse> {
+    if let Err(response) = merchant_exists(&mut db, merchant_id).await {
+        return Json(response);
+    }
 
-            // Filter out redundant pin codes
-            let unique_new_pincodes: Vec<String> = new_serviceable_pincodes
-                .iter()
-                .filter(|&code| !serviced_pincodes.contains(code))
-                .cloned()  // Cloning here to create Vec<String> from Vec<&String>
-                .collect();
+    let pincode_data = pincode_data.into_inner();
+    let terms = pincode_data.delivery_terms();
+    let mut new_serviceable_pincodes = utils::normalize_pincodes(pincode_data.pincodes);
+    let districts = utils::normalize_regions(pincode_data.districts);
+    let states = utils::normalize_regions(pincode_data.states);
+    let prefixes = utils::normalize_pincodes(pincode_data.prefixes);
 
-            // Concatenate unique pin codes with existing serviced pin codes
-            let formatted_pincodes = if serviced_pincodes.is_empty() {
-                unique_new_pincodes.join(", ")
-            } else {
-                format!("{}, {}", serviced_pincodes, unique_new_pincodes.join(", "))
-            };
+    let validated = validation::validate_pincodes(&new_serviceable_pincodes, &districts, &states, &prefixes, &pincode_data.ranges);
+    if let Err(errors) = validated.and(validation::validate_delivery_terms(&terms)) {
+        return Json(validation::error_response(errors));
+    }
 
-            let updated_merchant = create_merchant_data(merchant_id, new_serviceable_pincodes);
+    let ranges = utils::normalize_pincodes(pincode_data.ranges.iter().map(rules::range_value).collect());
 
-            if update_merchant_serviceability(&mut db, merchant_id, formatted_pincodes).await {
-                match store_data(&redis.client, &updated_merchant) {
-                    Ok(_) => Json(utils::ApiResponse {
-                        status: utils::ApiResponseStatus::Success,
-                        data: json!({"ONDC_merchant_id": format!("{}", merchant_id), "message": "Merchant Information added"}).into(),
-                    }),
-                    Err(err) => {
-                        eprintln!("Failed to store data in Redis: {:?}", err);
-                        Json(utils::ApiResponse {
-                            status: utils::ApiResponseStatus::Error,
-                            data: json!({"message": format!("{}", err)}).into(),
-                        })
-                    }
+    if !allow_unknown_pincodes.unwrap_or(false) {
+        if let Err(response) = directory::check_pincodes(&mut db, "pincodes", &new_serviceable_pincodes).await {
+            return Json(response);
+        }
+    }
+
+    match directory::expand_regions(&mut db, &districts, &states).await {
+        Ok((_, errors)) if !errors.is_empty() => return Json(validation::error_response(errors)),
+        Ok((region_pincodes, _)) => {
+            new_serviceable_pincodes = utils::normalize_pincodes([new_serviceable_pincodes, region_pincodes].concat());
+        }
+        Err(err) => {
+            eprintln!("Error expanding districts and states: {:?}", err);
+            return Json(utils::ApiResponse {
+                status: utils::ApiResponseStatus::Error,
+                data: json!({"message": "Failed to check the pincode directory"}).into(),
+            });
+        }
+    }
+
+    // Excluded pincodes stay excluded, whether they were sent explicitly or are part of a district or state
+    let skipped_pincodes = match exclusions::excluded_pincodes(&mut db, merchant_id, &new_serviceable_pincodes).await {
+        Ok(excluded) => excluded,
+        Err(err) => {
+            eprintln!("Error reading the exclusions of merchant {}: {:?}", merchant_id, err);
+            return Json(utils::ApiResponse {
+                status: utils::ApiResponseStatus::Error,
+                data: json!({"message": "Failed to read the merchant exclusions"}).into(),
+            });
+        }
+    };
+    new_serviceable_pincodes.retain(|code| !skipped_pincodes.contains(code));
+
+    let region_rules = directory::region_rules(merchant_id, &districts, &states);
+    let pincode_rules = rules::pincode_rules(merchant_id, &prefixes, &ranges);
+    let upsert_terms = terms.clone();
+
+    // Rules already serviced are skipped by their unique keys, pincodes already serviced only get the new terms.
+    // A pincode the merchant only services through a store is added to the merchant itself
+    let result = db.transaction::<_, diesel::result::Error, _>(|conn| async move {
+        directory::add_region_rules(conn, &region_rules).await?;
+        let added_rules = rules::add_rules(conn, &pincode_rules).await?;
+        let own_pincodes = load_own_pincodes(conn, merchant_id).await?;
+        let rows = upsert_merchant_pincodes(conn, merchant_id, &new_serviceable_pincodes, &upsert_terms).await?;
+        Ok((rows, added_rules, own_pincodes))
+    }.scope_boxed()).await;
+
+    match result {
+        Ok((rows, added_rules, own_pincodes)) => {
+            let (updated_pincodes, added_pincodes): (Vec<String>, Vec<String>) = rows
+                .iter()
+                .map(|row| row.pincode.clone())
+                .partition(|code| own_pincodes.contains(code));
+            let stored = store_serviceability(&redis.client, merchant_id, &added_pincodes)
+                .and_then(|_| store_delivery_terms(&redis.client, &rows))
+                .and_then(|_| rules::store_rules(&redis.client, &added_rules));
+
+            match stored {
+                Ok(_) => Json(utils::ApiResponse {
+                    status: utils::ApiResponseStatus::Success,
+                    data: json!({
+                        "ONDC_merchant_id": format!("{}", merchant_id),
+                        "pincodes_added": added_pincodes,
+                        "pincodes_updated": updated_pincodes,
+                        "pincodes_excluded": skipped_pincodes,
+                        "delivery_terms": terms,
+                        "districts": districts,
+                        "states": states,
+                        "prefixes": prefixes,
+                        "ranges": ranges,
+                        "message": "Merchant Information added"
+                    }).into(),
+                }),
+                Err(err) => {
+                    eprintln!("Failed to store data in Redis: {:?}", err);
+                    Json(utils::ApiResponse {
+                        status: utils::ApiResponseStatus::Error,
+                        data: json!({"message": format!("{}", err)}).into(),
+                    })
                 }
-            } else {
-                // Log an error and return an error response
-                eprintln!("Failed to add merchant to PostgreSQL");
-                Json(utils::ApiResponse {
-                    status: utils::ApiResponseStatus::Error,
-                    data: json!({"message": "Failed to add merchant to PostgreSQL"}).into(),
-                })
             }
         }
         Err(err) => {
-            // Handle the error from get_serviced_pincodes
-            eprintln!("Error fetching serviced pincodes: {}", err);
+            // Log an error and return an error response
+            eprintln!("Failed to add merchant to PostgreSQL: {:?}", err);
             Json(utils::ApiResponse {
                 status: utils::ApiResponseStatus::Error,
-                data: json!({"message": format!("{}", err)}).into(),
+                data: json!({"message": "Failed to add merchant to PostgreSQL"}).into(),
             })
         }
     }
 }
 
 
-// Delete pincode serviceability of merchants for a subset of pincodes
+// Delete pincode serviceability of merchants for a subset of pincodes (Postgres and Redis)
+// Districts and states remove their directory pincodes along with the district or state rule,
+// prefixes and ranges remove the matching rules
 #[delete("/merchant/serviceability/<merchant_id>", format = "json", data = "<pincode_data>")]
-async fn delete_merchant_serviceability_for_pincode(mut db: Connection<Db>, pincode_data: Json<utils::Pincodes> , merchant_id: i32) -> Json<utils::ApiResponse> {
-    
-    match get_serviced_pincodes(&mut db, merchant_id).await {
-        Ok(serviced_pincodes) => {
-            let pincodes_to_delete = pincode_data.into_inner().pincodes;
+async fn delete_merchant_serviceability_for_pincode(redis: &State<RedisClient>, mut db: Connection<Db>, pincode_data: Json<utils::Pincodes> , merchant_id: i32) -> Json<utils::ApiResponse> {
+    if let Err(response) = merchant_exists(&mut db, merchant_id).await {
+        return Json(response);
+    }
 
-            let modified_serviced_pincodes: String = {
-                let modified_serviced_pincodes = serviced_pincodes
-                    .split(", ")
-                    .filter(|&code| !pincodes_to_delete.contains(&code.trim().to_string()))
-                    .collect::<Vec<&str>>()
-                    .join(", ");
+    let pincode_data = pincode_data.into_inner();
+    let districts = utils::normalize_regions(pincode_data.districts);
+    let states = utils::normalize_regions(pincode_data.states);
+    let prefixes = utils::normalize_pincodes(pincode_data.prefixes);
+    let ranges = utils::normalize_pincodes(pincode_data.ranges.iter().map(rules::range_value).collect());
+
+    let result = db.transaction::<_, utils::StoreError, _>(|conn| async move {
+        let (region_pincodes, _) = directory::expand_regions(conn, &districts, &states).await?;
+        let pincodes_to_delete = utils::normalize_pincodes([pincode_data.pincodes, region_pincodes].concat());
+
+        directory::remove_region_rules(conn, merchant_id, &districts, &states).await?;
+        let removed_rules = rules::remove_rules(conn, merchant_id, &prefixes, &ranges).await?;
+        let removed_pincodes = remove_merchant_pincodes(conn, merchant_id, &pincodes_to_delete).await?;
+        // Pincodes still serviced by one of the merchant's stores stay in the index, only their terms go
+        let unserviced = stores::unserviced_pincodes(conn, merchant_id, &removed_pincodes).await?;
+
+        let mut update = index::IndexUpdate::default();
+        let (removed, rules_removed) = (removed_pincodes.clone(), removed_rules.clone());
+        update.add(merchant_id, move |_, pipe, version| {
+            remove_serviceability_commands(pipe, version, merchant_id, &unserviced);
+            remove_terms_commands(pipe, version, merchant_id, &removed);
+            for rule in &rules_removed {
+                rules::remove_rule_commands(pipe, version, rule);
+            }
+            Ok(())
+        });
 
-                modified_serviced_pincodes.trim().to_string()
-            };
+        let not_present: Vec<String> = pincodes_to_delete
+            .into_iter()
+            .filter(|code| !removed_pincodes.contains(code))
+            .collect();
 
-            println!("The modified pincodes after delete are {:?}", modified_serviced_pincodes);
+        Ok((removed_pincodes, removed_rules, not_present, update))
+    }.scope_boxed()).await;
 
-            if modified_serviced_pincodes != serviced_pincodes {
-                // Check if any pin codes were deleted before updating the database
-                if update_merchant_serviceability(&mut db, merchant_id, modified_serviced_pincodes).await {
-                    Json(utils::ApiResponse {
-                        status: utils::ApiResponseStatus::Success,
-                        data: json!({
-                            "ONDC_merchant_id": format!("{}", merchant_id),
-                            "message": "Merchant Information added"
-                        })
-                        .into(),
-                    })
-                } else {
-                    // Log an error and return an error response
-                    eprintln!("Failed to add merchant to PostgreSQL");
-                    Json(utils::ApiResponse {
-                        status: utils::ApiResponseStatus::Error,
-                        data: json!({
-                            "message": "Failed to add merchant to PostgreSQL"
-                        })
-                        .into(),
-                    })
-                }
-            } else {
-                // No pin codes were deleted, return an error response
-                Json(utils::ApiResponse {
-                    status: utils::ApiResponseStatus::Error,
-                    data: json!({
-                        "message": "No pin codes were deleted"
-                    })
-                    .into(),
+    match result {
+        Ok((removed_pincodes, removed_rules, not_present, update)) if !removed_pincodes.is_empty() || !removed_rules.is_empty() => {
+            update.apply(&redis.client);
+            Json(utils::ApiResponse {
+                status: utils::ApiResponseStatus::Success,
+                data: json!({
+                    "ONDC_merchant_id": format!("{}", merchant_id),
+                    "removed": removed_pincodes,
+                    "rules_removed": removed_rules,
+                    "not_present": not_present,
+                    "message": "Merchant serviceability updated"
                 })
-            }
+                .into(),
+            })
+        }
+        Ok((_, _, not_present, _)) => {
+            // No pin codes were deleted, return an error response
+            Json(utils::ApiResponse {
+                status: utils::ApiResponseStatus::Error,
+                data: json!({
+                    "not_present": not_present,
+                    "message": "No pin codes were deleted"
+                })
+                .into(),
+            })
         }
         Err(err) => {
-            // Handle the error from get_serviced_pincodes
-            eprintln!("Error fetching serviced pincodes: {}", err);
+            // Log an error and return an error response
+            eprintln!("Failed to delete merchant serviceability: {}", err);
             Json(utils::ApiResponse {
                 status: utils::ApiResponseStatus::Error,
-                data: json!({"message": format!("{}", err)}).into(),
+                data: json!({
+                    "message": format!("Failed to delete merchant serviceability: {}", err)
+                })
+                .into(),
             })
         }
     }
@@ -515,42 +1371,113 @@ async fn delete_merchant_serviceability_for_pincode(mut db: Connection<Db>, pinc
 async fn delete_merchant(redis: &State<RedisClient>, mut db: Connection<Db>, merchant_id: i32) -> Json<utils::ApiResponse> {
     use self::schema::merchants;
 
-    match get_serviced_pincodes(&mut db, merchant_id).await {
-        Ok(serviced_pincodes) => {
-            let vec_of_pincodes: Vec<String> = serviced_pincodes.split(" ,").map(|s| s.trim().to_string()).collect();
-
+    // What the merchant has in the index is read before the delete cascades to it, and removed from Redis
+    // once the delete has committed
+    let result = db.transaction::<_, utils::StoreError, _>(|conn| async move {
+        let serviced_pincodes = load_merchant_pincodes(conn, merchant_id).await?;
+        let merchant_rules = rules::load_rules(conn, merchant_id).await?;
+        let store_pincodes: Vec<(i32, String)> = stores::load_store_pincodes(conn, Some(&[merchant_id]))
+            .await?
+            .into_iter()
+            .map(|(_, store_id, pincode)| (store_id, pincode))
+            .collect();
+        let excluded = exclusions::load_exclusions(conn, merchant_id).await?;
+        // The pauses are kept in Postgres as an audit trail. They are ended so they can't apply to a merchant
+        // that is later given the same ID
+        let paused_pincodes = utils::normalize_pincodes(pauses::resume_pauses(conn, merchant_id, &[]).await?.into_iter().map(|pause| pause.pincode).collect());
+
+        // The merchant_pincodes, rule, exclusion, schedule, capacity and store rows are removed along with the merchant (ON DELETE CASCADE)
+        if diesel::delete(merchants::table.find(merchant_id)).execute(conn).await? == 0 {
+            return Ok(None);
+        }
 
-            for deleted_pincode in &vec_of_pincodes {
-                delete_merchant_serviceability(&redis.client, deleted_pincode, merchant_id).unwrap();
+        let mut update = index::IndexUpdate::default();
+        update.add(merchant_id, move |con, pipe, version| {
+            remove_serviceability_commands(pipe, version, merchant_id, &serviced_pincodes);
+            for rule in &merchant_rules {
+                rules::remove_rule_commands(pipe, version, rule);
             }
+            stores::remove_store_commands(pipe, version, merchant_id, &store_pincodes, &[]);
+            exclusions::remove_exclusion_commands(pipe, version, merchant_id, &excluded);
+            pauses::remove_pause_commands(pipe, version, merchant_id, &paused_pincodes);
+            schedules::add_schedule_commands(pipe, version, merchant_id, &schedules::Schedule::default());
+            capacity::add_limit_commands(pipe, version, merchant_id, &[]);
+            remove_merchant_details_commands(con, pipe, version, merchant_id)
+        });
 
-            match diesel::delete(merchants::table.filter(merchants::id.eq(merchant_id))).execute(&mut db).await {
-                Ok(rows) if rows > 0 => Json(utils::ApiResponse {
-                        status: utils::ApiResponseStatus::Success,
-                        data: json!({"ONDC_merchant_id": format!("{}", merchant_id), "message": "Merchant Information Deleted!"}).into(),
-                    }),
-                _ => Json(utils::ApiResponse {
-                    status: utils::ApiResponseStatus::Error,
-                    data: json!({"message": "Failed to delete the merchant"}).into(),
-                })
-            }
+        Ok(Some(update))
+    }.scope_boxed()).await;
+
+    match result {
+        Ok(Some(update)) => {
+            update.apply(&redis.client);
+            Json(utils::ApiResponse {
+                status: utils::ApiResponseStatus::Success,
+                data: json!({"ONDC_merchant_id": format!("{}", merchant_id), "message": "Merchant Information Deleted!"}).into(),
+            })
         }
+        Ok(None) => Json(utils::ApiResponse {
+            status: utils::ApiResponseStatus::Error,
+            data: json!({"message": format!("Merchant {} not found", merchant_id)}).into(),
+        }),
         Err(err) => {
-            // Handle the error from get_serviced_pincodes
-            eprintln!("Error fetching serviced pincodes: {}", err);
+            eprintln!("Failed to delete merchant {}: {}", merchant_id, err);
             Json(utils::ApiResponse {
                 status: utils::ApiResponseStatus::Error,
-                data: json!({"message": format!("{}", err)}).into(),
+                data: json!({"message": "Failed to delete the merchant"}).into(),
             })
         }
     }
 }
 
-fn delete_merchant_serviceability(redis_client: &redis::Client, pincode: &str, merchant_id: i32) -> redis::RedisResult<()> {
+fn delete_merchant_serviceability(redis_client: &redis::Client, merchant_id: i32, pincodes: &[String]) -> redis::RedisResult<()> {
+    if pincodes.is_empty() {
+        return Ok(());
+    }
+
+    let mut con = redis_client.get_connection()?;
+    index::write(&mut con, &[merchant_id], |_, pipe, version| {
+        remove_serviceability_commands(pipe, version, merchant_id, pincodes);
+        Ok(())
+    })
+}
+
+// Removes the specific merchant from the pincode-to-merchant mapping, the pincode from the merchant's set and the terms
+fn remove_serviceability_commands(pipe: &mut redis::Pipeline, version: u64, merchant_id: i32, pincodes: &[String]) {
+    for pincode in pincodes {
+        pipe.srem(index::pincode_key(version, pincode), merchant_id).ignore();
+        pipe.srem(index::merchant_pincodes_key(version, merchant_id), pincode).ignore();
+        pipe.hdel(index::terms_key(version, pincode), merchant_id).ignore();
+    }
+}
+
+fn delete_delivery_terms(redis_client: &redis::Client, merchant_id: i32, pincodes: &[String]) -> redis::RedisResult<()> {
+    if pincodes.is_empty() {
+        return Ok(());
+    }
+
     let mut con = redis_client.get_connection()?;
+    index::write(&mut con, &[merchant_id], |_, pipe, version| {
+        remove_terms_commands(pipe, version, merchant_id, pincodes);
+        Ok(())
+    })
+}
+
+fn remove_terms_commands(pipe: &mut redis::Pipeline, version: u64, merchant_id: i32, pincodes: &[String]) {
+    for pincode in pincodes {
+        pipe.hdel(index::terms_key(version, pincode), merchant_id).ignore();
+    }
+}
+
+fn remove_merchant_details_commands(con: &mut redis::Connection, pipe: &mut redis::Pipeline, version: u64, merchant_id: i32) -> redis::RedisResult<()> {
+    let category: Option<String> = con.hget(index::merchant_key(version, merchant_id), "business_category")?;
+    if let Some(category) = category {
+        pipe.srem(index::category_key(version, &category), merchant_id).ignore();
+    }
 
-    // Remove the specific merchant from the pincode-to-merchant mapping
-    con.srem(format!("pincodes:{}", pincode), merchant_id)?;
+    pipe.del(index::merchant_key(version, merchant_id)).ignore();
+    pipe.zrem(index::geo_key(version), merchant_id).ignore();
+    pipe.zrem(index::radius_key(version), merchant_id).ignore();
 
     Ok(())
 }
@@ -572,5 +1499,8 @@ fn rocket() -> _ {
         .attach(cors::cors())
         .manage(redis_client)
         .attach(stage())
-        .mount("/", routes![add_merchant, get_merchants_by_pincode, get_merchant_info, get_all_merchants, update_merchant_info, add_pincodes, delete_merchant_serviceability_for_pincode, delete_merchant, upload_csv])
+        .attach(reconcile::stage())
+        .attach(index::stage())
+        .attach(imports::stage())
+        .mount("/", routes![add_merchant, get_merchants_by_pincode, get_merchant_info, get_merchant_serviceability, get_all_merchants, export::export_merchants, update_merchant_info, add_pincodes, delete_merchant_serviceability_for_pincode, coverage::import_coverage, delete_merchant, ingest::upload_csv, imports::create_import, imports::get_import, imports::get_import_errors, reconcile::reconcile_index, index::reindex, directory::import_directory, directory::get_directory, exclusions::get_exclusions, exclusions::add_merchant_exclusions, exclusions::delete_merchant_exclusions, schedules::get_schedule, schedules::set_schedule, pauses::pause_merchant, pauses::resume_merchant, pauses::get_pauses, capacity::get_capacity, capacity::set_capacity, capacity::consume_capacity, stores::get_stores, stores::add_store, stores::update_store, stores::delete_store, stores::add_store_pincodes, stores::delete_store_serviceability])
 }
\ No newline at end of file
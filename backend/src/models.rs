@@ -2,7 +2,7 @@ use rocket_db_pools::diesel::prelude::*;
 use rocket::serde::{Serialize, Deserialize};
 
 // Model: User struct with id, name, email
-#[derive(Debug, Serialize, Deserialize, Queryable, Insertable, Selectable)]
+#[derive(Debug, PartialEq, Serialize, Deserialize, Queryable, Insertable, Selectable)]
 #[diesel(table_name = crate::schema::merchants)]
 pub struct Merchant {
     pub id: i32,
@@ -10,14 +10,234 @@ pub struct Merchant {
     pub business_category: String,
     pub phone_number: String,
     pub email: String,
-    pub pincodes_serviced: String,
+    // Store location and delivery radius of hyperlocal merchants, all set or all None
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    pub delivery_radius_km: Option<f64>,
+    // Seller ID or GSTIN of the merchant, unique across merchants
+    pub external_ref: Option<String>,
+}
+
+// One row per pincode serviced by a merchant
+#[derive(Debug, Serialize, Deserialize, Queryable, QueryableByName, Insertable, Selectable)]
+#[diesel(table_name = crate::schema::merchant_pincodes)]
+pub struct MerchantPincode {
+    pub merchant_id: i32,
+    pub pincode: String,
+}
+
+// Delivery terms of a merchant for a pincode, amounts are in paise. Unset terms are left out
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, Queryable, Insertable, Selectable, AsChangeset)]
+#[diesel(table_name = crate::schema::merchant_pincodes)]
+pub struct DeliveryTerms {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delivery_time_hours: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delivery_fee_paise: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_order_value_paise: Option<i32>,
+}
+
+impl DeliveryTerms {
+    pub fn is_empty(&self) -> bool {
+        self.delivery_time_hours.is_none() && self.delivery_fee_paise.is_none() && self.min_order_value_paise.is_none()
+    }
+}
+
+// Pincode serviced by a merchant along with its delivery terms
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Insertable, Selectable)]
+#[diesel(table_name = crate::schema::merchant_pincodes)]
+pub struct ServicedPincode {
+    pub merchant_id: i32,
+    pub pincode: String,
+    #[diesel(embed)]
+    #[serde(flatten)]
+    pub terms: DeliveryTerms,
 }
 
+// Pincode a merchant doesn't service even if one of its regions or rules covers it
 #[derive(Debug, Serialize, Deserialize, Queryable, Insertable, Selectable)]
+#[diesel(table_name = crate::schema::merchant_pincode_exclusions)]
+pub struct MerchantPincodeExclusion {
+    pub merchant_id: i32,
+    pub pincode: String,
+}
+
+// The location and external reference are left unchanged when omitted
+#[derive(Debug, Serialize, Deserialize, Queryable, Insertable, Selectable, AsChangeset)]
 #[diesel(table_name = crate::schema::merchants)]
 pub struct UpdateMerchantData {
     pub name: String,
     pub business_category: String,
     pub phone_number: String,
     pub email: String,
-}
\ No newline at end of file
+    #[serde(default)]
+    pub latitude: Option<f64>,
+    #[serde(default)]
+    pub longitude: Option<f64>,
+    #[serde(default)]
+    pub delivery_radius_km: Option<f64>,
+    #[serde(default)]
+    pub external_ref: Option<String>,
+}
+
+// District or state serviced by a merchant as a whole, region_type is "district" or "state"
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Insertable, Selectable)]
+#[diesel(table_name = crate::schema::merchant_regions)]
+pub struct MerchantRegion {
+    pub merchant_id: i32,
+    pub region_type: String,
+    pub name: String,
+}
+
+// Prefix ("56") or numeric range ("560001-560100") of pincodes serviced by a merchant, rule_type is "prefix" or "range"
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Insertable, Selectable)]
+#[diesel(table_name = crate::schema::merchant_pincode_rules)]
+pub struct MerchantPincodeRule {
+    pub merchant_id: i32,
+    pub rule_type: String,
+    pub value: String,
+}
+
+// Opening window of a merchant on a weekday (1 is Monday, 7 is Sunday), in India Standard Time.
+// A closing time at or before the opening time runs past midnight
+#[derive(Debug, Clone, Queryable, Insertable, Selectable)]
+#[diesel(table_name = crate::schema::merchant_operating_hours)]
+pub struct MerchantOperatingHours {
+    pub merchant_id: i32,
+    pub weekday: i16,
+    pub opens_at: rocket::time::Time,
+    pub closes_at: rocket::time::Time,
+}
+
+// Day a merchant is closed
+#[derive(Debug, Clone, Queryable, Insertable, Selectable)]
+#[diesel(table_name = crate::schema::merchant_holidays)]
+pub struct MerchantHoliday {
+    pub merchant_id: i32,
+    pub holiday: rocket::time::Date,
+    pub name: Option<String>,
+}
+
+// Temporary pause of a merchant for a pincode, kept as an audit trail
+#[derive(Debug, Clone, Queryable, Selectable)]
+#[diesel(table_name = crate::schema::merchant_pauses)]
+pub struct MerchantPause {
+    pub id: i32,
+    pub merchant_id: i32,
+    pub pincode: String,
+    pub reason: Option<String>,
+    pub paused_at: rocket::time::OffsetDateTime,
+    pub expires_at: rocket::time::OffsetDateTime,
+    pub resumed_at: Option<rocket::time::OffsetDateTime>,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = crate::schema::merchant_pauses)]
+pub struct NewMerchantPause {
+    pub merchant_id: i32,
+    pub pincode: String,
+    pub reason: Option<String>,
+    pub paused_at: rocket::time::OffsetDateTime,
+    pub expires_at: rocket::time::OffsetDateTime,
+}
+
+// Daily order capacity of a merchant across all pincodes
+#[derive(Debug, Clone, Queryable, Insertable, Selectable)]
+#[diesel(table_name = crate::schema::merchant_capacities)]
+pub struct MerchantCapacity {
+    pub merchant_id: i32,
+    pub daily_capacity: i32,
+}
+
+// Daily order capacity of a merchant for a pincode
+#[derive(Debug, Clone, Queryable, Insertable, Selectable)]
+#[diesel(table_name = crate::schema::merchant_pincode_capacities)]
+pub struct MerchantPincodeCapacity {
+    pub merchant_id: i32,
+    pub pincode: String,
+    pub daily_capacity: i32,
+}
+
+// Outlet of a merchant. Contact details left empty fall back to the merchant's
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Selectable)]
+#[diesel(table_name = crate::schema::stores)]
+pub struct Store {
+    pub id: i32,
+    pub merchant_id: i32,
+    pub name: String,
+    pub address: String,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    pub phone_number: Option<String>,
+    pub email: Option<String>,
+}
+
+// Details of a store as created and updated, fields left out are cleared on update
+#[derive(Debug, Clone, Insertable, AsChangeset)]
+#[diesel(table_name = crate::schema::stores, treat_none_as_null = true)]
+pub struct StoreDetails {
+    pub name: String,
+    pub address: String,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    pub phone_number: Option<String>,
+    pub email: Option<String>,
+}
+
+// One row per pincode serviced by a store
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Insertable, Selectable)]
+#[diesel(table_name = crate::schema::store_pincodes)]
+pub struct StorePincode {
+    pub store_id: i32,
+    pub pincode: String,
+}
+
+// Post office entry of the India Post pincode directory
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Insertable, Selectable)]
+#[diesel(table_name = crate::schema::pincode_directory)]
+pub struct PincodeDirectoryEntry {
+    pub pincode: String,
+    pub office_name: String,
+    pub district: String,
+    pub state: String,
+    pub region: String,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+}
+
+// Background import of a merchant CSV file, without the file itself
+#[derive(Debug, Clone, Queryable, Selectable)]
+#[diesel(table_name = crate::schema::import_jobs)]
+pub struct ImportJob {
+    pub id: i32,
+    pub status: String,
+    pub file_name: Option<String>,
+    pub allow_unknown_pincodes: bool,
+    pub total_rows: i32,
+    pub processed_rows: i32,
+    pub accepted_rows: i32,
+    pub rejected_rows: i32,
+    pub error: Option<String>,
+    pub created_at: rocket::time::OffsetDateTime,
+    pub started_at: Option<rocket::time::OffsetDateTime>,
+    pub finished_at: Option<rocket::time::OffsetDateTime>,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = crate::schema::import_jobs)]
+pub struct NewImportJob {
+    pub file_name: Option<String>,
+    pub csv_data: Vec<u8>,
+    pub allow_unknown_pincodes: bool,
+}
+
+// Reason a row of an import was rejected
+#[derive(Debug, Clone, Queryable, Insertable, Selectable)]
+#[diesel(table_name = crate::schema::import_job_errors)]
+pub struct ImportJobError {
+    pub job_id: i32,
+    pub line: i64,
+    pub field: String,
+    pub message: String,
+}
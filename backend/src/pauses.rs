@@ -0,0 +1,338 @@
+use rocket::serde::json::{Json, json};
+use rocket::serde::Serialize;
+use rocket::time::format_description::well_known::Rfc3339;
+use rocket::time::OffsetDateTime;
+use rocket::State;
+use rocket_db_pools::Connection;
+use rocket_db_pools::diesel::{AsyncPgConnection, prelude::*};
+use rocket_db_pools::diesel::scoped_futures::ScopedFutureExt;
+
+use crate::{index, models, utils, validation, Db, RedisClient};
+
+// Removes the merchants whose pause marker has expired from the paused set of a pincode.
+// KEYS[1] is the paused set and ARGV[1] the prefix of the pincode's pause markers
+const EXPIRE_PAUSES_SCRIPT: &str = r"
+local expired = 0
+for _, merchant_id in ipairs(redis.call('SMEMBERS', KEYS[1])) do
+    if redis.call('EXISTS', ARGV[1] .. merchant_id) == 0 then
+        redis.call('SREM', KEYS[1], merchant_id)
+        expired = expired + 1
+    end
+end
+return expired
+";
+
+// Pause as returned by the API, with RFC 3339 timestamps
+#[derive(Debug, Serialize)]
+pub struct PauseEntry {
+    pub id: i32,
+    pub pincode: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    pub paused_at: String,
+    pub expires_at: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resumed_at: Option<String>,
+    pub active: bool,
+}
+
+fn format_timestamp(timestamp: OffsetDateTime) -> String {
+    timestamp.format(&Rfc3339).unwrap_or_default()
+}
+
+fn pause_entry(pause: &models::MerchantPause, now: OffsetDateTime) -> PauseEntry {
+    PauseEntry {
+        id: pause.id,
+        pincode: pause.pincode.clone(),
+        reason: pause.reason.clone(),
+        paused_at: format_timestamp(pause.paused_at),
+        expires_at: format_timestamp(pause.expires_at),
+        resumed_at: pause.resumed_at.map(format_timestamp),
+        active: pause.resumed_at.is_none() && pause.expires_at > now,
+    }
+}
+
+// Pauses of the merchants that are neither expired nor lifted, oldest first so later pauses win
+pub async fn load_active_pauses(db: &mut AsyncPgConnection, merchant_ids: &[i32]) -> QueryResult<Vec<models::MerchantPause>> {
+    use crate::schema::merchant_pauses;
+
+    merchant_pauses::table
+        .filter(merchant_pauses::merchant_id.eq_any(merchant_ids))
+        .filter(merchant_pauses::resumed_at.is_null())
+        .filter(merchant_pauses::expires_at.gt(OffsetDateTime::now_utc()))
+        .select(models::MerchantPause::as_select())
+        .order(merchant_pauses::id.asc())
+        .load(db)
+        .await
+}
+
+// Ends the active pauses of the merchant for the pincodes, or for every pincode when none are given
+pub async fn resume_pauses(db: &mut AsyncPgConnection, merchant_id: i32, pincodes: &[String]) -> QueryResult<Vec<models::MerchantPause>> {
+    use crate::schema::merchant_pauses;
+
+    let now = OffsetDateTime::now_utc();
+    let mut query = diesel::update(merchant_pauses::table)
+        .filter(merchant_pauses::merchant_id.eq(merchant_id))
+        .filter(merchant_pauses::resumed_at.is_null())
+        .filter(merchant_pauses::expires_at.gt(now))
+        .into_boxed();
+    if !pincodes.is_empty() {
+        query = query.filter(merchant_pauses::pincode.eq_any(pincodes));
+    }
+
+    query
+        .set(merchant_pauses::resumed_at.eq(now))
+        .returning(models::MerchantPause::as_returning())
+        .get_results(db)
+        .await
+}
+
+// Adds the merchant to the paused set of the pincode along with a marker expiring with the pause
+pub fn add_pause_commands(pipe: &mut redis::Pipeline, version: u64, pause: &models::MerchantPause) {
+    let marker_key = index::pause_marker_key(version, &pause.pincode, pause.merchant_id);
+
+    pipe.sadd(index::paused_key(version, &pause.pincode), pause.merchant_id).ignore();
+    pipe.set(&marker_key, pause.id).ignore();
+    pipe.expire_at(&marker_key, pause.expires_at.unix_timestamp()).ignore();
+}
+
+pub fn remove_pause_commands(pipe: &mut redis::Pipeline, version: u64, merchant_id: i32, pincodes: &[String]) {
+    for pincode in pincodes {
+        pipe.srem(index::paused_key(version, pincode), merchant_id).ignore();
+        pipe.del(index::pause_marker_key(version, pincode, merchant_id)).ignore();
+    }
+}
+
+// Drops the expired pauses of the pincodes from their paused sets, so lookups can subtract the sets directly
+pub fn expire_pauses(redis_client: &redis::Client, pincodes: &[String]) -> redis::RedisResult<()> {
+    let mut con = redis_client.get_connection()?;
+    let version = index::current_version(&mut con)?;
+    let script = redis::Script::new(EXPIRE_PAUSES_SCRIPT);
+
+    for pincode in pincodes {
+        script
+            .key(index::paused_key(version, pincode))
+            .arg(index::pause_marker_prefix(version, pincode))
+            .invoke::<()>(&mut con)?;
+    }
+
+    Ok(())
+}
+
+// Pauses the merchant for the pincodes until the given time, without touching its serviceability.
+// Pausing a pincode that is already paused replaces the earlier pause, which is recorded as resumed
+#[post("/merchant/<merchant_id>/pause", format = "json", data = "<pause>")]
+pub(crate) async fn pause_merchant(redis: &State<RedisClient>, mut db: Connection<Db>, pause: Json<utils::PauseRequest>, merchant_id: i32) -> Json<utils::ApiResponse> {
+    if let Err(response) = crate::merchant_exists(&mut db, merchant_id).await {
+        return Json(response);
+    }
+
+    let mut pause = pause.into_inner();
+    pause.pincodes = utils::normalize_pincodes(pause.pincodes);
+    let now = OffsetDateTime::now_utc();
+    let expires_at = match validation::validate_pause(&pause, now) {
+        Ok(expires_at) => expires_at,
+        Err(errors) => return Json(validation::error_response(errors)),
+    };
+
+    let rows: Vec<models::NewMerchantPause> = pause
+        .pincodes
+        .iter()
+        .map(|pincode| models::NewMerchantPause {
+            merchant_id,
+            pincode: pincode.clone(),
+            reason: pause.reason.clone(),
+            paused_at: now,
+            expires_at,
+        })
+        .collect();
+    let pincodes = pause.pincodes;
+
+    let result = db.transaction::<_, utils::StoreError, _>(|conn| async move {
+        use crate::schema::merchant_pauses;
+
+        resume_pauses(conn, merchant_id, &pincodes).await?;
+        let pauses = diesel::insert_into(merchant_pauses::table)
+            .values(&rows)
+            .returning(models::MerchantPause::as_returning())
+            .get_results(conn)
+            .await?;
+
+        let mut update = index::IndexUpdate::default();
+        let added = pauses.clone();
+        update.add(merchant_id, move |_, pipe, version| {
+            for pause in &added {
+                add_pause_commands(pipe, version, pause);
+            }
+            Ok(())
+        });
+
+        Ok((pauses, update))
+    }.scope_boxed()).await;
+
+    match result {
+        Ok((pauses, update)) => {
+            update.apply(&redis.client);
+            Json(utils::ApiResponse {
+                status: utils::ApiResponseStatus::Success,
+                data: json!({
+                    "ONDC_merchant_id": format!("{}", merchant_id),
+                    "pauses": pauses.iter().map(|pause| pause_entry(pause, now)).collect::<Vec<_>>(),
+                    "message": "Merchant paused"
+                }).into(),
+            })
+        }
+        Err(err) => {
+            eprintln!("Failed to pause merchant {}: {}", merchant_id, err);
+            Json(utils::ApiResponse {
+                status: utils::ApiResponseStatus::Error,
+                data: json!({"message": format!("Failed to pause the merchant: {}", err)}).into(),
+            })
+        }
+    }
+}
+
+// Lifts the active pauses of the merchant for the pincodes before they expire, or all of them when no pincodes are sent
+#[delete("/merchant/<merchant_id>/pause", format = "json", data = "<pincode_data>")]
+pub(crate) async fn resume_merchant(redis: &State<RedisClient>, mut db: Connection<Db>, pincode_data: Json<utils::Pincodes>, merchant_id: i32) -> Json<utils::ApiResponse> {
+    if let Err(response) = crate::merchant_exists(&mut db, merchant_id).await {
+        return Json(response);
+    }
+
+    let pincodes = utils::normalize_pincodes(pincode_data.into_inner().pincodes);
+    let now = OffsetDateTime::now_utc();
+
+    let result = db.transaction::<_, utils::StoreError, _>(|conn| async move {
+        let resumed = resume_pauses(conn, merchant_id, &pincodes).await?;
+
+        let mut update = index::IndexUpdate::default();
+        let resumed_pincodes: Vec<String> = utils::normalize_pincodes(resumed.iter().map(|pause| pause.pincode.clone()).collect());
+        update.add(merchant_id, move |_, pipe, version| {
+            remove_pause_commands(pipe, version, merchant_id, &resumed_pincodes);
+            Ok(())
+        });
+
+        Ok((resumed, update))
+    }.scope_boxed()).await;
+
+    match result {
+        Ok((resumed, _)) if resumed.is_empty() => Json(utils::ApiResponse {
+            status: utils::ApiResponseStatus::Error,
+            data: json!({"message": "No active pauses were lifted"}).into(),
+        }),
+        Ok((resumed, update)) => {
+            update.apply(&redis.client);
+            Json(utils::ApiResponse {
+                status: utils::ApiResponseStatus::Success,
+                data: json!({
+                    "ONDC_merchant_id": format!("{}", merchant_id),
+                    "resumed": resumed.iter().map(|pause| pause_entry(pause, now)).collect::<Vec<_>>(),
+                    "message": "Merchant resumed"
+                }).into(),
+            })
+        }
+        Err(err) => {
+            eprintln!("Failed to resume merchant {}: {}", merchant_id, err);
+            Json(utils::ApiResponse {
+                status: utils::ApiResponseStatus::Error,
+                data: json!({"message": format!("Failed to resume the merchant: {}", err)}).into(),
+            })
+        }
+    }
+}
+
+// Audit trail of the merchant's pauses, newest first
+#[get("/merchant/<merchant_id>/pauses")]
+pub(crate) async fn get_pauses(mut db: Connection<Db>, merchant_id: i32) -> Json<utils::ApiResponse> {
+    use crate::schema::merchant_pauses;
+
+    if let Err(response) = crate::merchant_exists(&mut db, merchant_id).await {
+        return Json(response);
+    }
+
+    let pauses = merchant_pauses::table
+        .filter(merchant_pauses::merchant_id.eq(merchant_id))
+        .select(models::MerchantPause::as_select())
+        .order(merchant_pauses::id.desc())
+        .load(&mut db)
+        .await;
+
+    match pauses {
+        Ok(pauses) => {
+            let now = OffsetDateTime::now_utc();
+            Json(utils::ApiResponse {
+                status: utils::ApiResponseStatus::Success,
+                data: json!({
+                    "ONDC_merchant_id": format!("{}", merchant_id),
+                    "pauses": pauses.iter().map(|pause| pause_entry(pause, now)).collect::<Vec<_>>()
+                }).into(),
+            })
+        }
+        Err(err) => Json(utils::ApiResponse {
+            status: utils::ApiResponseStatus::Error,
+            data: json!({"message": format!("Failed to read the pauses: {:?}", err)}).into(),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::time::Duration;
+
+    use super::*;
+
+    fn pause(expires_in: Duration, resumed: bool) -> models::MerchantPause {
+        let paused_at = OffsetDateTime::from_unix_timestamp(1_700_000_000).unwrap();
+        models::MerchantPause {
+            id: 4,
+            merchant_id: 7,
+            pincode: "560001".to_string(),
+            reason: None,
+            paused_at,
+            expires_at: paused_at + expires_in,
+            resumed_at: resumed.then_some(paused_at),
+        }
+    }
+
+    #[test]
+    fn pauses_are_active_until_they_expire_or_are_resumed() {
+        let now = OffsetDateTime::from_unix_timestamp(1_700_000_000).unwrap() + Duration::hours(1);
+
+        assert!(pause_entry(&pause(Duration::hours(2), false), now).active);
+        assert!(!pause_entry(&pause(Duration::hours(1), false), now).active);
+        assert!(!pause_entry(&pause(Duration::hours(2), true), now).active);
+    }
+
+    #[test]
+    fn timestamps_are_rfc_3339() {
+        let entry = pause_entry(&pause(Duration::hours(2), true), OffsetDateTime::now_utc());
+
+        assert_eq!(entry.paused_at, "2023-11-14T22:13:20Z");
+        assert_eq!(entry.expires_at, "2023-11-15T00:13:20Z");
+        assert_eq!(entry.resumed_at.as_deref(), Some("2023-11-14T22:13:20Z"));
+    }
+
+    #[test]
+    fn pause_markers_expire_with_the_pause() {
+        let pause = pause(Duration::hours(2), false);
+        let mut pipe = redis::pipe();
+        add_pause_commands(&mut pipe, 3, &pause);
+
+        assert_eq!(
+            index::pipeline_commands(&pipe),
+            vec![
+                vec!["SADD".to_string(), "v3:paused:560001".to_string(), "7".to_string()],
+                vec!["SET".to_string(), "v3:pause:560001:7".to_string(), "4".to_string()],
+                vec!["EXPIREAT".to_string(), "v3:pause:560001:7".to_string(), pause.expires_at.unix_timestamp().to_string()],
+            ],
+        );
+    }
+
+    #[test]
+    fn resumed_pauses_drop_the_set_member_and_the_marker() {
+        let mut pipe = redis::pipe();
+        remove_pause_commands(&mut pipe, 0, 7, &["560001".to_string()]);
+
+        assert_eq!(index::pipeline_commands(&pipe), vec![vec!["SREM", "paused:560001", "7"], vec!["DEL", "pause:560001:7"]]);
+    }
+}
@@ -0,0 +1,958 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::env;
+use std::time::Duration;
+
+use dotenvy::dotenv;
+use redis::Commands;
+use rocket::fairing::AdHoc;
+use rocket::serde::json::{Json, json};
+use rocket::serde::Serialize;
+use rocket::State;
+use rocket_db_pools::{Connection, Database};
+use rocket_db_pools::diesel::{AsyncPgConnection, prelude::*};
+
+use crate::{capacity, index, models, rules, schedules, stores, utils, Db, RedisClient};
+
+// Number of merchants read from Postgres and compared with Redis at a time
+const RECONCILE_BATCH_SIZE: i64 = 1000;
+
+// Number of merchant hashes and positions read from Redis per pipeline
+const READ_BATCH_SIZE: usize = 500;
+
+// Redis keeps geo positions to about 0.6 m, positions read back are compared with this tolerance in degrees
+const LOCATION_TOLERANCE: f64 = 1e-5;
+
+// Default interval between background reconciliation runs
+const DEFAULT_INTERVAL_SECS: u64 = 3600;
+
+type Entry = (i32, String);
+
+// Entries present in Postgres but missing from a Redis index, and entries in Redis that Postgres doesn't have
+#[derive(Debug, Serialize)]
+pub struct IndexDiff<T> {
+    pub missing: Vec<T>,
+    pub stale: Vec<T>,
+}
+
+impl<T> Default for IndexDiff<T> {
+    fn default() -> Self {
+        IndexDiff { missing: Vec::new(), stale: Vec::new() }
+    }
+}
+
+impl<T> IndexDiff<T> {
+    fn is_empty(&self) -> bool {
+        self.missing.is_empty() && self.stale.is_empty()
+    }
+
+    fn extend(&mut self, other: IndexDiff<T>) {
+        self.missing.extend(other.missing);
+        self.stale.extend(other.stale);
+    }
+
+    fn merchant_ids(&self, merchant_id: fn(&T) -> i32) -> impl Iterator<Item = i32> + '_ {
+        self.missing.iter().chain(&self.stale).map(merchant_id)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct MerchantCategory {
+    pub merchant_id: i32,
+    pub category: String,
+}
+
+// Location of a merchant in the geo index, what either of its sorted sets lacks is None
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MerchantLocation {
+    pub merchant_id: i32,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    pub delivery_radius_km: Option<f64>,
+}
+
+// Schedule of a merchant as encoded in Redis
+#[derive(Debug, Serialize)]
+pub struct MerchantScheduleEntry {
+    pub merchant_id: i32,
+    pub schedule: String,
+}
+
+// Pincode serviced by a store of a merchant
+#[derive(Debug, Serialize)]
+pub struct StorePincodeEntry {
+    pub merchant_id: i32,
+    pub store_id: i32,
+    pub pincode: String,
+}
+
+// Daily capacity of a merchant, scope is "total" or a pincode
+#[derive(Debug, Serialize)]
+pub struct MerchantCapacityEntry {
+    pub merchant_id: i32,
+    pub scope: String,
+    pub daily_capacity: String,
+}
+
+// Merchants whose hash is missing or differs from Postgres, hashes of merchants Postgres doesn't have,
+// and the number of JSON blobs left in the legacy `merchants` set
+#[derive(Debug, Default, Serialize)]
+pub struct MerchantDetailsDiff {
+    pub outdated: Vec<i32>,
+    pub stale: Vec<i32>,
+    pub legacy_entries: usize,
+}
+
+// Differences between Postgres and the current version of the Redis index
+#[derive(Debug, Default, Serialize)]
+pub struct Discrepancies {
+    pub pincode_index: IndexDiff<models::MerchantPincode>,
+    pub merchant_pincode_index: IndexDiff<models::MerchantPincode>,
+    pub category_index: IndexDiff<MerchantCategory>,
+    pub location_index: IndexDiff<MerchantLocation>,
+    pub rule_index: IndexDiff<models::MerchantPincodeRule>,
+    pub exclusion_index: IndexDiff<models::MerchantPincode>,
+    pub terms_index: IndexDiff<models::ServicedPincode>,
+    pub schedule_index: IndexDiff<MerchantScheduleEntry>,
+    pub capacity_index: IndexDiff<MerchantCapacityEntry>,
+    pub store_index: IndexDiff<StorePincodeEntry>,
+    pub merchant_details: MerchantDetailsDiff,
+}
+
+impl Discrepancies {
+    pub fn is_consistent(&self) -> bool {
+        self.pincode_index.is_empty()
+            && self.merchant_pincode_index.is_empty()
+            && self.category_index.is_empty()
+            && self.location_index.is_empty()
+            && self.rule_index.is_empty()
+            && self.exclusion_index.is_empty()
+            && self.terms_index.is_empty()
+            && self.schedule_index.is_empty()
+            && self.capacity_index.is_empty()
+            && self.store_index.is_empty()
+            && self.merchant_details.outdated.is_empty()
+            && self.merchant_details.stale.is_empty()
+            && self.merchant_details.legacy_entries == 0
+    }
+
+    // Merchants with a discrepancy in any of the indexes
+    fn merchant_ids(&self) -> BTreeSet<i32> {
+        let mut merchant_ids = BTreeSet::new();
+        merchant_ids.extend(self.pincode_index.merchant_ids(|entry| entry.merchant_id));
+        merchant_ids.extend(self.merchant_pincode_index.merchant_ids(|entry| entry.merchant_id));
+        merchant_ids.extend(self.category_index.merchant_ids(|entry| entry.merchant_id));
+        merchant_ids.extend(self.location_index.merchant_ids(|entry| entry.merchant_id));
+        merchant_ids.extend(self.rule_index.merchant_ids(|entry| entry.merchant_id));
+        merchant_ids.extend(self.exclusion_index.merchant_ids(|entry| entry.merchant_id));
+        merchant_ids.extend(self.terms_index.merchant_ids(|entry| entry.merchant_id));
+        merchant_ids.extend(self.schedule_index.merchant_ids(|entry| entry.merchant_id));
+        merchant_ids.extend(self.capacity_index.merchant_ids(|entry| entry.merchant_id));
+        merchant_ids.extend(self.store_index.merchant_ids(|entry| entry.merchant_id));
+        merchant_ids.extend(&self.merchant_details.outdated);
+        merchant_ids.extend(&self.merchant_details.stale);
+        merchant_ids
+    }
+
+    fn extend(&mut self, other: Discrepancies) {
+        self.pincode_index.extend(other.pincode_index);
+        self.merchant_pincode_index.extend(other.merchant_pincode_index);
+        self.category_index.extend(other.category_index);
+        self.location_index.extend(other.location_index);
+        self.rule_index.extend(other.rule_index);
+        self.exclusion_index.extend(other.exclusion_index);
+        self.terms_index.extend(other.terms_index);
+        self.schedule_index.extend(other.schedule_index);
+        self.capacity_index.extend(other.capacity_index);
+        self.store_index.extend(other.store_index);
+        self.merchant_details.outdated.extend(other.merchant_details.outdated);
+        self.merchant_details.stale.extend(other.merchant_details.stale);
+        self.merchant_details.legacy_entries += other.merchant_details.legacy_entries;
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReconcileReport {
+    pub dry_run: bool,
+    pub merchants_checked: usize,
+    pub pincodes_checked: usize,
+    #[serde(flatten)]
+    pub discrepancies: Discrepancies,
+    pub repaired: bool,
+}
+
+fn to_pincode_entry((merchant_id, pincode): Entry) -> models::MerchantPincode {
+    models::MerchantPincode { merchant_id, pincode }
+}
+
+fn to_category_entry((merchant_id, category): Entry) -> MerchantCategory {
+    MerchantCategory { merchant_id, category }
+}
+
+// Rules are compared as (merchant_id, "<rule_type>:<value>")
+fn to_rule_entry((merchant_id, rule): Entry) -> models::MerchantPincodeRule {
+    let (rule_type, value) = rule.split_once(':').unwrap_or_default();
+    models::MerchantPincodeRule { merchant_id, rule_type: rule_type.to_string(), value: value.to_string() }
+}
+
+fn to_schedule_entry((merchant_id, schedule): Entry) -> MerchantScheduleEntry {
+    MerchantScheduleEntry { merchant_id, schedule }
+}
+
+// Capacity limits are compared as (merchant_id, "<scope>:<daily capacity>")
+fn to_capacity_entry((merchant_id, entry): Entry) -> MerchantCapacityEntry {
+    let (scope, daily_capacity) = entry.split_once(':').unwrap_or_default();
+    MerchantCapacityEntry { merchant_id, scope: scope.to_string(), daily_capacity: daily_capacity.to_string() }
+}
+
+// Store pincodes are compared as (merchant_id, "<store_id>:<pincode>")
+fn to_store_entry((merchant_id, entry): Entry) -> StorePincodeEntry {
+    let (store_id, pincode) = entry.split_once(':').unwrap_or_default();
+    StorePincodeEntry { merchant_id, store_id: store_id.parse().unwrap_or_default(), pincode: pincode.to_string() }
+}
+
+// Delivery terms are compared as (merchant_id, "<pincode>:<encoded terms>")
+fn to_terms_entry((merchant_id, entry): Entry) -> models::ServicedPincode {
+    let (pincode, terms) = entry.split_once(':').unwrap_or_default();
+    models::ServicedPincode {
+        merchant_id,
+        pincode: pincode.to_string(),
+        terms: index::decode_terms(terms).unwrap_or_default(),
+    }
+}
+
+fn diff<T>(expected: &BTreeSet<Entry>, actual: &BTreeSet<Entry>, to_entry: fn(Entry) -> T) -> IndexDiff<T> {
+    IndexDiff {
+        missing: expected.difference(actual).cloned().map(to_entry).collect(),
+        stale: actual.difference(expected).cloned().map(to_entry).collect(),
+    }
+}
+
+fn same_coordinate(expected: Option<f64>, actual: Option<f64>) -> bool {
+    match (expected, actual) {
+        (Some(expected), Some(actual)) => (expected - actual).abs() <= LOCATION_TOLERANCE,
+        (expected, actual) => expected.is_none() && actual.is_none(),
+    }
+}
+
+fn same_location(expected: &MerchantLocation, actual: &MerchantLocation) -> bool {
+    same_coordinate(expected.latitude, actual.latitude)
+        && same_coordinate(expected.longitude, actual.longitude)
+        && same_coordinate(expected.delivery_radius_km, actual.delivery_radius_km)
+}
+
+// A merchant whose location moved is both missing (where Postgres has it) and stale (where Redis has it)
+fn diff_locations(expected: &BTreeMap<i32, MerchantLocation>, actual: &BTreeMap<i32, MerchantLocation>) -> IndexDiff<MerchantLocation> {
+    let unmatched = |locations: &BTreeMap<i32, MerchantLocation>, others: &BTreeMap<i32, MerchantLocation>| -> Vec<MerchantLocation> {
+        locations
+            .values()
+            .filter(|location| !others.get(&location.merchant_id).is_some_and(|other| same_location(location, other)))
+            .cloned()
+            .collect()
+    };
+
+    IndexDiff {
+        missing: unmatched(expected, actual),
+        stale: unmatched(actual, expected),
+    }
+}
+
+// Reads every key matching the pattern and collects the (merchant_id, pincode) pairs stored in its set
+fn scan_index<F>(con: &mut redis::Connection, pattern: &str, parse: F) -> redis::RedisResult<BTreeSet<Entry>>
+where
+    F: Fn(&str, String) -> Option<Entry>,
+{
+    let keys: Vec<String> = con.scan_match(pattern)?.collect();
+    let mut entries = BTreeSet::new();
+
+    for key in keys {
+        let members: Vec<String> = con.smembers(&key)?;
+        entries.extend(members.into_iter().filter_map(|member| parse(&key, member)));
+    }
+
+    Ok(entries)
+}
+
+// Reads the positions and delivery radii of the geo index, keyed by merchant
+fn scan_locations(con: &mut redis::Connection, version: u64) -> redis::RedisResult<BTreeMap<i32, MerchantLocation>> {
+    let radii: Vec<(i32, f64)> = con.zrange_withscores(index::radius_key(version), 0, -1)?;
+    let located: Vec<i32> = con.zrange(index::geo_key(version), 0, -1)?;
+
+    let mut locations: BTreeMap<i32, MerchantLocation> = radii
+        .into_iter()
+        .map(|(merchant_id, delivery_radius_km)| {
+            (merchant_id, MerchantLocation { merchant_id, latitude: None, longitude: None, delivery_radius_km: Some(delivery_radius_km) })
+        })
+        .collect();
+
+    for batch in located.chunks(READ_BATCH_SIZE) {
+        let positions: Vec<Option<(f64, f64)>> = redis::cmd("GEOPOS").arg(index::geo_key(version)).arg(batch).query(con)?;
+
+        for (&merchant_id, position) in batch.iter().zip(positions) {
+            let location = locations
+                .entry(merchant_id)
+                .or_insert(MerchantLocation { merchant_id, latitude: None, longitude: None, delivery_radius_km: None });
+            if let Some((longitude, latitude)) = position {
+                location.latitude = Some(latitude);
+                location.longitude = Some(longitude);
+            }
+        }
+    }
+
+    Ok(locations)
+}
+
+// Rules found in any bucket of the rule index. Rules are added to and removed from all their buckets at once
+fn scan_rules(con: &mut redis::Connection, version: u64) -> redis::RedisResult<BTreeSet<Entry>> {
+    let keys: Vec<String> = con.scan_match(index::rules_pattern(version))?.collect();
+    let mut entries = BTreeSet::new();
+
+    for key in &keys {
+        let members: Vec<String> = con.zrange(key, 0, -1)?;
+        entries.extend(
+            members
+                .iter()
+                .filter_map(|member| index::parse_rule_member(member))
+                .map(|rule| (rule.merchant_id, format!("{}:{}", rule.rule_type, rule.value))),
+        );
+    }
+
+    Ok(entries)
+}
+
+fn scan_terms(con: &mut redis::Connection, version: u64) -> redis::RedisResult<BTreeSet<Entry>> {
+    let keys: Vec<String> = con.scan_match(index::terms_pattern(version))?.collect();
+    let mut entries = BTreeSet::new();
+
+    for key in keys {
+        let Some(pincode) = index::parse_terms_key(version, &key) else {
+            continue;
+        };
+        let terms: HashMap<String, String> = con.hgetall(&key)?;
+        entries.extend(
+            terms
+                .into_iter()
+                .filter_map(|(merchant_id, terms)| Some((merchant_id.parse().ok()?, format!("{}:{}", pincode, terms)))),
+        );
+    }
+
+    Ok(entries)
+}
+
+fn scan_schedules(con: &mut redis::Connection, version: u64) -> redis::RedisResult<BTreeSet<Entry>> {
+    let keys: Vec<String> = con.scan_match(index::schedule_pattern(version))?.collect();
+    let mut entries = BTreeSet::new();
+
+    for key in keys {
+        if let Some(merchant_id) = index::parse_schedule_key(version, &key) {
+            let schedule: Option<String> = con.get(&key)?;
+            entries.insert((merchant_id, schedule.unwrap_or_default()));
+        }
+    }
+
+    Ok(entries)
+}
+
+fn scan_capacities(con: &mut redis::Connection, version: u64) -> redis::RedisResult<BTreeSet<Entry>> {
+    let keys: Vec<String> = con.scan_match(index::capacity_pattern(version))?.collect();
+    let mut entries = BTreeSet::new();
+
+    for key in keys {
+        let Some(merchant_id) = index::parse_capacity_key(version, &key) else {
+            continue;
+        };
+        let limits: HashMap<String, String> = con.hgetall(&key)?;
+        entries.extend(limits.into_iter().map(|(scope, limit)| (merchant_id, format!("{}:{}", scope, limit))));
+    }
+
+    Ok(entries)
+}
+
+fn scan_stores(con: &mut redis::Connection, version: u64) -> redis::RedisResult<BTreeSet<Entry>> {
+    scan_index(con, &index::stores_pattern(version), |key, member| {
+        let (merchant_id, store_id) = index::parse_store_member(&member)?;
+        Some((merchant_id, format!("{}:{}", store_id, index::parse_stores_key(version, key)?)))
+    })
+}
+
+// Entries Postgres expects in the index for a set of merchants
+struct Expected {
+    merchants: Vec<models::Merchant>,
+    pincodes: BTreeSet<Entry>,
+    categories: BTreeSet<Entry>,
+    locations: BTreeMap<i32, MerchantLocation>,
+    rules: BTreeSet<Entry>,
+    exclusions: BTreeSet<Entry>,
+    terms: BTreeSet<Entry>,
+    schedules: BTreeSet<Entry>,
+    capacities: BTreeSet<Entry>,
+    stores: BTreeSet<Entry>,
+}
+
+async fn load_expected(db: &mut AsyncPgConnection, merchants: Vec<models::Merchant>) -> QueryResult<Expected> {
+    use crate::schema::{merchant_pincode_exclusions, merchant_pincode_rules, merchant_pincodes};
+
+    let merchant_ids: Vec<i32> = merchants.iter().map(|merchant| merchant.id).collect();
+
+    let pincode_rows = merchant_pincodes::table
+        .filter(merchant_pincodes::merchant_id.eq_any(&merchant_ids))
+        .select(models::ServicedPincode::as_select())
+        .load(db)
+        .await?;
+
+    let rule_rows = merchant_pincode_rules::table
+        .filter(merchant_pincode_rules::merchant_id.eq_any(&merchant_ids))
+        .select(models::MerchantPincodeRule::as_select())
+        .load(db)
+        .await?;
+
+    let exclusion_rows = merchant_pincode_exclusions::table
+        .filter(merchant_pincode_exclusions::merchant_id.eq_any(&merchant_ids))
+        .select((merchant_pincode_exclusions::merchant_id, merchant_pincode_exclusions::pincode))
+        .load::<Entry>(db)
+        .await?;
+
+    let store_rows = stores::load_store_pincodes(db, Some(&merchant_ids)).await?;
+    let merchant_schedules = schedules::load_schedules(db, Some(&merchant_ids)).await?;
+    let capacity_limits = capacity::load_limits(db, Some(&merchant_ids)).await?;
+
+    Ok(Expected {
+        // Merchants service the pincodes of their stores as well
+        pincodes: pincode_rows
+            .iter()
+            .map(|row| (row.merchant_id, row.pincode.clone()))
+            .chain(store_rows.iter().map(|(merchant_id, _, pincode)| (*merchant_id, pincode.clone())))
+            .collect(),
+        categories: merchants
+            .iter()
+            .map(|merchant| (merchant.id, utils::normalize_category(&merchant.business_category)))
+            .collect(),
+        locations: merchants
+            .iter()
+            .filter_map(|merchant| {
+                let (latitude, longitude, delivery_radius_km) = index::merchant_location(merchant)?;
+                Some((merchant.id, MerchantLocation {
+                    merchant_id: merchant.id,
+                    latitude: Some(latitude),
+                    longitude: Some(longitude),
+                    delivery_radius_km: Some(delivery_radius_km),
+                }))
+            })
+            .collect(),
+        rules: rule_rows
+            .into_iter()
+            .map(|rule| (rule.merchant_id, format!("{}:{}", rule.rule_type, rule.value)))
+            .collect(),
+        exclusions: exclusion_rows.into_iter().collect(),
+        terms: pincode_rows
+            .into_iter()
+            .filter(|row| !row.terms.is_empty())
+            .map(|row| (row.merchant_id, format!("{}:{}", row.pincode, index::encode_terms(&row.terms))))
+            .collect(),
+        schedules: merchant_schedules
+            .into_iter()
+            .map(|(merchant_id, schedule)| (merchant_id, schedule.encode()))
+            .collect(),
+        capacities: capacity_limits
+            .into_iter()
+            .flat_map(|(merchant_id, limits)| limits.into_iter().map(move |(scope, limit)| (merchant_id, format!("{}:{}", scope, limit))))
+            .collect(),
+        stores: store_rows
+            .into_iter()
+            .map(|(merchant_id, store_id, pincode)| (merchant_id, format!("{}:{}", store_id, pincode)))
+            .collect(),
+        merchants,
+    })
+}
+
+// Entries found in the current version of the Redis index
+#[derive(Default)]
+struct Indexed {
+    pincodes: BTreeSet<Entry>,
+    merchant_pincodes: BTreeSet<Entry>,
+    categories: BTreeSet<Entry>,
+    locations: BTreeMap<i32, MerchantLocation>,
+    rules: BTreeSet<Entry>,
+    exclusions: BTreeSet<Entry>,
+    terms: BTreeSet<Entry>,
+    schedules: BTreeSet<Entry>,
+    capacities: BTreeSet<Entry>,
+    stores: BTreeSet<Entry>,
+    // Merchants with a hash
+    merchants: BTreeSet<i32>,
+}
+
+// Entries of the merchants with an ID from `from` to `through`
+fn entries_between(entries: &BTreeSet<Entry>, from: i32, through: i32) -> BTreeSet<Entry> {
+    entries
+        .range((from, String::new())..)
+        .take_while(|(merchant_id, _)| *merchant_id <= through)
+        .cloned()
+        .collect()
+}
+
+impl Indexed {
+    fn scan(con: &mut redis::Connection, version: u64) -> redis::RedisResult<Indexed> {
+        let merchant_keys: Vec<String> = con.scan_match(index::merchant_pattern(version))?.collect();
+
+        Ok(Indexed {
+            pincodes: scan_index(con, &index::pincode_pattern(version), |key, member| {
+                Some((member.parse().ok()?, index::parse_pincode_key(version, key)?))
+            })?,
+            merchant_pincodes: scan_index(con, &index::merchant_pincodes_pattern(version), |key, member| {
+                Some((index::parse_merchant_pincodes_key(version, key)?, member))
+            })?,
+            categories: scan_index(con, &index::category_pattern(version), |key, member| {
+                Some((member.parse().ok()?, index::parse_category_key(version, key)?))
+            })?,
+            locations: scan_locations(con, version)?,
+            rules: scan_rules(con, version)?,
+            exclusions: scan_index(con, &index::excluded_pattern(version), |key, member| {
+                Some((member.parse().ok()?, index::parse_excluded_key(version, key)?))
+            })?,
+            terms: scan_terms(con, version)?,
+            schedules: scan_schedules(con, version)?,
+            capacities: scan_capacities(con, version)?,
+            stores: scan_stores(con, version)?,
+            merchants: merchant_keys.iter().filter_map(|key| index::parse_merchant_key(version, key)).collect(),
+        })
+    }
+
+    // Entries of the merchants with an ID from `from` to `through`
+    fn page(&self, from: i32, through: i32) -> Indexed {
+        Indexed {
+            pincodes: entries_between(&self.pincodes, from, through),
+            merchant_pincodes: entries_between(&self.merchant_pincodes, from, through),
+            categories: entries_between(&self.categories, from, through),
+            locations: self.locations.range(from..=through).map(|(merchant_id, location)| (*merchant_id, location.clone())).collect(),
+            rules: entries_between(&self.rules, from, through),
+            exclusions: entries_between(&self.exclusions, from, through),
+            terms: entries_between(&self.terms, from, through),
+            schedules: entries_between(&self.schedules, from, through),
+            capacities: entries_between(&self.capacities, from, through),
+            stores: entries_between(&self.stores, from, through),
+            merchants: self.merchants.range(from..=through).copied().collect(),
+        }
+    }
+
+    // Keeps the entries of the given merchants
+    fn only(mut self, merchant_ids: &BTreeSet<i32>) -> Indexed {
+        for entries in [
+            &mut self.pincodes,
+            &mut self.merchant_pincodes,
+            &mut self.categories,
+            &mut self.rules,
+            &mut self.exclusions,
+            &mut self.terms,
+            &mut self.schedules,
+            &mut self.capacities,
+            &mut self.stores,
+        ] {
+            entries.retain(|(merchant_id, _)| merchant_ids.contains(merchant_id));
+        }
+        self.locations.retain(|merchant_id, _| merchant_ids.contains(merchant_id));
+        self.merchants.retain(|merchant_id| merchant_ids.contains(merchant_id));
+        self
+    }
+}
+
+// Compares the merchant hashes with the merchant rows. Legacy entries are counted once for the whole index
+fn diff_merchant_details(con: &mut redis::Connection, version: u64, merchant_rows: &[models::Merchant], indexed: &BTreeSet<i32>) -> redis::RedisResult<MerchantDetailsDiff> {
+    let mut outdated = Vec::new();
+
+    for batch in merchant_rows.chunks(READ_BATCH_SIZE) {
+        let mut pipe = redis::pipe();
+        for merchant in batch {
+            pipe.hgetall(index::merchant_key(version, merchant.id));
+        }
+
+        let stored: Vec<HashMap<String, String>> = pipe.query(con)?;
+        for (merchant, fields) in batch.iter().zip(stored) {
+            if index::merchant_from_fields(fields).as_ref() != Some(merchant) {
+                outdated.push(merchant.id);
+            }
+        }
+    }
+
+    let expected_ids: BTreeSet<i32> = merchant_rows.iter().map(|merchant| merchant.id).collect();
+
+    Ok(MerchantDetailsDiff {
+        outdated,
+        stale: indexed.difference(&expected_ids).copied().collect(),
+        legacy_entries: 0,
+    })
+}
+
+fn find_discrepancies(con: &mut redis::Connection, version: u64, expected: &Expected, indexed: &Indexed) -> redis::RedisResult<Discrepancies> {
+    Ok(Discrepancies {
+        pincode_index: diff(&expected.pincodes, &indexed.pincodes, to_pincode_entry),
+        merchant_pincode_index: diff(&expected.pincodes, &indexed.merchant_pincodes, to_pincode_entry),
+        category_index: diff(&expected.categories, &indexed.categories, to_category_entry),
+        location_index: diff_locations(&expected.locations, &indexed.locations),
+        rule_index: diff(&expected.rules, &indexed.rules, to_rule_entry),
+        exclusion_index: diff(&expected.exclusions, &indexed.exclusions, to_pincode_entry),
+        terms_index: diff(&expected.terms, &indexed.terms, to_terms_entry),
+        schedule_index: diff(&expected.schedules, &indexed.schedules, to_schedule_entry),
+        capacity_index: diff(&expected.capacities, &indexed.capacities, to_capacity_entry),
+        store_index: diff(&expected.stores, &indexed.stores, to_store_entry),
+        merchant_details: diff_merchant_details(con, version, &expected.merchants, &indexed.merchants)?,
+    })
+}
+
+// Reads the merchants with discrepancies from Postgres again and compares them once more, so the repair writes
+// what Postgres holds now rather than what it held when their page was read
+async fn recheck(db: &mut AsyncPgConnection, con: &mut redis::Connection, version: u64, page: Indexed, merchant_ids: BTreeSet<i32>) -> Result<(Discrepancies, Expected), utils::StoreError> {
+    use crate::schema::merchants;
+
+    let ids: Vec<i32> = merchant_ids.iter().copied().collect();
+    let merchant_rows = merchants::table
+        .filter(merchants::id.eq_any(&ids))
+        .order(merchants::id.asc())
+        .load::<models::Merchant>(db)
+        .await?;
+
+    let fresh = load_expected(db, merchant_rows).await?;
+    let discrepancies = find_discrepancies(con, version, &fresh, &page.only(&merchant_ids))?;
+
+    Ok((discrepancies, fresh))
+}
+
+fn add_repair_commands(pipe: &mut redis::Pipeline, version: u64, discrepancies: &Discrepancies, merchant_rows: &[models::Merchant]) {
+    for entry in &discrepancies.pincode_index.missing {
+        pipe.sadd(index::pincode_key(version, &entry.pincode), entry.merchant_id).ignore();
+    }
+    for entry in &discrepancies.pincode_index.stale {
+        pipe.srem(index::pincode_key(version, &entry.pincode), entry.merchant_id).ignore();
+    }
+    for entry in &discrepancies.merchant_pincode_index.missing {
+        pipe.sadd(index::merchant_pincodes_key(version, entry.merchant_id), &entry.pincode).ignore();
+    }
+    for entry in &discrepancies.merchant_pincode_index.stale {
+        pipe.srem(index::merchant_pincodes_key(version, entry.merchant_id), &entry.pincode).ignore();
+    }
+    for entry in &discrepancies.category_index.missing {
+        pipe.sadd(index::category_key(version, &entry.category), entry.merchant_id).ignore();
+    }
+    for entry in &discrepancies.category_index.stale {
+        pipe.srem(index::category_key(version, &entry.category), entry.merchant_id).ignore();
+    }
+    for entry in &discrepancies.store_index.missing {
+        pipe.sadd(index::stores_key(version, &entry.pincode), index::store_member(entry.merchant_id, entry.store_id)).ignore();
+    }
+    for entry in &discrepancies.store_index.stale {
+        pipe.srem(index::stores_key(version, &entry.pincode), index::store_member(entry.merchant_id, entry.store_id)).ignore();
+    }
+    for entry in &discrepancies.exclusion_index.missing {
+        pipe.sadd(index::excluded_key(version, &entry.pincode), entry.merchant_id).ignore();
+    }
+    for entry in &discrepancies.exclusion_index.stale {
+        pipe.srem(index::excluded_key(version, &entry.pincode), entry.merchant_id).ignore();
+    }
+    // Stale schedules go first, a schedule that changed is both stale and missing
+    for entry in &discrepancies.schedule_index.stale {
+        pipe.del(index::schedule_key(version, entry.merchant_id)).ignore();
+    }
+    for entry in &discrepancies.schedule_index.missing {
+        pipe.set(index::schedule_key(version, entry.merchant_id), &entry.schedule).ignore();
+    }
+    // Stale limits go first, a limit that changed is both stale and missing
+    for entry in &discrepancies.capacity_index.stale {
+        pipe.hdel(index::capacity_key(version, entry.merchant_id), &entry.scope).ignore();
+    }
+    for entry in &discrepancies.capacity_index.missing {
+        pipe.hset(index::capacity_key(version, entry.merchant_id), &entry.scope, &entry.daily_capacity).ignore();
+    }
+    // Stale terms go first, terms that changed are both stale and missing
+    for entry in &discrepancies.terms_index.stale {
+        pipe.hdel(index::terms_key(version, &entry.pincode), entry.merchant_id).ignore();
+    }
+    for entry in &discrepancies.terms_index.missing {
+        pipe.hset(index::terms_key(version, &entry.pincode), entry.merchant_id, index::encode_terms(&entry.terms)).ignore();
+    }
+    for rule in &discrepancies.rule_index.missing {
+        rules::add_rule_commands(pipe, version, rule);
+    }
+    for rule in &discrepancies.rule_index.stale {
+        rules::remove_rule_commands(pipe, version, rule);
+    }
+    // Stale locations go first, a merchant whose location changed is both stale and missing
+    for entry in &discrepancies.location_index.stale {
+        pipe.zrem(index::geo_key(version), entry.merchant_id).ignore();
+        pipe.zrem(index::radius_key(version), entry.merchant_id).ignore();
+    }
+    for entry in &discrepancies.location_index.missing {
+        if let (Some(latitude), Some(longitude), Some(delivery_radius_km)) = (entry.latitude, entry.longitude, entry.delivery_radius_km) {
+            pipe.cmd("GEOADD").arg(index::geo_key(version)).arg(longitude).arg(latitude).arg(entry.merchant_id).ignore();
+            pipe.zadd(index::radius_key(version), entry.merchant_id, delivery_radius_km).ignore();
+        }
+    }
+    for merchant in merchant_rows.iter().filter(|merchant| discrepancies.merchant_details.outdated.contains(&merchant.id)) {
+        pipe.del(index::merchant_key(version, merchant.id)).ignore();
+        pipe.hset_multiple(index::merchant_key(version, merchant.id), &index::merchant_fields(merchant)).ignore();
+    }
+    for merchant_id in &discrepancies.merchant_details.stale {
+        pipe.del(index::merchant_key(version, *merchant_id)).ignore();
+    }
+}
+
+// Repairs the current version and the one being built, if any, and marks the merchants as changed for the reindex
+fn repair(con: &mut redis::Connection, discrepancies: &Discrepancies, merchant_rows: &[models::Merchant]) -> redis::RedisResult<()> {
+    let merchant_ids: Vec<i32> = discrepancies.merchant_ids().into_iter().collect();
+
+    index::write(con, &merchant_ids, |_, pipe, version| {
+        add_repair_commands(pipe, version, discrepancies, merchant_rows);
+        Ok(())
+    })
+}
+
+// Diffs the serviceability stored in Postgres against the current version of the Redis index and repairs Redis unless dry_run is set.
+// Redis is read first and Postgres after it, a page of merchants at a time; each page is compared with the Redis entries
+// of every merchant ID up to the next page, so entries left behind by deleted merchants are found as well
+pub async fn run(db: &mut AsyncPgConnection, redis_client: &redis::Client, dry_run: bool) -> Result<ReconcileReport, utils::StoreError> {
+    use crate::schema::merchants;
+
+    let mut con = redis_client.get_connection()?;
+    let version = index::current_version(&mut con)?;
+    let indexed = Indexed::scan(&mut con, version)?;
+
+    let mut report = ReconcileReport {
+        dry_run,
+        merchants_checked: 0,
+        pincodes_checked: 0,
+        discrepancies: Discrepancies::default(),
+        repaired: false,
+    };
+    let mut from = i32::MIN;
+
+    loop {
+        let merchant_rows = merchants::table
+            .filter(merchants::id.ge(from))
+            .order(merchants::id.asc())
+            .limit(RECONCILE_BATCH_SIZE)
+            .load::<models::Merchant>(db)
+            .await?;
+
+        // The last page covers every merchant ID after it
+        let through = match merchant_rows.last() {
+            Some(last) if merchant_rows.len() as i64 == RECONCILE_BATCH_SIZE => last.id,
+            _ => i32::MAX,
+        };
+
+        report.merchants_checked += merchant_rows.len();
+        let expected = load_expected(db, merchant_rows).await?;
+        report.pincodes_checked += expected.pincodes.len();
+
+        let page = indexed.page(from, through);
+        let found = find_discrepancies(&mut con, version, &expected, &page)?;
+
+        if !found.is_consistent() {
+            let (discrepancies, fresh) = recheck(db, &mut con, version, page, found.merchant_ids()).await?;
+
+            if !dry_run && !discrepancies.is_consistent() {
+                repair(&mut con, &discrepancies, &fresh.merchants)?;
+                report.repaired = true;
+            }
+            report.discrepancies.extend(discrepancies);
+        }
+
+        if through == i32::MAX {
+            break;
+        }
+        from = through + 1;
+    }
+
+    let legacy_entries: usize = con.scard(index::legacy_merchants_key(version))?;
+    if legacy_entries > 0 {
+        report.discrepancies.merchant_details.legacy_entries = legacy_entries;
+
+        if !dry_run {
+            index::write(&mut con, &[], |_, pipe, version| {
+                pipe.del(index::legacy_merchants_key(version)).ignore();
+                Ok(())
+            })?;
+            report.repaired = true;
+        }
+    }
+
+    Ok(report)
+}
+
+// Compares Postgres with Redis and reports the differences, repairing them only with dry_run=false
+#[post("/admin/reconcile?<dry_run>")]
+pub(crate) async fn reconcile_index(redis: &State<RedisClient>, mut db: Connection<Db>, dry_run: Option<bool>) -> Json<utils::ApiResponse> {
+    match run(&mut db, &redis.client, dry_run.unwrap_or(true)).await {
+        Ok(report) => Json(utils::ApiResponse {
+            status: utils::ApiResponseStatus::Success,
+            data: json!(report).into(),
+        }),
+        Err(err) => {
+            eprintln!("Failed to reconcile Redis with PostgreSQL: {}", err);
+            Json(utils::ApiResponse {
+                status: utils::ApiResponseStatus::Error,
+                data: json!({"message": format!("Failed to reconcile Redis with PostgreSQL: {}", err)}).into(),
+            })
+        }
+    }
+}
+
+// Runs the reconciliation periodically once the server has launched.
+// The interval is read from RECONCILE_INTERVAL_SECS, setting it to 0 disables the task
+pub fn stage() -> AdHoc {
+    AdHoc::on_liftoff("Redis Reconciliation Task", |rocket| Box::pin(async move {
+        dotenv().ok();
+
+        let interval_secs = env::var("RECONCILE_INTERVAL_SECS")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(DEFAULT_INTERVAL_SECS);
+
+        if interval_secs == 0 {
+            return;
+        }
+
+        let (Some(db), Some(redis)) = (Db::fetch(rocket), rocket.state::<RedisClient>()) else {
+            eprintln!("Reconciliation task not started: database or Redis client unavailable");
+            return;
+        };
+
+        let pool = db.0.clone();
+        let redis_client = redis.client.clone();
+
+        rocket::tokio::spawn(async move {
+            let mut interval = rocket::tokio::time::interval(Duration::from_secs(interval_secs));
+            // The first tick completes immediately, skip it so the first run happens after one interval
+            interval.tick().await;
+
+            loop {
+                interval.tick().await;
+
+                let mut con = match pool.get().await {
+                    Ok(con) => con,
+                    Err(err) => {
+                        eprintln!("Reconciliation skipped, could not get a database connection: {:?}", err);
+                        continue;
+                    }
+                };
+
+                match run(&mut con, &redis_client, false).await {
+                    Ok(report) if report.repaired => println!("Reconciliation repaired Redis: {:?}", report),
+                    Ok(_) => println!("Reconciliation found Redis consistent with PostgreSQL"),
+                    Err(err) => eprintln!("Reconciliation failed: {}", err),
+                }
+            }
+        });
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn location(merchant_id: i32, latitude: Option<f64>, longitude: Option<f64>, delivery_radius_km: Option<f64>) -> MerchantLocation {
+        MerchantLocation { merchant_id, latitude, longitude, delivery_radius_km }
+    }
+
+    fn locations(entries: Vec<MerchantLocation>) -> BTreeMap<i32, MerchantLocation> {
+        entries.into_iter().map(|entry| (entry.merchant_id, entry)).collect()
+    }
+
+    fn entries(pairs: &[(i32, &str)]) -> BTreeSet<Entry> {
+        pairs.iter().map(|(merchant_id, value)| (*merchant_id, value.to_string())).collect()
+    }
+
+    #[test]
+    fn positions_read_back_from_the_geo_index_match_within_the_tolerance() {
+        let expected = locations(vec![location(4, Some(12.9716), Some(77.5946), Some(5.0))]);
+        let actual = locations(vec![location(4, Some(12.971604), Some(77.594597), Some(5.0))]);
+
+        assert!(diff_locations(&expected, &actual).is_empty());
+    }
+
+    #[test]
+    fn moved_merchants_are_missing_and_stale() {
+        let expected = locations(vec![location(4, Some(12.9716), Some(77.5946), Some(5.0))]);
+        let actual = locations(vec![location(4, Some(28.6139), Some(77.5946), Some(5.0))]);
+
+        let diff = diff_locations(&expected, &actual);
+        assert_eq!(diff.missing, vec![location(4, Some(12.9716), Some(77.5946), Some(5.0))]);
+        assert_eq!(diff.stale, vec![location(4, Some(28.6139), Some(77.5946), Some(5.0))]);
+    }
+
+    #[test]
+    fn incomplete_and_unexpected_locations_are_stale() {
+        let expected = locations(vec![location(4, Some(12.9716), Some(77.5946), Some(5.0))]);
+        let actual = locations(vec![location(4, Some(12.9716), Some(77.5946), None), location(9, None, None, Some(3.0))]);
+
+        let diff = diff_locations(&expected, &actual);
+        assert_eq!(diff.missing.len(), 1);
+        assert_eq!(diff.stale, vec![location(4, Some(12.9716), Some(77.5946), None), location(9, None, None, Some(3.0))]);
+    }
+
+    #[test]
+    fn pages_cover_every_merchant_id_in_their_range() {
+        let indexed = Indexed {
+            pincodes: entries(&[(1, "560001"), (5, "560001"), (10, "560002"), (11, "560003")]),
+            merchants: BTreeSet::from([1, 5, 10, 11]),
+            ..Indexed::default()
+        };
+
+        let page = indexed.page(2, 10);
+        assert_eq!(page.pincodes, entries(&[(5, "560001"), (10, "560002")]));
+        assert_eq!(page.merchants, BTreeSet::from([5, 10]));
+
+        let last = indexed.page(11, i32::MAX);
+        assert_eq!(last.pincodes, entries(&[(11, "560003")]));
+    }
+
+    #[test]
+    fn rechecks_only_compare_the_merchants_with_discrepancies() {
+        let indexed = Indexed {
+            stores: entries(&[(1, "4:560001"), (5, "6:560001")]),
+            locations: locations(vec![location(1, None, None, Some(2.0)), location(5, None, None, Some(3.0))]),
+            ..Indexed::default()
+        };
+
+        let only = indexed.only(&BTreeSet::from([5]));
+        assert_eq!(only.stores, entries(&[(5, "6:560001")]));
+        assert_eq!(only.locations.keys().copied().collect::<Vec<_>>(), vec![5]);
+    }
+
+    #[test]
+    fn merchant_ids_cover_every_index() {
+        let discrepancies = Discrepancies {
+            pincode_index: diff(&entries(&[(3, "560001")]), &BTreeSet::new(), to_pincode_entry),
+            store_index: diff(&BTreeSet::new(), &entries(&[(7, "4:560001")]), to_store_entry),
+            merchant_details: MerchantDetailsDiff { outdated: vec![9], stale: vec![], legacy_entries: 0 },
+            ..Discrepancies::default()
+        };
+
+        assert!(!discrepancies.is_consistent());
+        assert_eq!(discrepancies.merchant_ids(), BTreeSet::from([3, 7, 9]));
+    }
+
+    #[test]
+    fn changed_values_are_removed_before_they_are_written_again() {
+        let discrepancies = Discrepancies {
+            schedule_index: diff(&entries(&[(3, "new")]), &entries(&[(3, "old")]), to_schedule_entry),
+            location_index: IndexDiff {
+                missing: vec![location(3, Some(12.5), Some(77.25), Some(5.0))],
+                stale: vec![location(3, Some(28.5), Some(77.25), Some(5.0))],
+            },
+            ..Discrepancies::default()
+        };
+
+        let mut pipe = redis::pipe();
+        add_repair_commands(&mut pipe, 2, &discrepancies, &[]);
+
+        assert_eq!(
+            index::pipeline_commands(&pipe),
+            vec![
+                vec!["DEL", "v2:schedule:3"],
+                vec!["SET", "v2:schedule:3", "new"],
+                vec!["ZREM", "v2:geo:merchants", "3"],
+                vec!["ZREM", "v2:geo:radius", "3"],
+                vec!["GEOADD", "v2:geo:merchants", "77.25", "12.5", "3"],
+                vec!["ZADD", "v2:geo:radius", "5.0", "3"],
+            ],
+        );
+    }
+}
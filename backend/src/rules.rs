@@ -0,0 +1,217 @@
+use std::collections::{BTreeSet, HashMap};
+use std::ops::RangeInclusive;
+
+use redis::Commands;
+use rocket_db_pools::diesel::{AsyncPgConnection, prelude::*};
+
+use crate::{index, models, utils};
+
+pub const RULE_PREFIX: &str = "prefix";
+pub const RULE_RANGE: &str = "range";
+
+// Lowest and highest pincode covered by a rule, a prefix "56" covers 560000 to 569999
+pub fn bounds(rule_type: &str, value: &str) -> Option<(u32, u32)> {
+    match rule_type {
+        RULE_PREFIX => Some((format!("{:0<6}", value).parse().ok()?, format!("{:9<6}", value).parse().ok()?)),
+        RULE_RANGE => {
+            let (from, to) = value.split_once('-')?;
+            Some((from.parse().ok()?, to.parse().ok()?))
+        }
+        _ => None,
+    }
+}
+
+// Buckets of the rule index a rule covering the pincodes from low to high is added to
+pub fn buckets(low: u32, high: u32) -> RangeInclusive<u32> {
+    index::rule_bucket(low)..=index::rule_bucket(high)
+}
+
+// Ranges are stored as "<from>-<to>"
+pub fn range_value(range: &utils::PincodeRange) -> String {
+    format!("{}-{}", range.from.trim(), range.to.trim())
+}
+
+pub fn pincode_rules(merchant_id: i32, prefixes: &[String], ranges: &[String]) -> Vec<models::MerchantPincodeRule> {
+    let rule = |rule_type: &str, value: &String| models::MerchantPincodeRule {
+        merchant_id,
+        rule_type: rule_type.to_string(),
+        value: value.clone(),
+    };
+
+    prefixes
+        .iter()
+        .map(|prefix| rule(RULE_PREFIX, prefix))
+        .chain(ranges.iter().map(|range| rule(RULE_RANGE, range)))
+        .collect()
+}
+
+// Inserts the rules, skipping ones the merchant already has. Returns the newly added rules
+pub async fn add_rules(db: &mut AsyncPgConnection, rules: &[models::MerchantPincodeRule]) -> QueryResult<Vec<models::MerchantPincodeRule>> {
+    use crate::schema::merchant_pincode_rules;
+
+    if rules.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    diesel::insert_into(merchant_pincode_rules::table)
+        .values(rules)
+        .on_conflict_do_nothing()
+        .returning(models::MerchantPincodeRule::as_returning())
+        .get_results(db)
+        .await
+}
+
+// Removes the prefix and range rules of a merchant. Returns the rules that were actually removed
+pub async fn remove_rules(db: &mut AsyncPgConnection, merchant_id: i32, prefixes: &[String], ranges: &[String]) -> QueryResult<Vec<models::MerchantPincodeRule>> {
+    use crate::schema::merchant_pincode_rules;
+
+    let mut removed = diesel::delete(
+        merchant_pincode_rules::table
+            .filter(merchant_pincode_rules::merchant_id.eq(merchant_id))
+            .filter(merchant_pincode_rules::rule_type.eq(RULE_PREFIX))
+            .filter(merchant_pincode_rules::value.eq_any(prefixes)),
+    )
+    .returning(models::MerchantPincodeRule::as_returning())
+    .get_results(db)
+    .await?;
+
+    removed.extend(
+        diesel::delete(
+            merchant_pincode_rules::table
+                .filter(merchant_pincode_rules::merchant_id.eq(merchant_id))
+                .filter(merchant_pincode_rules::rule_type.eq(RULE_RANGE))
+                .filter(merchant_pincode_rules::value.eq_any(ranges)),
+        )
+        .returning(models::MerchantPincodeRule::as_returning())
+        .get_results(db)
+        .await?,
+    );
+
+    Ok(removed)
+}
+
+pub async fn load_rules(db: &mut AsyncPgConnection, merchant_id: i32) -> QueryResult<Vec<models::MerchantPincodeRule>> {
+    use crate::schema::merchant_pincode_rules;
+
+    merchant_pincode_rules::table
+        .filter(merchant_pincode_rules::merchant_id.eq(merchant_id))
+        .select(models::MerchantPincodeRule::as_select())
+        .load(db)
+        .await
+}
+
+// Adds the rule to every bucket of the Redis rule index it covers, scored by the lowest pincode it covers
+pub fn add_rule_commands(pipe: &mut redis::Pipeline, version: u64, rule: &models::MerchantPincodeRule) {
+    if let Some((low, high)) = bounds(&rule.rule_type, &rule.value) {
+        for bucket in buckets(low, high) {
+            pipe.zadd(index::rules_key(version, bucket), index::rule_member(rule), low).ignore();
+        }
+    }
+}
+
+pub fn remove_rule_commands(pipe: &mut redis::Pipeline, version: u64, rule: &models::MerchantPincodeRule) {
+    if let Some((low, high)) = bounds(&rule.rule_type, &rule.value) {
+        for bucket in buckets(low, high) {
+            pipe.zrem(index::rules_key(version, bucket), index::rule_member(rule)).ignore();
+        }
+    }
+}
+
+pub fn store_rules(redis_client: &redis::Client, rules: &[models::MerchantPincodeRule]) -> redis::RedisResult<()> {
+    if rules.is_empty() {
+        return Ok(());
+    }
+
+    // A rule is in all of its buckets or none of them
+    let merchant_ids: Vec<i32> = rules.iter().map(|rule| rule.merchant_id).collect::<BTreeSet<i32>>().into_iter().collect();
+    let mut con = redis_client.get_connection()?;
+    index::write(&mut con, &merchant_ids, |_, pipe, version| {
+        for rule in rules {
+            add_rule_commands(pipe, version, rule);
+        }
+        Ok(())
+    })
+}
+
+pub fn delete_rules(redis_client: &redis::Client, rules: &[models::MerchantPincodeRule]) -> redis::RedisResult<()> {
+    if rules.is_empty() {
+        return Ok(());
+    }
+
+    let merchant_ids: Vec<i32> = rules.iter().map(|rule| rule.merchant_id).collect::<BTreeSet<i32>>().into_iter().collect();
+    let mut con = redis_client.get_connection()?;
+    index::write(&mut con, &merchant_ids, |_, pipe, version| {
+        for rule in rules {
+            remove_rule_commands(pipe, version, rule);
+        }
+        Ok(())
+    })
+}
+
+// Merchants whose prefix or range rules cover each of the pincodes, along with the rule that matched
+pub fn retrieve_rule_matches(redis_client: &redis::Client, pincodes: &[String], category: Option<&str>) -> redis::RedisResult<HashMap<String, Vec<(u32, utils::RuleMatch)>>> {
+    let mut con = redis_client.get_connection()?;
+    let version = index::current_version(&mut con)?;
+    let mut matches = HashMap::new();
+
+    for pincode in pincodes {
+        let Ok(number) = pincode.parse::<u32>() else {
+            continue;
+        };
+
+        // Rules of the pincode's bucket starting at or below it, the ones ending below it are dropped here
+        let members: Vec<String> = con.zrangebyscore(index::rules_key(version, index::rule_bucket(number)), "-inf", number)?;
+        let mut candidates: Vec<(u32, utils::RuleMatch)> = members
+            .iter()
+            .filter_map(|member| index::parse_rule_member(member))
+            .filter(|rule| bounds(&rule.rule_type, &rule.value).is_some_and(|(_, high)| number <= high))
+            .map(|rule| {
+                (rule.merchant_id as u32, utils::RuleMatch { pincode: Some(pincode.clone()), rule: rule.rule_type, value: rule.value })
+            })
+            .collect();
+
+        if let (Some(category), false) = (category, candidates.is_empty()) {
+            let candidate_ids: Vec<u32> = candidates.iter().map(|(merchant_id, _)| *merchant_id).collect();
+            let in_category: Vec<bool> = redis::cmd("SMISMEMBER").arg(index::category_key(version, category)).arg(&candidate_ids).query(&mut con)?;
+            candidates = candidates.into_iter().zip(in_category).filter(|(_, in_category)| *in_category).map(|(candidate, _)| candidate).collect();
+        }
+
+        matches.insert(pincode.clone(), candidates);
+    }
+
+    Ok(matches)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bounds_of_prefixes_cover_every_pincode_starting_with_them() {
+        assert_eq!(bounds(RULE_PREFIX, "56"), Some((560000, 569999)));
+        assert_eq!(bounds(RULE_PREFIX, "56003"), Some((560030, 560039)));
+        assert_eq!(bounds(RULE_PREFIX, "5a"), None);
+    }
+
+    #[test]
+    fn bounds_of_ranges_are_their_ends() {
+        assert_eq!(bounds(RULE_RANGE, "560001-560100"), Some((560001, 560100)));
+        assert_eq!(bounds(RULE_RANGE, "560001"), None);
+        assert_eq!(bounds("district", "560001-560100"), None);
+    }
+
+    #[test]
+    fn buckets_cover_the_thousands_a_rule_spans() {
+        assert_eq!(buckets(560001, 560999), 560..=560);
+        assert_eq!(buckets(560900, 561100), 560..=561);
+        assert_eq!(buckets(560000, 569999).count(), 10);
+    }
+
+    #[test]
+    fn range_values_are_trimmed() {
+        let range = utils::PincodeRange { from: " 560001".to_string(), to: "560100 ".to_string() };
+
+        assert_eq!(range_value(&range), "560001-560100");
+        assert_eq!(bounds(RULE_RANGE, &range_value(&range)), Some((560001, 560100)));
+    }
+}
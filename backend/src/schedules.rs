@@ -0,0 +1,447 @@
+use std::collections::{BTreeMap, HashMap};
+
+use rocket::serde::json::{Json, json};
+use rocket::serde::{Deserialize, Serialize};
+use rocket::time::format_description::well_known::Rfc3339;
+use rocket::time::{Date, Month, OffsetDateTime, Time, UtcOffset};
+use rocket::State;
+use rocket_db_pools::Connection;
+use rocket_db_pools::diesel::{AsyncPgConnection, prelude::*};
+use rocket_db_pools::diesel::scoped_futures::ScopedFutureExt;
+
+use crate::{index, models, utils, validation, Db, RedisClient};
+
+// Opening hours and holidays are in India Standard Time (UTC+05:30)
+const IST_OFFSET_SECS: i32 = 5 * 3600 + 30 * 60;
+
+const DAYS: [(&str, &str); 7] = [
+    ("mon", "monday"),
+    ("tue", "tuesday"),
+    ("wed", "wednesday"),
+    ("thu", "thursday"),
+    ("fri", "friday"),
+    ("sat", "saturday"),
+    ("sun", "sunday"),
+];
+
+// Weekday number of "mon" or "monday" (case-insensitive), 1 is Monday and 7 is Sunday
+pub fn parse_day(day: &str) -> Option<i16> {
+    let day = day.trim().to_lowercase();
+    let position = DAYS.iter().position(|(short, long)| day == *short || day == *long)?;
+    Some(position as i16 + 1)
+}
+
+fn day_name(weekday: i16) -> String {
+    DAYS.get(weekday as usize - 1).map(|(short, _)| short.to_string()).unwrap_or_default()
+}
+
+// Parses "HH:MM"
+pub fn parse_time(time: &str) -> Option<Time> {
+    let (hour, minute) = time.trim().split_once(':')?;
+    if hour.len() != 2 || minute.len() != 2 {
+        return None;
+    }
+
+    Time::from_hms(hour.parse().ok()?, minute.parse().ok()?, 0).ok()
+}
+
+fn format_time(time: Time) -> String {
+    format!("{:02}:{:02}", time.hour(), time.minute())
+}
+
+// Parses "YYYY-MM-DD"
+pub fn parse_date(date: &str) -> Option<Date> {
+    let mut parts = date.trim().splitn(3, '-');
+    let year = parts.next()?.parse().ok()?;
+    let month = Month::try_from(parts.next()?.parse::<u8>().ok()?).ok()?;
+    let day = parts.next()?.parse().ok()?;
+
+    Date::from_calendar_date(year, month, day).ok()
+}
+
+pub fn format_date(date: Date) -> String {
+    format!("{:04}-{:02}-{:02}", date.year(), u8::from(date.month()), date.day())
+}
+
+pub fn ist_offset() -> UtcOffset {
+    UtcOffset::from_whole_seconds(IST_OFFSET_SECS).unwrap_or(UtcOffset::UTC)
+}
+
+pub fn parse_timestamp(timestamp: &str) -> Option<OffsetDateTime> {
+    OffsetDateTime::parse(timestamp.trim(), &Rfc3339).ok()
+}
+
+fn minute_of_day(time: Time) -> u16 {
+    time.hour() as u16 * 60 + time.minute() as u16
+}
+
+// Schedule of a merchant as stored in Redis: (weekday, opening minute, closing minute) windows and the holidays
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Schedule {
+    pub hours: Vec<(i16, u16, u16)>,
+    pub holidays: Vec<String>,
+}
+
+impl Schedule {
+    pub fn new(hours: &[models::MerchantOperatingHours], holidays: &[models::MerchantHoliday]) -> Self {
+        let mut schedule = Schedule {
+            hours: hours.iter().map(|row| (row.weekday, minute_of_day(row.opens_at), minute_of_day(row.closes_at))).collect(),
+            holidays: holidays.iter().map(|row| format_date(row.holiday)).collect(),
+        };
+        // Sorted so the same schedule is always encoded the same way
+        schedule.hours.sort();
+        schedule.holidays.sort();
+        schedule
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hours.is_empty() && self.holidays.is_empty()
+    }
+
+    pub fn encode(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+
+    pub fn decode(value: &str) -> Option<Self> {
+        serde_json::from_str(value).ok()
+    }
+
+    // Holidays close the whole day. Without opening hours the merchant is open on every other day
+    pub fn is_open(&self, at: OffsetDateTime) -> bool {
+        let local = at.to_offset(ist_offset());
+
+        if self.holidays.contains(&format_date(local.date())) {
+            return false;
+        }
+        if self.hours.is_empty() {
+            return true;
+        }
+
+        let weekday = local.weekday().number_from_monday() as i16;
+        let previous_day = if weekday == 1 { 7 } else { weekday - 1 };
+        let minute = minute_of_day(local.time());
+
+        // A window closing at or before its opening time runs into the next day
+        self.hours.iter().any(|&(day, opens, closes)| {
+            if opens < closes {
+                day == weekday && (opens..closes).contains(&minute)
+            } else {
+                (day == weekday && minute >= opens) || (day == previous_day && minute < closes)
+            }
+        })
+    }
+}
+
+pub async fn load_schedule(db: &mut AsyncPgConnection, merchant_id: i32) -> QueryResult<(Vec<models::MerchantOperatingHours>, Vec<models::MerchantHoliday>)> {
+    use crate::schema::{merchant_holidays, merchant_operating_hours};
+
+    let hours = merchant_operating_hours::table
+        .filter(merchant_operating_hours::merchant_id.eq(merchant_id))
+        .select(models::MerchantOperatingHours::as_select())
+        .order((merchant_operating_hours::weekday.asc(), merchant_operating_hours::opens_at.asc()))
+        .load(db)
+        .await?;
+
+    let holidays = merchant_holidays::table
+        .filter(merchant_holidays::merchant_id.eq(merchant_id))
+        .select(models::MerchantHoliday::as_select())
+        .order(merchant_holidays::holiday.asc())
+        .load(db)
+        .await?;
+
+    Ok((hours, holidays))
+}
+
+// Rows of a validated schedule. Windows opening on the same day at the same time keep the last closing time
+fn schedule_rows(merchant_id: i32, schedule: &utils::MerchantSchedule) -> (Vec<models::MerchantOperatingHours>, Vec<models::MerchantHoliday>) {
+    let mut windows = BTreeMap::new();
+    for hours in &schedule.hours {
+        let (Some(opens_at), Some(closes_at)) = (parse_time(&hours.opens_at), parse_time(&hours.closes_at)) else {
+            continue;
+        };
+
+        let weekdays: Vec<i16> = if hours.days.is_empty() {
+            (1..=7).collect()
+        } else {
+            hours.days.iter().filter_map(|day| parse_day(day)).collect()
+        };
+        for weekday in weekdays {
+            windows.insert((weekday, opens_at), closes_at);
+        }
+    }
+
+    let mut holidays = BTreeMap::new();
+    for holiday in &schedule.holidays {
+        if let Some(date) = parse_date(&holiday.date) {
+            holidays.insert(date, holiday.name.clone());
+        }
+    }
+
+    (
+        windows
+            .into_iter()
+            .map(|((weekday, opens_at), closes_at)| models::MerchantOperatingHours { merchant_id, weekday, opens_at, closes_at })
+            .collect(),
+        holidays
+            .into_iter()
+            .map(|(holiday, name)| models::MerchantHoliday { merchant_id, holiday, name })
+            .collect(),
+    )
+}
+
+// Replaces the opening hours and holidays of the merchant
+async fn replace_schedule(db: &mut AsyncPgConnection, merchant_id: i32, hours: &[models::MerchantOperatingHours], holidays: &[models::MerchantHoliday]) -> QueryResult<()> {
+    use crate::schema::{merchant_holidays, merchant_operating_hours};
+
+    diesel::delete(merchant_operating_hours::table.filter(merchant_operating_hours::merchant_id.eq(merchant_id)))
+        .execute(db)
+        .await?;
+    diesel::delete(merchant_holidays::table.filter(merchant_holidays::merchant_id.eq(merchant_id)))
+        .execute(db)
+        .await?;
+
+    if !hours.is_empty() {
+        diesel::insert_into(merchant_operating_hours::table).values(hours).execute(db).await?;
+    }
+    if !holidays.is_empty() {
+        diesel::insert_into(merchant_holidays::table).values(holidays).execute(db).await?;
+    }
+
+    Ok(())
+}
+
+// Same days with the same opening window are listed together
+fn schedule_response(hours: &[models::MerchantOperatingHours], holidays: &[models::MerchantHoliday]) -> utils::MerchantSchedule {
+    let mut windows: Vec<((Time, Time), Vec<String>)> = Vec::new();
+    for row in hours {
+        let window = (row.opens_at, row.closes_at);
+        match windows.iter_mut().find(|(existing, _)| *existing == window) {
+            Some((_, days)) => days.push(day_name(row.weekday)),
+            None => windows.push((window, vec![day_name(row.weekday)])),
+        }
+    }
+
+    utils::MerchantSchedule {
+        hours: windows
+            .into_iter()
+            .map(|((opens_at, closes_at), days)| utils::OpeningHours { days, opens_at: format_time(opens_at), closes_at: format_time(closes_at) })
+            .collect(),
+        holidays: holidays
+            .iter()
+            .map(|row| utils::Holiday { date: format_date(row.holiday), name: row.name.clone() })
+            .collect(),
+    }
+}
+
+// Replaces the schedule of the merchant, removing it when it is empty
+pub fn add_schedule_commands(pipe: &mut redis::Pipeline, version: u64, merchant_id: i32, schedule: &Schedule) {
+    if schedule.is_empty() {
+        pipe.del(index::schedule_key(version, merchant_id)).ignore();
+    } else {
+        pipe.set(index::schedule_key(version, merchant_id), schedule.encode()).ignore();
+    }
+}
+
+// Keeps the merchants open at the given time, merchants without a schedule are always open
+pub fn open_merchant_ids(redis_client: &redis::Client, merchant_ids: Vec<u32>, at: Option<OffsetDateTime>) -> redis::RedisResult<Vec<u32>> {
+    let Some(at) = at else {
+        return Ok(merchant_ids);
+    };
+    if merchant_ids.is_empty() {
+        return Ok(merchant_ids);
+    }
+
+    let mut con = redis_client.get_connection()?;
+    let version = index::current_version(&mut con)?;
+    let keys: Vec<String> = merchant_ids.iter().map(|&merchant_id| index::schedule_key(version, merchant_id as i32)).collect();
+    let schedules: Vec<Option<String>> = redis::cmd("MGET").arg(&keys).query(&mut con)?;
+
+    Ok(merchant_ids
+        .into_iter()
+        .zip(schedules)
+        .filter(|(_, schedule)| schedule.as_deref().and_then(Schedule::decode).is_none_or(|schedule| schedule.is_open(at)))
+        .map(|(merchant_id, _)| merchant_id)
+        .collect())
+}
+
+// Schedules of the merchants, for the reindex and the reconciliation
+pub async fn load_schedules(db: &mut AsyncPgConnection, merchant_ids: Option<&[i32]>) -> QueryResult<HashMap<i32, Schedule>> {
+    use crate::schema::{merchant_holidays, merchant_operating_hours};
+
+    let mut hours_query = merchant_operating_hours::table.select(models::MerchantOperatingHours::as_select()).into_boxed();
+    let mut holidays_query = merchant_holidays::table.select(models::MerchantHoliday::as_select()).into_boxed();
+    if let Some(merchant_ids) = merchant_ids {
+        hours_query = hours_query.filter(merchant_operating_hours::merchant_id.eq_any(merchant_ids));
+        holidays_query = holidays_query.filter(merchant_holidays::merchant_id.eq_any(merchant_ids));
+    }
+
+    let mut hours: HashMap<i32, Vec<models::MerchantOperatingHours>> = HashMap::new();
+    for row in hours_query.load(db).await? {
+        hours.entry(row.merchant_id).or_default().push(row);
+    }
+    let mut holidays: HashMap<i32, Vec<models::MerchantHoliday>> = HashMap::new();
+    for row in holidays_query.load(db).await? {
+        holidays.entry(row.merchant_id).or_default().push(row);
+    }
+
+    let mut merchant_ids: Vec<i32> = hours.keys().chain(holidays.keys()).copied().collect();
+    merchant_ids.sort();
+    merchant_ids.dedup();
+
+    Ok(merchant_ids
+        .into_iter()
+        .map(|merchant_id| {
+            let schedule = Schedule::new(
+                hours.get(&merchant_id).map(Vec::as_slice).unwrap_or_default(),
+                holidays.get(&merchant_id).map(Vec::as_slice).unwrap_or_default(),
+            );
+            (merchant_id, schedule)
+        })
+        .collect())
+}
+
+// Returns the opening hours and holidays of the merchant
+#[get("/merchant/<merchant_id>/schedule")]
+pub(crate) async fn get_schedule(mut db: Connection<Db>, merchant_id: i32) -> Json<utils::ApiResponse> {
+    if let Err(response) = crate::merchant_exists(&mut db, merchant_id).await {
+        return Json(response);
+    }
+
+    match load_schedule(&mut db, merchant_id).await {
+        Ok((hours, holidays)) => Json(utils::ApiResponse {
+            status: utils::ApiResponseStatus::Success,
+            data: json!({"ONDC_merchant_id": format!("{}", merchant_id), "schedule": schedule_response(&hours, &holidays)}).into(),
+        }),
+        Err(err) => Json(utils::ApiResponse {
+            status: utils::ApiResponseStatus::Error,
+            data: json!({"message": format!("Failed to read the schedule: {:?}", err)}).into(),
+        }),
+    }
+}
+
+// Replaces the opening hours and holidays of the merchant. Sending no hours makes the merchant open around the clock
+#[put("/merchant/<merchant_id>/schedule", format = "json", data = "<schedule>")]
+pub(crate) async fn set_schedule(redis: &State<RedisClient>, mut db: Connection<Db>, schedule: Json<utils::MerchantSchedule>, merchant_id: i32) -> Json<utils::ApiResponse> {
+    if let Err(response) = crate::merchant_exists(&mut db, merchant_id).await {
+        return Json(response);
+    }
+
+    let schedule = schedule.into_inner();
+    if let Err(errors) = validation::validate_schedule(&schedule) {
+        return Json(validation::error_response(errors));
+    }
+
+    let (hours, holidays) = schedule_rows(merchant_id, &schedule);
+
+    let result = db.transaction::<_, utils::StoreError, _>(|conn| async move {
+        replace_schedule(conn, merchant_id, &hours, &holidays).await?;
+
+        let mut update = index::IndexUpdate::default();
+        let schedule = Schedule::new(&hours, &holidays);
+        update.add(merchant_id, move |_, pipe, version| {
+            add_schedule_commands(pipe, version, merchant_id, &schedule);
+            Ok(())
+        });
+
+        Ok((schedule_response(&hours, &holidays), update))
+    }.scope_boxed()).await;
+
+    match result {
+        Ok((schedule, update)) => {
+            update.apply(&redis.client);
+            Json(utils::ApiResponse {
+                status: utils::ApiResponseStatus::Success,
+                data: json!({
+                    "ONDC_merchant_id": format!("{}", merchant_id),
+                    "schedule": schedule,
+                    "message": "Merchant schedule updated"
+                }).into(),
+            })
+        }
+        Err(err) => {
+            eprintln!("Failed to update the merchant schedule: {}", err);
+            Json(utils::ApiResponse {
+                status: utils::ApiResponseStatus::Error,
+                data: json!({"message": format!("Failed to update the merchant schedule: {}", err)}).into(),
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::time::PrimitiveDateTime;
+
+    use super::*;
+
+    // 2024-01-01 is a Monday
+    fn at(day: u8, hour: u8, minute: u8) -> OffsetDateTime {
+        let date = Date::from_calendar_date(2024, Month::January, day).unwrap();
+        PrimitiveDateTime::new(date, Time::from_hms(hour, minute, 0).unwrap()).assume_offset(ist_offset())
+    }
+
+    #[test]
+    fn is_open_within_the_hours_of_the_day() {
+        let schedule = Schedule { hours: vec![(1, 9 * 60, 21 * 60)], holidays: Vec::new() };
+
+        assert!(schedule.is_open(at(1, 9, 0)));
+        assert!(schedule.is_open(at(1, 20, 59)));
+        assert!(!schedule.is_open(at(1, 21, 0)));
+        assert!(!schedule.is_open(at(1, 8, 59)));
+        assert!(!schedule.is_open(at(2, 12, 0)));
+    }
+
+    #[test]
+    fn is_open_uses_india_standard_time() {
+        let schedule = Schedule { hours: vec![(1, 9 * 60, 21 * 60)], holidays: Vec::new() };
+
+        // 04:00 UTC is 09:30 IST
+        assert!(schedule.is_open(at(1, 9, 30).to_offset(UtcOffset::UTC)));
+        assert!(!schedule.is_open(PrimitiveDateTime::new(at(1, 0, 0).date(), Time::from_hms(16, 0, 0).unwrap()).assume_utc()));
+    }
+
+    #[test]
+    fn is_open_runs_overnight_hours_into_the_next_day() {
+        // Sunday 22:00 to Monday 02:00
+        let schedule = Schedule { hours: vec![(7, 22 * 60, 2 * 60)], holidays: Vec::new() };
+
+        assert!(schedule.is_open(at(7, 23, 0)));
+        assert!(schedule.is_open(at(8, 1, 59)));
+        assert!(!schedule.is_open(at(8, 2, 0)));
+        assert!(!schedule.is_open(at(7, 21, 59)));
+    }
+
+    #[test]
+    fn is_open_closes_on_holidays() {
+        let always_open = Schedule { hours: Vec::new(), holidays: vec!["2024-01-26".to_string()] };
+        assert!(always_open.is_open(at(25, 3, 0)));
+        assert!(!always_open.is_open(at(26, 12, 0)));
+
+        // Open all day on Fridays, but not on the Friday that is a holiday
+        let schedule = Schedule { hours: vec![(5, 0, 0)], holidays: vec!["2024-01-26".to_string()] };
+        assert!(schedule.is_open(at(19, 12, 0)));
+        assert!(!schedule.is_open(at(26, 12, 0)));
+    }
+
+    #[test]
+    fn parse_day_and_time() {
+        assert_eq!(parse_day(" Monday "), Some(1));
+        assert_eq!(parse_day("SUN"), Some(7));
+        assert_eq!(parse_day("weekday"), None);
+        assert_eq!(parse_time("09:30"), Time::from_hms(9, 30, 0).ok());
+        assert_eq!(parse_time("9:30"), None);
+        assert_eq!(parse_time("24:00"), None);
+        assert_eq!(parse_date("2024-02-30"), None);
+    }
+
+    #[test]
+    fn empty_schedules_are_removed_from_the_index() {
+        let mut pipe = redis::pipe();
+        add_schedule_commands(&mut pipe, 3, 7, &Schedule::default());
+        add_schedule_commands(&mut pipe, 3, 7, &Schedule { hours: vec![(5, 0, 0)], holidays: Vec::new() });
+
+        let commands = index::pipeline_commands(&pipe);
+        assert_eq!(commands[0], vec!["DEL", "v3:schedule:7"]);
+        assert_eq!(commands[1][..2], ["SET", "v3:schedule:7"]);
+    }
+}
@@ -1,5 +1,108 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    merchant_pincodes (merchant_id, pincode) {
+        merchant_id -> Int4,
+        pincode -> Varchar,
+        delivery_time_hours -> Nullable<Int4>,
+        delivery_fee_paise -> Nullable<Int4>,
+        min_order_value_paise -> Nullable<Int4>,
+    }
+}
+
+diesel::table! {
+    pincode_directory (pincode, office_name) {
+        #[max_length = 6]
+        pincode -> Varchar,
+        #[max_length = 255]
+        office_name -> Varchar,
+        #[max_length = 255]
+        district -> Varchar,
+        #[max_length = 255]
+        state -> Varchar,
+        #[max_length = 255]
+        region -> Varchar,
+        latitude -> Nullable<Float8>,
+        longitude -> Nullable<Float8>,
+    }
+}
+
+diesel::table! {
+    merchant_capacities (merchant_id) {
+        merchant_id -> Int4,
+        daily_capacity -> Int4,
+    }
+}
+
+diesel::table! {
+    merchant_holidays (merchant_id, holiday) {
+        merchant_id -> Int4,
+        holiday -> Date,
+        #[max_length = 255]
+        name -> Nullable<Varchar>,
+    }
+}
+
+diesel::table! {
+    merchant_operating_hours (merchant_id, weekday, opens_at) {
+        merchant_id -> Int4,
+        weekday -> Int2,
+        opens_at -> Time,
+        closes_at -> Time,
+    }
+}
+
+diesel::table! {
+    merchant_pauses (id) {
+        id -> Int4,
+        merchant_id -> Int4,
+        #[max_length = 6]
+        pincode -> Varchar,
+        #[max_length = 255]
+        reason -> Nullable<Varchar>,
+        paused_at -> Timestamptz,
+        expires_at -> Timestamptz,
+        resumed_at -> Nullable<Timestamptz>,
+    }
+}
+
+diesel::table! {
+    merchant_pincode_capacities (merchant_id, pincode) {
+        merchant_id -> Int4,
+        #[max_length = 6]
+        pincode -> Varchar,
+        daily_capacity -> Int4,
+    }
+}
+
+diesel::table! {
+    merchant_pincode_exclusions (merchant_id, pincode) {
+        merchant_id -> Int4,
+        #[max_length = 6]
+        pincode -> Varchar,
+    }
+}
+
+diesel::table! {
+    merchant_pincode_rules (merchant_id, rule_type, value) {
+        merchant_id -> Int4,
+        #[max_length = 16]
+        rule_type -> Varchar,
+        #[max_length = 13]
+        value -> Varchar,
+    }
+}
+
+diesel::table! {
+    merchant_regions (merchant_id, region_type, name) {
+        merchant_id -> Int4,
+        #[max_length = 16]
+        region_type -> Varchar,
+        #[max_length = 255]
+        name -> Varchar,
+    }
+}
+
 diesel::table! {
     merchants (id) {
         id -> Int4,
@@ -11,6 +114,97 @@ diesel::table! {
         phone_number -> Varchar,
         #[max_length = 255]
         email -> Varchar,
-        pincodes_serviced -> Varchar,
+        latitude -> Nullable<Float8>,
+        longitude -> Nullable<Float8>,
+        delivery_radius_km -> Nullable<Float8>,
+        #[max_length = 64]
+        external_ref -> Nullable<Varchar>,
+    }
+}
+
+diesel::table! {
+    store_pincodes (store_id, pincode) {
+        store_id -> Int4,
+        #[max_length = 6]
+        pincode -> Varchar,
+    }
+}
+
+diesel::table! {
+    stores (id) {
+        id -> Int4,
+        merchant_id -> Int4,
+        #[max_length = 255]
+        name -> Varchar,
+        #[max_length = 1024]
+        address -> Varchar,
+        latitude -> Nullable<Float8>,
+        longitude -> Nullable<Float8>,
+        #[max_length = 20]
+        phone_number -> Nullable<Varchar>,
+        #[max_length = 255]
+        email -> Nullable<Varchar>,
     }
 }
+
+diesel::table! {
+    import_job_errors (id) {
+        id -> Int4,
+        job_id -> Int4,
+        line -> Int8,
+        #[max_length = 255]
+        field -> Varchar,
+        message -> Text,
+    }
+}
+
+diesel::table! {
+    import_jobs (id) {
+        id -> Int4,
+        #[max_length = 16]
+        status -> Varchar,
+        #[max_length = 255]
+        file_name -> Nullable<Varchar>,
+        csv_data -> Bytea,
+        allow_unknown_pincodes -> Bool,
+        total_rows -> Int4,
+        processed_rows -> Int4,
+        accepted_rows -> Int4,
+        rejected_rows -> Int4,
+        error -> Nullable<Text>,
+        created_at -> Timestamptz,
+        started_at -> Nullable<Timestamptz>,
+        finished_at -> Nullable<Timestamptz>,
+        lease_expires_at -> Nullable<Timestamptz>,
+    }
+}
+
+diesel::joinable!(import_job_errors -> import_jobs (job_id));
+diesel::joinable!(merchant_capacities -> merchants (merchant_id));
+diesel::joinable!(merchant_holidays -> merchants (merchant_id));
+diesel::joinable!(merchant_operating_hours -> merchants (merchant_id));
+diesel::joinable!(merchant_pincodes -> merchants (merchant_id));
+diesel::joinable!(merchant_pincode_capacities -> merchants (merchant_id));
+diesel::joinable!(merchant_pincode_exclusions -> merchants (merchant_id));
+diesel::joinable!(merchant_pincode_rules -> merchants (merchant_id));
+diesel::joinable!(merchant_regions -> merchants (merchant_id));
+diesel::joinable!(store_pincodes -> stores (store_id));
+diesel::joinable!(stores -> merchants (merchant_id));
+
+diesel::allow_tables_to_appear_in_same_query!(
+    import_job_errors,
+    import_jobs,
+    merchant_capacities,
+    merchant_holidays,
+    merchant_operating_hours,
+    merchant_pauses,
+    merchant_pincode_capacities,
+    merchant_pincode_exclusions,
+    merchant_pincode_rules,
+    merchant_pincodes,
+    merchant_regions,
+    merchants,
+    pincode_directory,
+    store_pincodes,
+    stores,
+);
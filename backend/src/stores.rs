@@ -0,0 +1,601 @@
+use std::collections::{BTreeSet, HashMap};
+
+use rocket::serde::json::{Json, json};
+use rocket::State;
+use rocket_db_pools::Connection;
+use rocket_db_pools::diesel::{AsyncPgConnection, prelude::*};
+use rocket_db_pools::diesel::scoped_futures::ScopedFutureExt;
+
+use crate::{directory, exclusions, index, models, utils, validation, Db, RedisClient};
+
+// Pincode serviced by a store, (merchant_id, store_id, pincode)
+pub type ServicedStorePincode = (i32, i32, String);
+
+async fn load_store(db: &mut AsyncPgConnection, merchant_id: i32, store_id: i32) -> QueryResult<models::Store> {
+    use crate::schema::stores;
+
+    stores::table
+        .filter(stores::id.eq(store_id))
+        .filter(stores::merchant_id.eq(merchant_id))
+        .select(models::Store::as_select())
+        .first(db)
+        .await
+}
+
+pub async fn load_stores_by_id(db: &mut AsyncPgConnection, store_ids: &[i32]) -> QueryResult<HashMap<i32, models::Store>> {
+    use crate::schema::stores;
+
+    let rows = stores::table
+        .filter(stores::id.eq_any(store_ids))
+        .select(models::Store::as_select())
+        .load(db)
+        .await?;
+
+    Ok(rows.into_iter().map(|store| (store.id, store)).collect())
+}
+
+// Pincodes serviced by the stores of the merchants, or of every merchant when none are given
+pub async fn load_store_pincodes(db: &mut AsyncPgConnection, merchant_ids: Option<&[i32]>) -> QueryResult<Vec<ServicedStorePincode>> {
+    use crate::schema::{store_pincodes, stores};
+
+    let mut query = store_pincodes::table
+        .inner_join(stores::table)
+        .select((stores::merchant_id, store_pincodes::store_id, store_pincodes::pincode))
+        .order((stores::merchant_id.asc(), store_pincodes::store_id.asc(), store_pincodes::pincode.asc()))
+        .into_boxed();
+    if let Some(merchant_ids) = merchant_ids {
+        query = query.filter(stores::merchant_id.eq_any(merchant_ids));
+    }
+
+    query.load(db).await
+}
+
+// Pincodes among the given ones the merchant still services, itself or through one of its stores
+async fn still_serviced(db: &mut AsyncPgConnection, merchant_id: i32, pincodes: &[String]) -> QueryResult<BTreeSet<String>> {
+    use crate::schema::{merchant_pincodes, store_pincodes, stores};
+
+    if pincodes.is_empty() {
+        return Ok(BTreeSet::new());
+    }
+
+    let direct = merchant_pincodes::table
+        .filter(merchant_pincodes::merchant_id.eq(merchant_id))
+        .filter(merchant_pincodes::pincode.eq_any(pincodes))
+        .select(merchant_pincodes::pincode)
+        .load::<String>(db)
+        .await?;
+    let through_stores = store_pincodes::table
+        .inner_join(stores::table)
+        .filter(stores::merchant_id.eq(merchant_id))
+        .filter(store_pincodes::pincode.eq_any(pincodes))
+        .select(store_pincodes::pincode)
+        .load::<String>(db)
+        .await?;
+
+    Ok(direct.into_iter().chain(through_stores).collect())
+}
+
+// Pincodes among the removed ones the merchant no longer services at all
+pub async fn unserviced_pincodes(db: &mut AsyncPgConnection, merchant_id: i32, removed: &[String]) -> QueryResult<Vec<String>> {
+    let serviced = still_serviced(db, merchant_id, removed).await?;
+    Ok(removed.iter().filter(|pincode| !serviced.contains(*pincode)).cloned().collect())
+}
+
+// Inserts the pincodes for a store, skipping ones already serviced. Returns the newly added pincodes
+async fn insert_store_pincodes(db: &mut AsyncPgConnection, store_id: i32, pincodes: &[String]) -> QueryResult<Vec<String>> {
+    use crate::schema::store_pincodes;
+
+    if pincodes.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let rows: Vec<models::StorePincode> = pincodes
+        .iter()
+        .map(|code| models::StorePincode { store_id, pincode: code.clone() })
+        .collect();
+
+    diesel::insert_into(store_pincodes::table)
+        .values(&rows)
+        .on_conflict_do_nothing()
+        .returning(store_pincodes::pincode)
+        .get_results(db)
+        .await
+}
+
+// Removes the pincodes of a store. Returns the pincodes that were actually removed
+async fn remove_store_pincodes(db: &mut AsyncPgConnection, store_id: i32, pincodes: &[String]) -> QueryResult<Vec<String>> {
+    use crate::schema::store_pincodes;
+
+    diesel::delete(
+        store_pincodes::table
+            .filter(store_pincodes::store_id.eq(store_id))
+            .filter(store_pincodes::pincode.eq_any(pincodes)),
+    )
+    .returning(store_pincodes::pincode)
+    .get_results(db)
+    .await
+}
+
+// Removes the pincodes from every store of the merchant. Returns the removed (store_id, pincode) pairs
+pub async fn remove_merchant_store_pincodes(db: &mut AsyncPgConnection, merchant_id: i32, pincodes: &[String]) -> QueryResult<Vec<(i32, String)>> {
+    use crate::schema::{store_pincodes, stores};
+
+    let store_ids = stores::table.filter(stores::merchant_id.eq(merchant_id)).select(stores::id);
+
+    diesel::delete(
+        store_pincodes::table
+            .filter(store_pincodes::store_id.eq_any(store_ids))
+            .filter(store_pincodes::pincode.eq_any(pincodes)),
+    )
+    .returning((store_pincodes::store_id, store_pincodes::pincode))
+    .get_results(db)
+    .await
+}
+
+// Adds the store to the pincode's stores set, and the merchant to the pincode like a pincode it services itself
+pub fn add_store_commands(pipe: &mut redis::Pipeline, version: u64, merchant_id: i32, store_id: i32, pincode: &str) {
+    pipe.sadd(index::stores_key(version, pincode), index::store_member(merchant_id, store_id)).ignore();
+    pipe.sadd(index::pincode_key(version, pincode), merchant_id).ignore();
+    pipe.sadd(index::merchant_pincodes_key(version, merchant_id), pincode).ignore();
+}
+
+pub fn remove_store_commands(pipe: &mut redis::Pipeline, version: u64, merchant_id: i32, removed: &[(i32, String)], unserviced: &[String]) {
+    for (store_id, pincode) in removed {
+        pipe.srem(index::stores_key(version, pincode), index::store_member(merchant_id, *store_id)).ignore();
+    }
+    for pincode in unserviced {
+        pipe.srem(index::pincode_key(version, pincode), merchant_id).ignore();
+        pipe.srem(index::merchant_pincodes_key(version, merchant_id), pincode).ignore();
+    }
+}
+
+// Stores of the merchants servicing each of the pincodes. None when none of the merchants has a store there
+pub fn retrieve_store_matches(redis_client: &redis::Client, pincodes: &[String], merchant_ids: &[u32]) -> redis::RedisResult<Option<Vec<utils::StoreMatch>>> {
+    if merchant_ids.is_empty() || pincodes.is_empty() {
+        return Ok(None);
+    }
+
+    let mut con = redis_client.get_connection()?;
+    let version = index::current_version(&mut con)?;
+
+    let mut pipe = redis::pipe();
+    for pincode in pincodes {
+        pipe.smembers(index::stores_key(version, pincode));
+    }
+    let members: Vec<Vec<String>> = pipe.query(&mut con)?;
+
+    let mut matches = Vec::new();
+    for (pincode, members) in pincodes.iter().zip(members) {
+        let mut stores: Vec<(u32, i32)> = members
+            .iter()
+            .filter_map(|member| index::parse_store_member(member))
+            .map(|(merchant_id, store_id)| (merchant_id as u32, store_id))
+            .filter(|(merchant_id, _)| merchant_ids.contains(merchant_id))
+            .collect();
+        stores.sort();
+
+        matches.extend(stores.into_iter().map(|(merchant_id, store_id)| utils::StoreMatch {
+            merchant_id,
+            store_id,
+            pincode: pincode.clone(),
+            store: None,
+        }));
+    }
+
+    Ok((!matches.is_empty()).then_some(matches))
+}
+
+// Pincodes the merchant hasn't excluded among the normalized ones, and the ones it has.
+// Unknown pincodes are rejected unless allow_unknown_pincodes is set
+async fn check_store_pincodes(db: &mut AsyncPgConnection, merchant_id: i32, pincodes: Vec<String>, allow_unknown_pincodes: bool) -> Result<(Vec<String>, Vec<String>), utils::ApiResponse> {
+    if !allow_unknown_pincodes {
+        directory::check_pincodes(db, "pincodes_serviced", &pincodes).await?;
+    }
+
+    match exclusions::excluded_pincodes(db, merchant_id, &pincodes).await {
+        Ok(excluded) => {
+            let (excluded, pincodes): (Vec<String>, Vec<String>) = pincodes.into_iter().partition(|code| excluded.contains(code));
+            Ok((pincodes, excluded))
+        }
+        Err(err) => {
+            eprintln!("Error reading the exclusions of merchant {}: {:?}", merchant_id, err);
+            Err(utils::ApiResponse {
+                status: utils::ApiResponseStatus::Error,
+                data: json!({"message": "Failed to read the merchant exclusions"}).into(),
+            })
+        }
+    }
+}
+
+// Adds and removes pincodes of a store in Postgres, and their index writes to the update.
+// Returns the pincodes actually added and removed
+async fn update_store_pincodes(db: &mut AsyncPgConnection, update: &mut index::IndexUpdate, store: &models::Store, add: &[String], remove: &[String]) -> QueryResult<(Vec<String>, Vec<String>)> {
+    let added = insert_store_pincodes(db, store.id, add).await?;
+    let removed = remove_store_pincodes(db, store.id, remove).await?;
+    let unserviced = unserviced_pincodes(db, store.merchant_id, &removed).await?;
+
+    let (merchant_id, store_id) = (store.merchant_id, store.id);
+    let new_pincodes = added.clone();
+    let removed_pairs: Vec<(i32, String)> = removed.iter().map(|pincode| (store_id, pincode.clone())).collect();
+    update.add(merchant_id, move |_, pipe, version| {
+        for pincode in &new_pincodes {
+            add_store_commands(pipe, version, merchant_id, store_id, pincode);
+        }
+        remove_store_commands(pipe, version, merchant_id, &removed_pairs, &unserviced);
+        Ok(())
+    });
+
+    Ok((added, removed))
+}
+
+fn store_not_found(merchant_id: i32, store_id: i32, err: diesel::result::Error) -> Json<utils::ApiResponse> {
+    eprintln!("Error fetching store {} of merchant {}: {:?}", store_id, merchant_id, err);
+    Json(utils::ApiResponse {
+        status: utils::ApiResponseStatus::Error,
+        data: json!({"message": format!("Store {} of merchant {} not found", store_id, merchant_id)}).into(),
+    })
+}
+
+// Lists the stores of the merchant along with the pincodes each of them services
+#[get("/merchant/<merchant_id>/stores")]
+pub(crate) async fn get_stores(mut db: Connection<Db>, merchant_id: i32) -> Json<utils::ApiResponse> {
+    use crate::schema::stores;
+
+    if let Err(response) = crate::merchant_exists(&mut db, merchant_id).await {
+        return Json(response);
+    }
+
+    let rows = stores::table
+        .filter(stores::merchant_id.eq(merchant_id))
+        .select(models::Store::as_select())
+        .order(stores::id.asc())
+        .load(&mut db)
+        .await;
+    let result = match rows {
+        Ok(rows) => load_store_pincodes(&mut db, Some(&[merchant_id])).await.map(|pincodes| (rows, pincodes)),
+        Err(err) => Err(err),
+    };
+
+    match result {
+        Ok((rows, pincodes)) => {
+            let mut pincodes_by_store: HashMap<i32, Vec<String>> = HashMap::new();
+            for (_, store_id, pincode) in pincodes {
+                pincodes_by_store.entry(store_id).or_default().push(pincode);
+            }
+
+            let stores: Vec<utils::StoreInfo> = rows
+                .into_iter()
+                .map(|store| {
+                    let pincodes_serviced = pincodes_by_store.remove(&store.id).unwrap_or_default();
+                    utils::StoreInfo { store, pincodes_serviced }
+                })
+                .collect();
+
+            Json(utils::ApiResponse {
+                status: utils::ApiResponseStatus::Success,
+                data: json!({"ONDC_merchant_id": format!("{}", merchant_id), "stores": stores}).into(),
+            })
+        }
+        Err(err) => Json(utils::ApiResponse {
+            status: utils::ApiResponseStatus::Error,
+            data: json!({"message": format!("Failed to read the stores: {:?}", err)}).into(),
+        }),
+    }
+}
+
+// Adds a store to the merchant along with the pincodes it services, which the merchant then services as well.
+// Pincodes the merchant excluded are skipped, pincodes missing from the pincode directory are rejected unless allow_unknown_pincodes=true
+#[post("/merchant/<merchant_id>/stores?<allow_unknown_pincodes>", format = "json", data = "<store>")]
+pub(crate) async fn add_store(redis: &State<RedisClient>, mut db: Connection<Db>, store: Json<utils::StoreData>, merchant_id: i32, allow_unknown_pincodes: Option<bool>) -> Json<utils::ApiResponse> {
+    if let Err(response) = crate::merchant_exists(&mut db, merchant_id).await {
+        return Json(response);
+    }
+
+    let mut store = store.into_inner();
+    store.pincodes_serviced = store.pincodes_serviced.map(utils::normalize_pincodes);
+    if let Err(errors) = validation::validate_store(&store) {
+        return Json(validation::error_response(errors));
+    }
+
+    let pincodes = store.pincodes_serviced.clone().unwrap_or_default();
+    let (pincodes, excluded) = match check_store_pincodes(&mut db, merchant_id, pincodes, allow_unknown_pincodes.unwrap_or(false)).await {
+        Ok(checked) => checked,
+        Err(response) => return Json(response),
+    };
+
+    let details = store.details();
+
+    let result = db.transaction::<_, utils::StoreError, _>(|conn| async move {
+        use crate::schema::stores;
+
+        let store = diesel::insert_into(stores::table)
+            .values((stores::merchant_id.eq(merchant_id), &details))
+            .returning(models::Store::as_returning())
+            .get_result(conn)
+            .await?;
+        let mut update = index::IndexUpdate::default();
+        let (added, _) = update_store_pincodes(conn, &mut update, &store, &pincodes, &[]).await?;
+
+        Ok((store, added, update))
+    }.scope_boxed()).await;
+
+    match result {
+        Ok((store, added, update)) => {
+            update.apply(&redis.client);
+            Json(utils::ApiResponse {
+                status: utils::ApiResponseStatus::Success,
+                data: json!({
+                    "ONDC_merchant_id": format!("{}", merchant_id),
+                    "store_id": store.id,
+                    "pincodes_added": added,
+                    "pincodes_excluded": excluded,
+                    "message": "Store added"
+                }).into(),
+            })
+        }
+        Err(err) => {
+            eprintln!("Failed to add a store to merchant {}: {}", merchant_id, err);
+            Json(utils::ApiResponse {
+                status: utils::ApiResponseStatus::Error,
+                data: json!({"message": format!("Failed to add the store: {}", err)}).into(),
+            })
+        }
+    }
+}
+
+// Replaces the details of a store. When pincodes_serviced is sent it replaces the store's pincodes as well
+#[put("/merchant/<merchant_id>/stores/<store_id>?<allow_unknown_pincodes>", format = "json", data = "<store>")]
+pub(crate) async fn update_store(redis: &State<RedisClient>, mut db: Connection<Db>, store: Json<utils::StoreData>, merchant_id: i32, store_id: i32, allow_unknown_pincodes: Option<bool>) -> Json<utils::ApiResponse> {
+    let current = match load_store(&mut db, merchant_id, store_id).await {
+        Ok(current) => current,
+        Err(err) => return store_not_found(merchant_id, store_id, err),
+    };
+
+    let mut store = store.into_inner();
+    store.pincodes_serviced = store.pincodes_serviced.map(utils::normalize_pincodes);
+    if let Err(errors) = validation::validate_store(&store) {
+        return Json(validation::error_response(errors));
+    }
+
+    let (pincodes, excluded) = match store.pincodes_serviced.clone() {
+        Some(pincodes) => match check_store_pincodes(&mut db, merchant_id, pincodes, allow_unknown_pincodes.unwrap_or(false)).await {
+            Ok((pincodes, excluded)) => (Some(pincodes), excluded),
+            Err(response) => return Json(response),
+        },
+        None => (None, Vec::new()),
+    };
+
+    let details = store.details();
+
+    let result = db.transaction::<_, utils::StoreError, _>(|conn| async move {
+        use crate::schema::stores;
+
+        let store = diesel::update(stores::table.filter(stores::id.eq(current.id)))
+            .set(&details)
+            .returning(models::Store::as_returning())
+            .get_result(conn)
+            .await?;
+
+        let mut update = index::IndexUpdate::default();
+        let Some(pincodes) = pincodes else {
+            return Ok((store, Vec::new(), Vec::new(), update));
+        };
+        let serviced: Vec<String> = load_store_pincodes(conn, Some(&[merchant_id]))
+            .await?
+            .into_iter()
+            .filter(|(_, id, _)| *id == store.id)
+            .map(|(_, _, pincode)| pincode)
+            .collect();
+        let remove: Vec<String> = serviced.into_iter().filter(|code| !pincodes.contains(code)).collect();
+        let (added, removed) = update_store_pincodes(conn, &mut update, &store, &pincodes, &remove).await?;
+
+        Ok((store, added, removed, update))
+    }.scope_boxed()).await;
+
+    match result {
+        Ok((store, added, removed, update)) => {
+            update.apply(&redis.client);
+            Json(utils::ApiResponse {
+                status: utils::ApiResponseStatus::Success,
+                data: json!({
+                    "ONDC_merchant_id": format!("{}", merchant_id),
+                    "store": store,
+                    "pincodes_added": added,
+                    "pincodes_removed": removed,
+                    "pincodes_excluded": excluded,
+                    "message": "Store updated"
+                }).into(),
+            })
+        }
+        Err(err) => {
+            eprintln!("Failed to update store {} of merchant {}: {}", store_id, merchant_id, err);
+            Json(utils::ApiResponse {
+                status: utils::ApiResponseStatus::Error,
+                data: json!({"message": format!("Failed to update the store: {}", err)}).into(),
+            })
+        }
+    }
+}
+
+// Adds pincodes to a store's serviceability, skipping the ones the merchant excluded
+#[put("/merchant/<merchant_id>/stores/<store_id>/pincodes?<allow_unknown_pincodes>", format = "json", data = "<pincode_data>")]
+pub(crate) async fn add_store_pincodes(redis: &State<RedisClient>, mut db: Connection<Db>, pincode_data: Json<utils::Pincodes>, merchant_id: i32, store_id: i32, allow_unknown_pincodes: Option<bool>) -> Json<utils::ApiResponse> {
+    let store = match load_store(&mut db, merchant_id, store_id).await {
+        Ok(store) => store,
+        Err(err) => return store_not_found(merchant_id, store_id, err),
+    };
+
+    let pincodes = utils::normalize_pincodes(pincode_data.into_inner().pincodes);
+    if let Err(errors) = validation::validate_serviced_pincodes(&pincodes) {
+        return Json(validation::error_response(errors));
+    }
+    let (pincodes, excluded) = match check_store_pincodes(&mut db, merchant_id, pincodes, allow_unknown_pincodes.unwrap_or(false)).await {
+        Ok(checked) => checked,
+        Err(response) => return Json(response),
+    };
+
+    let result = db.transaction::<_, utils::StoreError, _>(|conn| async move {
+        let mut update = index::IndexUpdate::default();
+        let (added, _) = update_store_pincodes(conn, &mut update, &store, &pincodes, &[]).await?;
+        Ok((added, update))
+    }.scope_boxed()).await;
+
+    match result {
+        Ok((added, update)) => {
+            update.apply(&redis.client);
+            Json(utils::ApiResponse {
+                status: utils::ApiResponseStatus::Success,
+                data: json!({
+                    "ONDC_merchant_id": format!("{}", merchant_id),
+                    "store_id": store_id,
+                    "pincodes_added": added,
+                    "pincodes_excluded": excluded,
+                    "message": "Store serviceability updated"
+                }).into(),
+            })
+        }
+        Err(err) => {
+            eprintln!("Failed to add pincodes to store {} of merchant {}: {}", store_id, merchant_id, err);
+            Json(utils::ApiResponse {
+                status: utils::ApiResponseStatus::Error,
+                data: json!({"message": format!("Failed to update the store serviceability: {}", err)}).into(),
+            })
+        }
+    }
+}
+
+// Removes pincodes from a store's serviceability. The merchant keeps servicing them if it does so itself or through another store
+#[delete("/merchant/<merchant_id>/stores/<store_id>/pincodes", format = "json", data = "<pincode_data>")]
+pub(crate) async fn delete_store_serviceability(redis: &State<RedisClient>, mut db: Connection<Db>, pincode_data: Json<utils::Pincodes>, merchant_id: i32, store_id: i32) -> Json<utils::ApiResponse> {
+    let store = match load_store(&mut db, merchant_id, store_id).await {
+        Ok(store) => store,
+        Err(err) => return store_not_found(merchant_id, store_id, err),
+    };
+
+    let pincodes = utils::normalize_pincodes(pincode_data.into_inner().pincodes);
+
+    let result = db.transaction::<_, utils::StoreError, _>(|conn| async move {
+        let mut update = index::IndexUpdate::default();
+        let (_, removed) = update_store_pincodes(conn, &mut update, &store, &[], &pincodes).await?;
+        Ok((removed, update))
+    }.scope_boxed()).await;
+
+    match result {
+        Ok((removed, _)) if removed.is_empty() => Json(utils::ApiResponse {
+            status: utils::ApiResponseStatus::Error,
+            data: json!({"message": "No pin codes were deleted"}).into(),
+        }),
+        Ok((removed, update)) => {
+            update.apply(&redis.client);
+            Json(utils::ApiResponse {
+                status: utils::ApiResponseStatus::Success,
+                data: json!({
+                    "ONDC_merchant_id": format!("{}", merchant_id),
+                    "store_id": store_id,
+                    "removed": removed,
+                    "message": "Store serviceability updated"
+                }).into(),
+            })
+        }
+        Err(err) => {
+            eprintln!("Failed to remove pincodes from store {} of merchant {}: {}", store_id, merchant_id, err);
+            Json(utils::ApiResponse {
+                status: utils::ApiResponseStatus::Error,
+                data: json!({"message": format!("Failed to update the store serviceability: {}", err)}).into(),
+            })
+        }
+    }
+}
+
+// Deletes a store along with its serviceability
+#[delete("/merchant/<merchant_id>/stores/<store_id>")]
+pub(crate) async fn delete_store(redis: &State<RedisClient>, mut db: Connection<Db>, merchant_id: i32, store_id: i32) -> Json<utils::ApiResponse> {
+    let store = match load_store(&mut db, merchant_id, store_id).await {
+        Ok(store) => store,
+        Err(err) => return store_not_found(merchant_id, store_id, err),
+    };
+
+    // The store_pincodes rows are removed along with the store (ON DELETE CASCADE)
+    let result = db.transaction::<_, utils::StoreError, _>(|conn| async move {
+        use crate::schema::stores;
+
+        let pincodes: Vec<String> = load_store_pincodes(conn, Some(&[merchant_id]))
+            .await?
+            .into_iter()
+            .filter(|(_, id, _)| *id == store.id)
+            .map(|(_, _, pincode)| pincode)
+            .collect();
+        diesel::delete(stores::table.filter(stores::id.eq(store.id))).execute(conn).await?;
+
+        let unserviced = unserviced_pincodes(conn, merchant_id, &pincodes).await?;
+        let removed: Vec<(i32, String)> = pincodes.iter().map(|pincode| (store.id, pincode.clone())).collect();
+
+        let mut update = index::IndexUpdate::default();
+        let unindexed = unserviced.clone();
+        update.add(merchant_id, move |_, pipe, version| {
+            remove_store_commands(pipe, version, merchant_id, &removed, &unindexed);
+            Ok(())
+        });
+
+        Ok((unserviced, update))
+    }.scope_boxed()).await;
+
+    match result {
+        Ok((unserviced, update)) => {
+            update.apply(&redis.client);
+            Json(utils::ApiResponse {
+                status: utils::ApiResponseStatus::Success,
+                data: json!({
+                    "ONDC_merchant_id": format!("{}", merchant_id),
+                    "store_id": store_id,
+                    "pincodes_removed": unserviced,
+                    "message": "Store deleted"
+                }).into(),
+            })
+        }
+        Err(err) => {
+            eprintln!("Failed to delete store {} of merchant {}: {}", store_id, merchant_id, err);
+            Json(utils::ApiResponse {
+                status: utils::ApiResponseStatus::Error,
+                data: json!({"message": format!("Failed to delete the store: {}", err)}).into(),
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn added_store_pincodes_index_the_store_and_the_merchant() {
+        let mut pipe = redis::pipe();
+        add_store_commands(&mut pipe, 2, 7, 11, "560001");
+
+        assert_eq!(
+            index::pipeline_commands(&pipe),
+            vec![
+                vec!["SADD", "v2:stores:560001", "7:11"],
+                vec!["SADD", "v2:pincodes:560001", "7"],
+                vec!["SADD", "v2:merchant:7:pincodes", "560001"],
+            ],
+        );
+    }
+
+    #[test]
+    fn merchant_stays_on_pincodes_still_serviced_by_another_store() {
+        let mut pipe = redis::pipe();
+        let removed = vec![(11, "560001".to_string()), (11, "560002".to_string())];
+        remove_store_commands(&mut pipe, 0, 7, &removed, &["560002".to_string()]);
+
+        assert_eq!(
+            index::pipeline_commands(&pipe),
+            vec![
+                vec!["SREM", "stores:560001", "7:11"],
+                vec!["SREM", "stores:560002", "7:11"],
+                vec!["SREM", "pincodes:560002", "7"],
+                vec!["SREM", "merchant:7:pincodes", "560002"],
+            ],
+        );
+    }
+}
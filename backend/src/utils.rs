@@ -1,6 +1,11 @@
 use rocket::serde::{Serialize, Deserialize};
 use rocket_contrib::json::JsonValue;
 use rocket::form::FromForm;
+use rocket::fs::TempFile;
+use std::collections::{BTreeMap, BTreeSet};
+use std::{env, fmt, io};
+
+use crate::models;
 
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -16,6 +21,24 @@ pub struct MerchantData {
     pub business_category: String,
     pub contact: ContactInformation,
     pub pincodes_serviced: Vec<String>,
+    // Hyperlocal merchants also service every point within delivery_radius_km of their store
+    #[serde(default)]
+    pub latitude: Option<f64>,
+    #[serde(default)]
+    pub longitude: Option<f64>,
+    #[serde(default)]
+    pub delivery_radius_km: Option<f64>,
+    // Seller ID or GSTIN, matched by CSV uploads with upsert=true
+    #[serde(default)]
+    pub external_ref: Option<String>,
+}
+
+// Merchant row along with the pincodes it services
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MerchantInfo {
+    #[serde(flatten)]
+    pub merchant: models::Merchant,
+    pub pincodes_serviced: Vec<String>,
 }
 
 #[derive(Debug)]
@@ -25,12 +48,292 @@ pub struct RedisClient {
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct MerchantServiceability {
-    pub merchant_ids: Vec<u32>
+    pub merchant_ids: Vec<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub merchants: Option<Vec<MerchantSummary>>,
+    // With explain=true, the rules that matched each merchant
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub explain: Option<BTreeMap<u32, Vec<RuleMatch>>>,
+    // Delivery terms of the merchants that set them for the requested pincodes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delivery: Option<BTreeMap<u32, Vec<PincodeDeliveryTerms>>>,
+    // Stores of the matched merchants servicing the requested pincodes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stores: Option<Vec<StoreMatch>>,
+}
+
+// Store of a merchant servicing a pincode, with its details when expand=merchant is requested
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoreMatch {
+    pub merchant_id: u32,
+    pub store_id: i32,
+    pub pincode: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub store: Option<StoreSummary>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoreSummary {
+    pub name: String,
+    pub address: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latitude: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub longitude: Option<f64>,
+    pub contact: ContactInformation,
+}
+
+impl StoreSummary {
+    // The store's contact details, falling back to the merchant's where the store has none
+    pub fn new(store: &models::Store, merchant: Option<&models::Merchant>) -> Self {
+        let fallback = |store_value: &Option<String>, merchant_value: Option<&String>| {
+            store_value.clone().or_else(|| merchant_value.cloned()).unwrap_or_default()
+        };
+
+        StoreSummary {
+            name: store.name.clone(),
+            address: store.address.clone(),
+            latitude: store.latitude,
+            longitude: store.longitude,
+            contact: ContactInformation {
+                phone_number: fallback(&store.phone_number, merchant.map(|merchant| &merchant.phone_number)),
+                email: fallback(&store.email, merchant.map(|merchant| &merchant.email)),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PincodeDeliveryTerms {
+    pub pincode: String,
+    #[serde(flatten)]
+    pub terms: models::DeliveryTerms,
+}
+
+// Rule through which a merchant services a pincode: "pincode" for an explicit match, "prefix", "range" or "radius"
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RuleMatch {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pincode: Option<String>,
+    pub rule: String,
+    pub value: String,
+}
+
+// Merchant details returned with the serviceability when expand=merchant is requested
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MerchantSummary {
+    pub id: i32,
+    pub name: String,
+    pub business_category: String,
+    pub contact: ContactInformation,
+}
+
+impl From<&models::Merchant> for MerchantSummary {
+    fn from(merchant: &models::Merchant) -> Self {
+        MerchantSummary {
+            id: merchant.id,
+            name: merchant.name.clone(),
+            business_category: merchant.business_category.clone(),
+            contact: ContactInformation {
+                phone_number: merchant.phone_number.clone(),
+                email: merchant.email.clone(),
+            },
+        }
+    }
+}
+
+// Query parameters of GET /merchant/serviceability, either pincodes or lat and lng are required
+#[derive(Debug, FromForm)]
+pub struct ServiceabilityQuery {
+    pub pincodes: Option<String>,
+    pub lat: Option<f64>,
+    pub lng: Option<f64>,
+    pub expand: Option<String>,
+    pub category: Option<String>,
+    pub mode: Option<String>,
+    pub explain: Option<bool>,
+    // RFC 3339 timestamp, only merchants open at that time are returned
+    pub at: Option<String>,
+    // Hides the merchants that used up their daily capacity
+    pub available_only: Option<bool>,
+}
+
+// Merchants servicing all or any of the requested pincodes
+#[derive(Debug, Serialize)]
+pub struct CombinedServiceability {
+    pub mode: String,
+    pub pincodes: Vec<String>,
+    #[serde(flatten)]
+    pub serviceability: MerchantServiceability,
+}
+
+// Merchants whose delivery radius covers the requested point
+#[derive(Debug, Serialize)]
+pub struct LocationServiceability {
+    pub latitude: f64,
+    pub longitude: f64,
+    #[serde(flatten)]
+    pub serviceability: MerchantServiceability,
+}
+
+// Inclusive range of pincodes
+#[derive(Debug, Clone, FromForm, Serialize, Deserialize)]
+pub struct PincodeRange {
+    pub from: String,
+    pub to: String,
 }
 
 #[derive(Debug, FromForm, Serialize, Deserialize)]
 pub struct Pincodes {
-    pub pincodes: Vec<String>
+    #[serde(default)]
+    pub pincodes: Vec<String>,
+    #[serde(default)]
+    pub districts: Vec<String>,
+    #[serde(default)]
+    pub states: Vec<String>,
+    #[serde(default)]
+    pub prefixes: Vec<String>,
+    #[serde(default)]
+    pub ranges: Vec<PincodeRange>,
+    // Delivery terms applied to the pincodes, districts and states of the request
+    #[serde(default)]
+    pub delivery_time_hours: Option<i32>,
+    #[serde(default)]
+    pub delivery_fee_paise: Option<i32>,
+    #[serde(default)]
+    pub min_order_value_paise: Option<i32>,
+}
+
+impl Pincodes {
+    pub fn delivery_terms(&self) -> models::DeliveryTerms {
+        models::DeliveryTerms {
+            delivery_time_hours: self.delivery_time_hours,
+            delivery_fee_paise: self.delivery_fee_paise,
+            min_order_value_paise: self.min_order_value_paise,
+        }
+    }
+}
+
+// Opening window on the given days ("mon" to "sun", every day when empty), "HH:MM" in India Standard Time
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OpeningHours {
+    #[serde(default)]
+    pub days: Vec<String>,
+    pub opens_at: String,
+    pub closes_at: String,
+}
+
+// Day the merchant is closed, "YYYY-MM-DD"
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Holiday {
+    pub date: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+}
+
+// Weekly opening hours and holidays of a merchant. Merchants without opening hours are always open
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MerchantSchedule {
+    #[serde(default)]
+    pub hours: Vec<OpeningHours>,
+    #[serde(default)]
+    pub holidays: Vec<Holiday>,
+}
+
+// Pincodes to pause a merchant for, until an RFC 3339 timestamp
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PauseRequest {
+    #[serde(default)]
+    pub pincodes: Vec<String>,
+    pub until: String,
+    #[serde(default)]
+    pub reason: Option<String>,
+}
+
+// Outlet of a merchant. Without a contact the merchant's contact is used
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoreData {
+    pub name: String,
+    pub address: String,
+    #[serde(default)]
+    pub latitude: Option<f64>,
+    #[serde(default)]
+    pub longitude: Option<f64>,
+    #[serde(default)]
+    pub contact: Option<ContactInformation>,
+    // Replaces the store's pincodes when sent on update
+    #[serde(default)]
+    pub pincodes_serviced: Option<Vec<String>>,
+}
+
+impl StoreData {
+    pub fn details(&self) -> models::StoreDetails {
+        models::StoreDetails {
+            name: self.name.trim().to_string(),
+            address: self.address.trim().to_string(),
+            latitude: self.latitude,
+            longitude: self.longitude,
+            phone_number: self.contact.as_ref().map(|contact| contact.phone_number.clone()),
+            email: self.contact.as_ref().map(|contact| contact.email.clone()),
+        }
+    }
+}
+
+// Store along with the pincodes it services
+#[derive(Debug, Serialize)]
+pub struct StoreInfo {
+    #[serde(flatten)]
+    pub store: models::Store,
+    pub pincodes_serviced: Vec<String>,
+}
+
+// Daily order capacity of a merchant, overall and per pincode. Unset limits are unlimited
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CapacityLimits {
+    #[serde(default)]
+    pub daily_capacity: Option<i32>,
+    #[serde(default)]
+    pub pincodes: BTreeMap<String, i32>,
+}
+
+// Orders taken by a merchant for a pincode, counted against its capacity
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CapacityConsumption {
+    pub pincode: String,
+    #[serde(default = "default_orders")]
+    pub orders: i32,
+}
+
+fn default_orders() -> i32 {
+    1
+}
+
+// Error for operations that write to both Postgres and Redis
+#[derive(Debug)]
+pub enum StoreError {
+    Postgres(diesel::result::Error),
+    Redis(redis::RedisError),
+}
+
+impl From<diesel::result::Error> for StoreError {
+    fn from(err: diesel::result::Error) -> Self {
+        StoreError::Postgres(err)
+    }
+}
+
+impl From<redis::RedisError> for StoreError {
+    fn from(err: redis::RedisError) -> Self {
+        StoreError::Redis(err)
+    }
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StoreError::Postgres(err) => write!(f, "PostgreSQL error: {}", err),
+            StoreError::Redis(err) => write!(f, "Redis error: {}", err),
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -43,4 +346,48 @@ pub enum ApiResponseStatus {
 pub struct ApiResponse {
     pub status: ApiResponseStatus,
     pub data: JsonValue,
-}
\ No newline at end of file
+}
+
+// Reads an uploaded file into memory, through a randomly named temporary file
+pub async fn read_upload(upload: &mut TempFile<'_>) -> io::Result<Vec<u8>> {
+    let filepath = env::temp_dir().join(format!("upload-{}", rand::random::<u64>()));
+    upload.persist_to(&filepath).await?;
+    let data = rocket::tokio::fs::read(&filepath).await;
+    let _ = rocket::tokio::fs::remove_file(&filepath).await;
+
+    data
+}
+
+// Trims the pincodes, drops empty entries and removes duplicates
+pub fn normalize_pincodes(pincodes: Vec<String>) -> Vec<String> {
+    pincodes
+        .into_iter()
+        .map(|pincode| pincode.trim().to_string())
+        .filter(|pincode| !pincode.is_empty())
+        .collect::<BTreeSet<String>>()
+        .into_iter()
+        .collect()
+}
+
+// Trims the external reference, an empty one is no reference
+pub fn normalize_external_ref(external_ref: Option<String>) -> Option<String> {
+    external_ref
+        .map(|external_ref| external_ref.trim().to_string())
+        .filter(|external_ref| !external_ref.is_empty())
+}
+
+// Categories are matched case-insensitively, so they are stored lowercased
+pub fn normalize_category(category: &str) -> String {
+    category.trim().to_lowercase()
+}
+
+// District and state names are matched case-insensitively, so they are stored lowercased
+pub fn normalize_regions(regions: Vec<String>) -> Vec<String> {
+    regions
+        .into_iter()
+        .map(|region| region.trim().to_lowercase())
+        .filter(|region| !region.is_empty())
+        .collect::<BTreeSet<String>>()
+        .into_iter()
+        .collect()
+}
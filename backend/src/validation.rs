@@ -0,0 +1,432 @@
+use lettre::Address;
+use rocket::serde::json::json;
+use rocket::serde::Serialize;
+
+use crate::{models, schedules, utils};
+
+// Limits of the VARCHAR columns in the merchants table
+const MAX_TEXT_LENGTH: usize = 255;
+const MAX_PHONE_NUMBER_LENGTH: usize = 20;
+const MAX_EXTERNAL_REF_LENGTH: usize = 64;
+
+// Redis GEOADD only accepts latitudes within the Web Mercator range
+const MAX_LATITUDE: f64 = 85.05112878;
+const MAX_LONGITUDE: f64 = 180.0;
+const MAX_DELIVERY_RADIUS_KM: f64 = 100.0;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn new(field: &str, message: String) -> Self {
+        FieldError { field: field.to_string(), message }
+    }
+}
+
+// Indian pincodes are 6 digits and never start with 0
+pub fn is_valid_pincode(pincode: &str) -> bool {
+    pincode.len() == 6
+        && pincode.bytes().all(|b| b.is_ascii_digit())
+        && !pincode.starts_with('0')
+}
+
+// Accepts a 10 digit Indian mobile number (starting with 6-9), optionally prefixed with +91,
+// or any other number in E.164 format (+ followed by up to 15 digits)
+pub fn is_valid_phone_number(phone_number: &str) -> bool {
+    let is_indian_mobile = |number: &str| {
+        number.len() == 10
+            && number.bytes().all(|b| b.is_ascii_digit())
+            && matches!(number.as_bytes()[0], b'6'..=b'9')
+    };
+
+    if let Some(number) = phone_number.strip_prefix("+91") {
+        return is_indian_mobile(number);
+    }
+
+    if let Some(number) = phone_number.strip_prefix('+') {
+        return (7..=15).contains(&number.len())
+            && number.bytes().all(|b| b.is_ascii_digit())
+            && !number.starts_with('0');
+    }
+
+    is_indian_mobile(phone_number)
+}
+
+fn validate_text(errors: &mut Vec<FieldError>, field: &str, value: &str) {
+    if value.trim().is_empty() {
+        errors.push(FieldError::new(field, "must not be empty".to_string()));
+    } else if value.chars().count() > MAX_TEXT_LENGTH {
+        errors.push(FieldError::new(field, format!("must be at most {} characters", MAX_TEXT_LENGTH)));
+    }
+}
+
+fn validate_phone_number(errors: &mut Vec<FieldError>, field: &str, phone_number: &str) {
+    if phone_number.len() > MAX_PHONE_NUMBER_LENGTH || !is_valid_phone_number(phone_number) {
+        errors.push(FieldError::new(
+            field,
+            format!("{} is not a valid phone number, expected a 10 digit Indian mobile number or an E.164 number", phone_number),
+        ));
+    }
+}
+
+fn validate_email(errors: &mut Vec<FieldError>, field: &str, email: &str) {
+    if email.chars().count() > MAX_TEXT_LENGTH || email.parse::<Address>().is_err() {
+        errors.push(FieldError::new(field, format!("{} is not a valid email address", email)));
+    }
+}
+
+// Seller IDs and GSTINs are letters and digits, with dashes, underscores, dots or slashes as separators
+fn validate_external_ref(errors: &mut Vec<FieldError>, external_ref: Option<&str>) {
+    let Some(external_ref) = external_ref else {
+        return;
+    };
+
+    if external_ref.chars().count() > MAX_EXTERNAL_REF_LENGTH {
+        errors.push(FieldError::new("external_ref", format!("must be at most {} characters", MAX_EXTERNAL_REF_LENGTH)));
+    } else if external_ref.is_empty() || !external_ref.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '/')) {
+        errors.push(FieldError::new(
+            "external_ref",
+            format!("{} is not a valid external reference, expected letters, digits, '-', '_', '.' or '/'", external_ref),
+        ));
+    }
+}
+
+fn validate_pincode_list(errors: &mut Vec<FieldError>, field: &str, pincodes: &[String]) {
+    for pincode in pincodes {
+        if !is_valid_pincode(pincode) {
+            errors.push(FieldError::new(
+                field,
+                format!("{} is not a valid pincode, expected 6 digits not starting with 0", pincode),
+            ));
+        }
+    }
+}
+
+// Checks a coordinate pair is one Redis can index
+pub fn validate_coordinates(errors: &mut Vec<FieldError>, latitude: f64, longitude: f64) {
+    if !(-MAX_LATITUDE..=MAX_LATITUDE).contains(&latitude) {
+        errors.push(FieldError::new("latitude", format!("{} is not a valid latitude, expected a value between -{} and {}", latitude, MAX_LATITUDE, MAX_LATITUDE)));
+    }
+    if !(-MAX_LONGITUDE..=MAX_LONGITUDE).contains(&longitude) {
+        errors.push(FieldError::new("longitude", format!("{} is not a valid longitude, expected a value between -{} and {}", longitude, MAX_LONGITUDE, MAX_LONGITUDE)));
+    }
+}
+
+fn validate_location(errors: &mut Vec<FieldError>, latitude: Option<f64>, longitude: Option<f64>, delivery_radius_km: Option<f64>) {
+    match (latitude, longitude, delivery_radius_km) {
+        (None, None, None) => {}
+        (Some(latitude), Some(longitude), Some(delivery_radius_km)) => {
+            validate_coordinates(errors, latitude, longitude);
+            if !(delivery_radius_km > 0.0 && delivery_radius_km <= MAX_DELIVERY_RADIUS_KM) {
+                errors.push(FieldError::new(
+                    "delivery_radius_km",
+                    format!("{} is not a valid delivery radius, expected more than 0 and at most {} km", delivery_radius_km, MAX_DELIVERY_RADIUS_KM),
+                ));
+            }
+        }
+        _ => errors.push(FieldError::new("delivery_radius_km", "latitude, longitude and delivery_radius_km must be set together".to_string())),
+    }
+}
+
+fn into_result(errors: Vec<FieldError>) -> Result<(), Vec<FieldError>> {
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+// Validates a merchant being onboarded through JSON or CSV
+pub fn validate_merchant(merchant_data: &utils::MerchantData) -> Result<(), Vec<FieldError>> {
+    let mut errors = Vec::new();
+
+    validate_text(&mut errors, "name", &merchant_data.name);
+    validate_text(&mut errors, "business_category", &merchant_data.business_category);
+    validate_phone_number(&mut errors, "contact.phone_number", &merchant_data.contact.phone_number);
+    validate_email(&mut errors, "contact.email", &merchant_data.contact.email);
+    validate_pincode_list(&mut errors, "pincodes_serviced", &merchant_data.pincodes_serviced);
+    validate_location(&mut errors, merchant_data.latitude, merchant_data.longitude, merchant_data.delivery_radius_km);
+    validate_external_ref(&mut errors, merchant_data.external_ref.as_deref());
+
+    into_result(errors)
+}
+
+// Validates the details sent to PUT /merchant/<merchant_id>
+pub fn validate_update(update_data: &models::UpdateMerchantData) -> Result<(), Vec<FieldError>> {
+    let mut errors = Vec::new();
+
+    validate_text(&mut errors, "name", &update_data.name);
+    validate_text(&mut errors, "business_category", &update_data.business_category);
+    validate_phone_number(&mut errors, "phone_number", &update_data.phone_number);
+    validate_email(&mut errors, "email", &update_data.email);
+    validate_location(&mut errors, update_data.latitude, update_data.longitude, update_data.delivery_radius_km);
+    validate_external_ref(&mut errors, update_data.external_ref.as_deref());
+
+    into_result(errors)
+}
+
+// Limit of the address column of the stores table
+const MAX_ADDRESS_LENGTH: usize = 1024;
+
+// Validates a store sent to POST or PUT /merchant/<merchant_id>/stores
+pub fn validate_store(store: &utils::StoreData) -> Result<(), Vec<FieldError>> {
+    let mut errors = Vec::new();
+
+    validate_text(&mut errors, "name", &store.name);
+    if store.address.trim().is_empty() {
+        errors.push(FieldError::new("address", "must not be empty".to_string()));
+    } else if store.address.chars().count() > MAX_ADDRESS_LENGTH {
+        errors.push(FieldError::new("address", format!("must be at most {} characters", MAX_ADDRESS_LENGTH)));
+    }
+    match (store.latitude, store.longitude) {
+        (None, None) => {}
+        (Some(latitude), Some(longitude)) => validate_coordinates(&mut errors, latitude, longitude),
+        _ => errors.push(FieldError::new("longitude", "latitude and longitude must be set together".to_string())),
+    }
+    if let Some(contact) = &store.contact {
+        validate_phone_number(&mut errors, "contact.phone_number", &contact.phone_number);
+        validate_email(&mut errors, "contact.email", &contact.email);
+    }
+    if let Some(pincodes) = &store.pincodes_serviced {
+        validate_pincode_list(&mut errors, "pincodes_serviced", pincodes);
+    }
+
+    into_result(errors)
+}
+
+// Prefixes cover a whole postal circle or region, so they are 1 to 5 digits and never start with 0
+pub fn is_valid_pincode_prefix(prefix: &str) -> bool {
+    (1..=5).contains(&prefix.len())
+        && prefix.bytes().all(|b| b.is_ascii_digit())
+        && !prefix.starts_with('0')
+}
+
+fn validate_ranges(errors: &mut Vec<FieldError>, ranges: &[utils::PincodeRange]) {
+    for range in ranges {
+        let (from, to) = (range.from.trim(), range.to.trim());
+
+        if !is_valid_pincode(from) || !is_valid_pincode(to) {
+            errors.push(FieldError::new("ranges", format!("{}-{} is not a valid range, expected two valid pincodes", from, to)));
+        } else if from > to {
+            errors.push(FieldError::new("ranges", format!("{}-{} is not a valid range, from must not be after to", from, to)));
+        }
+    }
+}
+
+// Validates the pincodes, districts, states, prefixes and ranges added to a merchant's serviceability
+pub fn validate_pincodes(pincodes: &[String], districts: &[String], states: &[String], prefixes: &[String], ranges: &[utils::PincodeRange]) -> Result<(), Vec<FieldError>> {
+    let mut errors = Vec::new();
+
+    if pincodes.is_empty() && districts.is_empty() && states.is_empty() && prefixes.is_empty() && ranges.is_empty() {
+        errors.push(FieldError::new("pincodes", "must contain at least one pincode, district, state, prefix or range".to_string()));
+    }
+    validate_pincode_list(&mut errors, "pincodes", pincodes);
+    for prefix in prefixes.iter().filter(|prefix| !is_valid_pincode_prefix(prefix)) {
+        errors.push(FieldError::new("prefixes", format!("{} is not a valid pincode prefix, expected 1 to 5 digits not starting with 0", prefix)));
+    }
+    validate_ranges(&mut errors, ranges);
+
+    into_result(errors)
+}
+
+// Validates pincodes serviced one by one, as by a store, without districts, states, prefixes or ranges
+pub fn validate_serviced_pincodes(pincodes: &[String]) -> Result<(), Vec<FieldError>> {
+    let mut errors = Vec::new();
+
+    if pincodes.is_empty() {
+        errors.push(FieldError::new("pincodes", "must contain at least one pincode".to_string()));
+    }
+    validate_pincode_list(&mut errors, "pincodes", pincodes);
+
+    into_result(errors)
+}
+
+// Delivery times are capped at 30 days
+const MAX_DELIVERY_TIME_HOURS: i32 = 720;
+
+// Validates the delivery terms set along with a merchant's pincodes
+pub fn validate_delivery_terms(terms: &models::DeliveryTerms) -> Result<(), Vec<FieldError>> {
+    let mut errors = Vec::new();
+
+    if let Some(hours) = terms.delivery_time_hours {
+        if !(1..=MAX_DELIVERY_TIME_HOURS).contains(&hours) {
+            errors.push(FieldError::new("delivery_time_hours", format!("{} is not a valid delivery time, expected 1 to {} hours", hours, MAX_DELIVERY_TIME_HOURS)));
+        }
+    }
+    if terms.delivery_fee_paise.is_some_and(|fee| fee < 0) {
+        errors.push(FieldError::new("delivery_fee_paise", "must not be negative".to_string()));
+    }
+    if terms.min_order_value_paise.is_some_and(|value| value < 0) {
+        errors.push(FieldError::new("min_order_value_paise", "must not be negative".to_string()));
+    }
+
+    into_result(errors)
+}
+
+// Validates the opening hours and holidays sent to PUT /merchant/<merchant_id>/schedule
+pub fn validate_schedule(schedule: &utils::MerchantSchedule) -> Result<(), Vec<FieldError>> {
+    let mut errors = Vec::new();
+
+    for hours in &schedule.hours {
+        for day in hours.days.iter().filter(|day| schedules::parse_day(day).is_none()) {
+            errors.push(FieldError::new("hours.days", format!("{} is not a valid day, expected mon to sun", day)));
+        }
+        for (field, time) in [("hours.opens_at", &hours.opens_at), ("hours.closes_at", &hours.closes_at)] {
+            if schedules::parse_time(time).is_none() {
+                errors.push(FieldError::new(field, format!("{} is not a valid time, expected HH:MM", time)));
+            }
+        }
+    }
+    for holiday in &schedule.holidays {
+        if schedules::parse_date(&holiday.date).is_none() {
+            errors.push(FieldError::new("holidays.date", format!("{} is not a valid date, expected YYYY-MM-DD", holiday.date)));
+        }
+        if holiday.name.as_ref().is_some_and(|name| name.chars().count() > MAX_TEXT_LENGTH) {
+            errors.push(FieldError::new("holidays.name", format!("must be at most {} characters", MAX_TEXT_LENGTH)));
+        }
+    }
+
+    into_result(errors)
+}
+
+// Pauses are meant to cover floods or strikes, not to replace removing the serviceability
+const MAX_PAUSE_DAYS: i64 = 30;
+
+// Validates a pause sent to POST /merchant/<merchant_id>/pause. Returns the time the pause expires
+pub fn validate_pause(pause: &utils::PauseRequest, now: rocket::time::OffsetDateTime) -> Result<rocket::time::OffsetDateTime, Vec<FieldError>> {
+    let mut errors = Vec::new();
+
+    if pause.pincodes.is_empty() {
+        errors.push(FieldError::new("pincodes", "must contain at least one pincode".to_string()));
+    }
+    validate_pincode_list(&mut errors, "pincodes", &pause.pincodes);
+    let until = schedules::parse_timestamp(&pause.until);
+    match until {
+        None => errors.push(FieldError::new("until", format!("{} is not a valid timestamp, expected RFC 3339", pause.until))),
+        Some(until) if until <= now => errors.push(FieldError::new("until", "must be in the future".to_string())),
+        Some(until) if until - now > rocket::time::Duration::days(MAX_PAUSE_DAYS) => {
+            errors.push(FieldError::new("until", format!("must be at most {} days away", MAX_PAUSE_DAYS)));
+        }
+        Some(_) => {}
+    }
+    if pause.reason.as_ref().is_some_and(|reason| reason.chars().count() > MAX_TEXT_LENGTH) {
+        errors.push(FieldError::new("reason", format!("must be at most {} characters", MAX_TEXT_LENGTH)));
+    }
+
+    into_result(errors).map(|_| until.unwrap_or(now))
+}
+
+// Validates the limits sent to PUT /merchant/<merchant_id>/capacity
+pub fn validate_capacity(limits: &utils::CapacityLimits) -> Result<(), Vec<FieldError>> {
+    let mut errors = Vec::new();
+
+    if limits.daily_capacity.is_some_and(|capacity| capacity <= 0) {
+        errors.push(FieldError::new("daily_capacity", "must be greater than 0".to_string()));
+    }
+    for (pincode, capacity) in &limits.pincodes {
+        if !is_valid_pincode(pincode) {
+            errors.push(FieldError::new("pincodes", format!("{} is not a valid pincode, expected 6 digits not starting with 0", pincode)));
+        }
+        if *capacity <= 0 {
+            errors.push(FieldError::new("pincodes", format!("capacity for {} must be greater than 0", pincode)));
+        }
+    }
+
+    into_result(errors)
+}
+
+// Validates the orders sent to POST /merchant/<merchant_id>/capacity/consume
+pub fn validate_consumption(consumption: &utils::CapacityConsumption) -> Result<(), Vec<FieldError>> {
+    let mut errors = Vec::new();
+
+    validate_pincode_list(&mut errors, "pincode", std::slice::from_ref(&consumption.pincode));
+    if consumption.orders <= 0 {
+        errors.push(FieldError::new("orders", "must be greater than 0".to_string()));
+    }
+
+    into_result(errors)
+}
+
+// Validates the pincodes a merchant excludes from its serviceability
+pub fn validate_exclusions(pincodes: &[String]) -> Result<(), Vec<FieldError>> {
+    let mut errors = Vec::new();
+
+    if pincodes.is_empty() {
+        errors.push(FieldError::new("pincodes", "must contain at least one pincode".to_string()));
+    }
+    validate_pincode_list(&mut errors, "pincodes", pincodes);
+
+    into_result(errors)
+}
+
+pub fn error_response(errors: Vec<FieldError>) -> utils::ApiResponse {
+    utils::ApiResponse {
+        status: utils::ApiResponseStatus::Error,
+        data: json!({"message": "Validation failed", "errors": errors}).into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields(result: Result<(), Vec<FieldError>>) -> Vec<String> {
+        result.err().unwrap_or_default().into_iter().map(|error| error.field).collect()
+    }
+
+    #[test]
+    fn pincodes_are_six_digits_not_starting_with_zero() {
+        assert!(is_valid_pincode("560001"));
+        assert!(!is_valid_pincode("056001"));
+        assert!(!is_valid_pincode("56001"));
+        assert!(!is_valid_pincode("5600011"));
+        assert!(!is_valid_pincode("56000a"));
+        assert!(!is_valid_pincode(" 560001"));
+    }
+
+    #[test]
+    fn phone_numbers_are_indian_mobiles_or_e164() {
+        assert!(is_valid_phone_number("9876543210"));
+        assert!(is_valid_phone_number("+919876543210"));
+        assert!(is_valid_phone_number("+14155550123"));
+        assert!(!is_valid_phone_number("5876543210"));
+        assert!(!is_valid_phone_number("+915876543210"));
+        assert!(!is_valid_phone_number("98765 43210"));
+        assert!(!is_valid_phone_number("+0123456789"));
+        assert!(!is_valid_phone_number("+1234567890123456"));
+    }
+
+    #[test]
+    fn emails_must_be_addresses() {
+        let check = |email: &str| {
+            let mut errors = Vec::new();
+            validate_email(&mut errors, "contact.email", email);
+            errors.len()
+        };
+
+        assert_eq!(check("owner@freshmart.in"), 0);
+        assert_eq!(check("not-an-email"), 1);
+        assert_eq!(check("owner@"), 1);
+        assert_eq!(check(&format!("{}@freshmart.in", "a".repeat(MAX_TEXT_LENGTH))), 1);
+    }
+
+    #[test]
+    fn serviced_pincodes_must_be_valid_and_not_empty() {
+        assert!(validate_serviced_pincodes(&["560001".to_string()]).is_ok());
+        assert_eq!(fields(validate_serviced_pincodes(&[])), vec!["pincodes"]);
+        assert_eq!(fields(validate_serviced_pincodes(&["560001".to_string(), "bad".to_string(), "012345".to_string()])), vec!["pincodes", "pincodes"]);
+    }
+
+    #[test]
+    fn delivery_terms_are_bounded() {
+        let terms = |hours, fee| models::DeliveryTerms { delivery_time_hours: hours, delivery_fee_paise: fee, min_order_value_paise: None };
+
+        assert!(validate_delivery_terms(&terms(Some(1), Some(0))).is_ok());
+        assert!(validate_delivery_terms(&terms(Some(MAX_DELIVERY_TIME_HOURS), None)).is_ok());
+        assert_eq!(fields(validate_delivery_terms(&terms(Some(0), Some(-1)))), vec!["delivery_time_hours", "delivery_fee_paise"]);
+        assert_eq!(fields(validate_delivery_terms(&terms(Some(MAX_DELIVERY_TIME_HOURS + 1), None))), vec!["delivery_time_hours"]);
+    }
+}
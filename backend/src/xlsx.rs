@@ -0,0 +1,162 @@
+use std::io::{Cursor, Read};
+
+use calamine::{Data, Reader, Xlsx};
+use zip::ZipArchive;
+
+// Largest uncompressed part of the workbook that is read, so that a small upload can't expand into gigabytes
+const MAX_ENTRY_SIZE: u64 = 64 * 1024 * 1024;
+
+// Columns run from A to XFD
+const MAX_COLUMNS: usize = 16384;
+
+// XLSX files are ZIP archives, which start with a local file header
+pub fn is_xlsx(data: &[u8]) -> bool {
+    data.starts_with(b"PK\x03\x04")
+}
+
+// calamine reads whole parts of the archive into memory, so every part is inflated up to the limit first.
+// The size in the archive is declared by the file, reading stops past the limit whatever it says
+fn check_entry_sizes(data: &[u8]) -> Result<(), String> {
+    let mut archive = ZipArchive::new(Cursor::new(data)).map_err(|err| format!("Failed to open the XLSX file: {}", err))?;
+    for index in 0..archive.len() {
+        let mut entry = archive.by_index(index).map_err(|err| format!("Failed to read the XLSX file: {}", err))?;
+        let name = entry.name().to_string();
+        let too_large = || format!("{} in the XLSX file is larger than {} MB uncompressed", name, MAX_ENTRY_SIZE / (1024 * 1024));
+        if entry.size() > MAX_ENTRY_SIZE {
+            return Err(too_large());
+        }
+        let size = std::io::copy(&mut entry.by_ref().take(MAX_ENTRY_SIZE + 1), &mut std::io::sink())
+            .map_err(|err| format!("Failed to read {} from the XLSX file: {}", name, err))?;
+        if size > MAX_ENTRY_SIZE {
+            return Err(too_large());
+        }
+    }
+
+    Ok(())
+}
+
+// Reads the rows of the first worksheet as text, along with their row numbers. Empty cells are empty strings
+pub fn read_rows(data: &[u8]) -> Result<Vec<(u64, Vec<String>)>, String> {
+    check_entry_sizes(data)?;
+    // calamine indexes the shared strings without checking the index, a malformed upload is refused instead of panicking
+    std::panic::catch_unwind(|| read_sheet(data)).unwrap_or_else(|_| Err("The XLSX worksheet refers to shared strings that don't exist".to_string()))
+}
+
+fn read_sheet(data: &[u8]) -> Result<Vec<(u64, Vec<String>)>, String> {
+    let mut workbook: Xlsx<_> = Xlsx::new(Cursor::new(data)).map_err(|err| format!("The file is not an XLSX workbook: {}", err))?;
+    let sheet = workbook.sheet_names().first().cloned().ok_or_else(|| "The XLSX workbook has no worksheets".to_string())?;
+
+    // Cells are streamed rather than read as a range, which would allocate every cell up to the furthest one
+    let mut cells = workbook.worksheet_cells_reader(&sheet).map_err(|err| format!("Failed to read the XLSX worksheet: {}", err))?;
+    let mut rows: Vec<(u64, Vec<String>)> = Vec::new();
+    while let Some(cell) = cells.next_cell().map_err(|err| format!("Failed to read the XLSX worksheet: {}", err))? {
+        let (row, column) = cell.get_position();
+        let column = column as usize;
+        if column >= MAX_COLUMNS {
+            return Err(format!("The XLSX worksheet has more than {} columns", MAX_COLUMNS));
+        }
+
+        let line = row as u64 + 1;
+        if rows.last().map(|(last, _)| *last != line).unwrap_or(true) {
+            rows.push((line, Vec::new()));
+        }
+        let text = Data::from(cell.get_value().clone()).to_string();
+        if let Some((_, cells)) = rows.last_mut() {
+            if cells.len() <= column {
+                cells.resize(column + 1, String::new());
+            }
+            cells[column] = text;
+        }
+    }
+
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use zip::write::FileOptions;
+    use zip::{CompressionMethod, ZipWriter};
+
+    use super::*;
+
+    fn workbook(sheet_rows: &str) -> Vec<u8> {
+        let entries = [
+            (
+                "xl/workbook.xml",
+                r#"<workbook xmlns:r="http://schemas.openxmlformats.org/officeDocument/2006/relationships"><sheets><sheet name="Merchants" sheetId="1" r:id="rId1"/></sheets></workbook>"#.to_string(),
+            ),
+            ("xl/_rels/workbook.xml.rels", r#"<Relationships><Relationship Id="rId1" Target="worksheets/merchants.xml"/></Relationships>"#.to_string()),
+            ("xl/sharedStrings.xml", "<sst><si><t>name</t></si><si><r><t>Fresh</t></r><r><t xml:space=\"preserve\"> Mart</t></r></si><si><t></t></si></sst>".to_string()),
+            ("xl/worksheets/merchants.xml", format!("<worksheet><sheetData>{}</sheetData></worksheet>", sheet_rows)),
+        ];
+
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, content) in entries {
+            writer.start_file(name, FileOptions::default().compression_method(CompressionMethod::Stored)).unwrap();
+            writer.write_all(content.as_bytes()).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn read_rows_reads_shared_inline_and_sparse_cells() {
+        let data = workbook(
+            r#"<row r="1"><c r="A1" t="s"><v>0</v></c><c r="B1" t="inlineStr"><is><t>pincodes</t></is></c></row>
+               <row r="3"><c r="A3" t="s"><v>1</v></c><c r="C3"><v>560001</v></c></row>
+               <row r="4"/>
+               <row r="5"><c t="s"><v>2</v></c><c><v>4.5</v></c></row>"#,
+        );
+
+        let rows = read_rows(&data).unwrap();
+
+        assert_eq!(
+            rows,
+            vec![
+                (1, vec!["name".to_string(), "pincodes".to_string()]),
+                (3, vec!["Fresh Mart".to_string(), String::new(), "560001".to_string()]),
+                (5, vec![String::new(), "4.5".to_string()]),
+            ]
+        );
+    }
+
+    #[test]
+    fn read_rows_refuses_cells_past_xfd() {
+        let data = workbook(r#"<row r="1"><c r="XFE1"><v>1</v></c></row>"#);
+
+        assert_eq!(read_rows(&data).err().unwrap(), "The XLSX worksheet has more than 16384 columns");
+    }
+
+    #[test]
+    fn read_rows_refuses_missing_shared_strings() {
+        let data = workbook(r#"<row r="1"><c r="A1" t="s"><v>7</v></c></row>"#);
+
+        assert_eq!(read_rows(&data).err().unwrap(), "The XLSX worksheet refers to shared strings that don't exist");
+    }
+
+    #[test]
+    fn read_rows_refuses_parts_larger_than_the_limit() {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        writer.start_file("xl/sharedStrings.xml", FileOptions::default().compression_method(CompressionMethod::Deflated)).unwrap();
+        let chunk = vec![b' '; 1024 * 1024];
+        for _ in 0..=MAX_ENTRY_SIZE / chunk.len() as u64 {
+            writer.write_all(&chunk).unwrap();
+        }
+        let data = writer.finish().unwrap().into_inner();
+
+        assert_eq!(read_rows(&data).err().unwrap(), "xl/sharedStrings.xml in the XLSX file is larger than 64 MB uncompressed");
+    }
+
+    #[test]
+    fn read_rows_refuses_files_that_are_not_workbooks() {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        writer.start_file("readme.txt", FileOptions::default().compression_method(CompressionMethod::Stored)).unwrap();
+        writer.write_all(b"not a workbook").unwrap();
+        let data = writer.finish().unwrap().into_inner();
+
+        assert!(is_xlsx(&data));
+        assert!(read_rows(&data).err().unwrap().starts_with("The file is not an XLSX workbook"));
+        assert!(!is_xlsx(b"name,pincodes\n"));
+    }
+}
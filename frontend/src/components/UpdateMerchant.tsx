@@ -188,7 +188,7 @@ const UpdateMerchant: React.FC<UpdateMerchantProps> = ({ id }) => {
                           <strong>Business Category:</strong> {merchantInfo.business_category}
                         </Typography>
                         <Typography variant="body1">
-                          <strong>Pincodes Serviced:</strong> {merchantInfo.pincodes_serviced}
+                          <strong>Pincodes Serviced:</strong> {merchantInfo.pincodes_serviced?.join(", ")}
                         </Typography>
                       </Grid>
                     </Grid>
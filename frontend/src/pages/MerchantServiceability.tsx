@@ -44,7 +44,7 @@ interface MerchantDetails {
     id: number;
     name: string;
     phone_number: string;
-    pincodes_serviced: string;
+    pincodes_serviced: string[];
   };
 }
 
@@ -222,7 +222,7 @@ const MerchantServiceability = () => {
               </Label>
               <Separator orientation="vertical" />
               <Label className="text-right">
-                {merchantDetails.data.pincodes_serviced}
+                {merchantDetails.data.pincodes_serviced.join(", ")}
               </Label>
             </div>
           </div>